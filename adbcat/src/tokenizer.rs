@@ -1,10 +1,14 @@
 use lazy_static::lazy_static;
 use regex::Regex;
 
+#[rustfmt::skip]
 static KEYWORDS: &[&str] = &[
     "SELECT", "FROM", "WHERE", "INSERT", "INTO", "VALUES", "DELETE", "DROP", "UPDATE", "JOIN",
     "LEFT", "RIGHT", "INNER", "OUTER", "FULL", "SET", "ON", "AND", "OR", "CREATE", "TABLE", "TYPE",
-    "AS", "VARIANT", "true", "false",
+    "AS", "VARIANT", "RECORD", "MATCH", "GROUP", "BY", "COUNT", "SUM", "MIN", "MAX", "AVG", "TAG",
+    "ORDER", "ASC", "DESC", "LIMIT", "OFFSET", "NOT", "CAST", "IN", "EXISTS", "UNION", "ALL",
+    "INTERSECT", "EXCEPT", "WITH", "RECURSIVE", "VIEW", "ALTER", "ADD", "COLUMN", "DEFAULT",
+    "RENAME", "TO", "true", "false",
 ];
 
 lazy_static! {
@@ -6,12 +6,20 @@ pub use span::*;
 
 use crate::types::Value;
 use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display, Formatter};
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
 pub enum Expr<'a> {
     #[serde(borrow)]
     Ident(Spanned<&'a str>),
     Value(Spanned<Value<'a>>),
+    Sum(Option<&'a str>, &'a str, Vec<Spanned<Expr<'a>>>),
+
+    /// Record literal, e.g. `{ x: 1.0, y: 2.0 }`
+    Record(Vec<(Spanned<&'a str>, Spanned<Expr<'a>>)>),
+
+    /// Accessing a field of a record, e.g. `p.x`
+    Field(Box<Spanned<Expr<'a>>>, Spanned<&'a str>),
     Eql(Box<(Spanned<Expr<'a>>, Spanned<Expr<'a>>)>),
     NEq(Box<(Spanned<Expr<'a>>, Spanned<Expr<'a>>)>),
     LEq(Box<(Spanned<Expr<'a>>, Spanned<Expr<'a>>)>),
@@ -20,6 +28,60 @@ pub enum Expr<'a> {
     GEq(Box<(Spanned<Expr<'a>>, Spanned<Expr<'a>>)>),
     And(Box<(Spanned<Expr<'a>>, Spanned<Expr<'a>>)>),
     Or(Box<(Spanned<Expr<'a>>, Spanned<Expr<'a>>)>),
+    Not(Box<Spanned<Expr<'a>>>),
+
+    /// Arithmetic on integers or doubles
+    ///
+    /// Integer arithmetic wraps around on overflow, and dividing an integer by zero is an error.
+    /// Doubles follow IEEE 754, e.g. `1.0 / 0.0` is infinity.
+    Add(Box<(Spanned<Expr<'a>>, Spanned<Expr<'a>>)>),
+    Sub(Box<(Spanned<Expr<'a>>, Spanned<Expr<'a>>)>),
+    Mul(Box<(Spanned<Expr<'a>>, Spanned<Expr<'a>>)>),
+    Div(Box<(Spanned<Expr<'a>>, Spanned<Expr<'a>>)>),
+    Rem(Box<(Spanned<Expr<'a>>, Spanned<Expr<'a>>)>),
+    Neg(Box<Spanned<Expr<'a>>>),
+
+    /// Converting between integers and doubles, e.g. `CAST(price AS Integer)`
+    ///
+    /// Doubles are rounded towards zero, and saturate at the bounds of Integer. NaN becomes 0.
+    Cast(Box<Spanned<Expr<'a>>>, Spanned<&'a str>),
+
+    /// Matching a value against patterns, e.g. `MATCH shape { Circle(r) => r, _ => 0.0 }`
+    ///
+    /// The arm of the first pattern which matches is evaluated.
+    Match(
+        Box<Spanned<Expr<'a>>>,
+        Vec<(Spanned<Pattern<'a>>, Spanned<Expr<'a>>)>,
+    ),
+
+    /// An aggregate function over the rows of a group, e.g. `SUM(price)`
+    ///
+    /// The argument is only left out for `COUNT(*)`.
+    Aggregate(Aggregate, Option<Box<Spanned<Expr<'a>>>>),
+
+    /// The name of the constructor of a sum-type value, e.g. `TAG(status)`
+    Tag(Box<Spanned<Expr<'a>>>),
+
+    /// Whether a value is selected by a subquery, e.g. `id IN (SELECT user_id FROM orders)`
+    In(Box<Spanned<Expr<'a>>>, Box<Select<'a>>),
+
+    /// Whether a subquery selects any rows, e.g. `EXISTS (SELECT 1 FROM orders)`
+    Exists(Box<Select<'a>>),
+
+    /// The value selected by a subquery, e.g. `(SELECT MAX(price) FROM items)`
+    ///
+    /// Unless the subquery always selects a single row, the value is optional and is `None` when
+    /// no rows are selected. Selecting more than one row is an error.
+    Subquery(Box<Select<'a>>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub enum Aggregate {
+    Count,
+    Sum,
+    Min,
+    Max,
+    Avg,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -30,33 +92,111 @@ pub struct Ass<'a> {
     pub expr: Spanned<Expr<'a>>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
 pub struct Select<'a> {
+    /// The common tables which the select may read from, e.g. `WITH t AS (SELECT ...)`
+    #[serde(borrow)]
+    pub with: Option<With<'a>>,
+
     #[serde(borrow)]
     pub items: Vec<Spanned<Expr<'a>>>,
 
+    /// The names given to the selected values, e.g. `SELECT u.id AS uid`, one for every item
+    #[serde(borrow)]
+    pub aliases: Vec<Option<Spanned<&'a str>>>,
+
     #[serde(borrow)]
     pub from: Option<SelectFrom<'a>>,
 
     #[serde(borrow)]
     pub where_clause: Option<WhereClause<'a>>,
+
+    /// The expressions to group the rows by, empty if there is no group-by clause
+    #[serde(borrow)]
+    pub group_by: Vec<Spanned<Expr<'a>>>,
+
+    /// The expressions to sort the rows by, empty if there is no order-by clause
+    ///
+    /// With set operations, these sort the combined rows, and may only refer to their columns.
+    #[serde(borrow)]
+    pub order_by: Vec<(Spanned<Expr<'a>>, Order)>,
+
+    /// The maximum number of rows to select
+    pub limit: Option<usize>,
+
+    /// The number of rows to skip before the selected rows
+    pub offset: Option<usize>,
+
+    /// The selects whose rows are combined with the rows of this one, e.g.
+    /// `SELECT a FROM t UNION SELECT b FROM u`, in the order they are applied in
+    ///
+    /// These selects are never ordered or limited themselves.
+    #[serde(borrow)]
+    pub set_operations: Vec<(SetOperation, Spanned<Select<'a>>)>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+/// A with-clause, which names selects so that they can be read like tables
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+pub struct With<'a> {
+    /// Whether the tables may read from themselves, e.g. `WITH RECURSIVE`
+    pub recursive: bool,
+
+    /// The tables, which may read from the tables before them
+    #[serde(borrow)]
+    pub tables: Vec<CommonTable<'a>>,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+pub struct CommonTable<'a> {
+    pub name: Spanned<&'a str>,
+
+    #[serde(borrow)]
+    pub select: Select<'a>,
+}
+
+/// A way to combine the rows of two selects
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub enum SetOperation {
+    /// The distinct rows of both selects
+    Union,
+
+    /// All rows of both selects, including duplicates
+    UnionAll,
+
+    /// The distinct rows which are selected by both selects
+    Intersect,
+
+    /// The distinct rows of the first select which are not selected by the second one
+    Except,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub enum Order {
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
 pub enum WhereItem<'a> {
     #[serde(borrow)]
     Expr(Spanned<Expr<'a>>),
-    Pattern(Spanned<&'a str>, Spanned<Pattern<'a>>),
+    /// A pattern matched on a column, which may be qualified by the name of its table
+    Pattern(Spanned<Expr<'a>>, Spanned<Pattern<'a>>),
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
 pub enum SelectFrom<'a> {
-    Table(&'a str),
+    Table(Spanned<&'a str>),
     Select(Box<Select<'a>>),
     Join(Box<Join<'a>>),
+
+    /// Naming the rows of a table, select or join, e.g. `users AS u`
+    ///
+    /// The columns can then be qualified by the name, e.g. `u.id`.
+    Alias(Box<SelectFrom<'a>>, &'a str),
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
 pub struct Join<'a> {
     #[serde(borrow)]
     pub table_a: SelectFrom<'a>,
@@ -69,7 +209,7 @@ pub struct Join<'a> {
     pub on_clause: Option<Spanned<Expr<'a>>>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Copy)]
 pub enum JoinType {
     Inner,
     LeftOuter,
@@ -77,7 +217,7 @@ pub enum JoinType {
     FullOuter,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
 pub struct WhereClause<'a> {
     #[serde(borrow)]
     pub items: Vec<WhereItem<'a>>,
@@ -97,6 +237,22 @@ pub struct Drop<'a> {
     //  pub drop_clause: Option<DropClause>, // should be cascade or restrict
 }
 
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CreateView<'a> {
+    pub name: Spanned<&'a str>,
+
+    /// The source text of the select, which is stored with the view
+    pub query: &'a str,
+
+    #[serde(borrow)]
+    pub select: Select<'a>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DropView<'a> {
+    pub view: &'a str,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Insert<'a> {
     pub table: &'a str,
@@ -116,7 +272,73 @@ pub enum InsertFrom<'a> {
 #[derive(Debug, Deserialize, Serialize)]
 pub struct CreateTable<'a> {
     pub table: &'a str,
-    pub columns: Vec<(Spanned<&'a str>, Spanned<&'a str>)>,
+
+    #[serde(borrow)]
+    pub columns: Vec<(Spanned<&'a str>, TypeRef<'a>)>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AlterTable<'a> {
+    pub table: &'a str,
+
+    #[serde(borrow)]
+    pub alteration: Alteration<'a>,
+}
+
+/// A change to the columns of a table
+#[derive(Debug, Deserialize, Serialize)]
+pub enum Alteration<'a> {
+    /// Adding a column after the other columns, e.g. `ADD COLUMN c Integer DEFAULT 0`
+    ///
+    /// The column has the default value in all rows which are already in the table.
+    AddColumn {
+        #[serde(borrow)]
+        column: Spanned<&'a str>,
+
+        #[serde(borrow)]
+        column_type: TypeRef<'a>,
+
+        #[serde(borrow)]
+        default: Spanned<Expr<'a>>,
+    },
+    DropColumn(Spanned<&'a str>),
+
+    /// Renaming a column, e.g. `RENAME COLUMN a TO b`
+    RenameColumn(Spanned<&'a str>, Spanned<&'a str>),
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AlterType<'a> {
+    pub name: Spanned<&'a str>,
+
+    #[serde(borrow)]
+    pub alteration: TypeAlteration<'a>,
+}
+
+/// A change to the variants of a sum-type
+#[derive(Debug, Deserialize, Serialize)]
+pub enum TypeAlteration<'a> {
+    /// Adding a variant after the other variants, e.g. `ADD VARIANT Archived(Integer)`
+    AddVariant {
+        variant: Spanned<&'a str>,
+
+        #[serde(borrow)]
+        members: Vec<TypeRef<'a>>,
+    },
+
+    /// Renaming a variant, e.g. `RENAME VARIANT Active TO Open`
+    RenameVariant(Spanned<&'a str>, Spanned<&'a str>),
+}
+
+/// A reference to a type, e.g. `Integer` or `Maybe<Integer>`
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TypeRef<'a> {
+    #[serde(borrow)]
+    pub name: Spanned<&'a str>,
+
+    /// Type arguments of a generic type
+    #[serde(borrow)]
+    pub args: Vec<TypeRef<'a>>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -127,8 +349,12 @@ pub enum Stmt<'a> {
     Delete(Delete<'a>),
     Update(Update<'a>),
     CreateTable(CreateTable<'a>),
-    CreateType(CreateType<'a>),
+    CreateType(Vec<CreateType<'a>>),
     Drop(Drop<'a>),
+    CreateView(CreateView<'a>),
+    DropView(DropView<'a>),
+    AlterTable(AlterTable<'a>),
+    AlterType(AlterType<'a>),
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -149,11 +375,139 @@ pub enum CreateType<'a> {
         #[serde(borrow)]
         name: Spanned<&'a str>,
 
+        /// Type parameters, if the type is generic
+        #[serde(borrow)]
+        params: Vec<Spanned<&'a str>>,
+
+        #[serde(borrow)]
+        variants: Vec<(Spanned<&'a str>, Vec<TypeRef<'a>>)>,
+    },
+    Record {
+        #[serde(borrow)]
+        name: Spanned<&'a str>,
+
         #[serde(borrow)]
-        variants: Vec<(Spanned<&'a str>, Vec<Spanned<&'a str>>)>,
+        fields: Vec<(Spanned<&'a str>, TypeRef<'a>)>,
     },
 }
 
+impl<'a> Select<'a> {
+    /// The names of the columns which the selected values are stored in
+    ///
+    /// Selected columns and fields keep their names unless they are given new ones, and other
+    /// values have empty names.
+    pub fn column_names(&self) -> Vec<&'a str> {
+        self.items
+            .iter()
+            .zip(&self.aliases)
+            .map(|(item, alias)| match (alias, &item.value) {
+                (Some(alias), _) => alias.value,
+                (None, Expr::Ident(name)) => name.value,
+                (None, Expr::Field(_, field)) => field.value,
+                (None, _) => "",
+            })
+            .collect()
+    }
+}
+
+impl<'a> Expr<'a> {
+    /// Find the aggregate function calls in an expression
+    pub fn aggregates<'e>(&'e self, found: &mut Vec<&'e Expr<'a>>) {
+        match self {
+            Expr::Ident(_) | Expr::Value(_) => {}
+            Expr::Sum(_, _, args) => args.iter().for_each(|arg| arg.aggregates(found)),
+            Expr::Record(fields) => fields.iter().for_each(|(_, e)| e.aggregates(found)),
+            Expr::Field(box record, _)
+            | Expr::Tag(box record)
+            | Expr::Not(box record)
+            | Expr::Neg(box record)
+            | Expr::Cast(box record, _)
+            | Expr::In(box record, _) => record.aggregates(found),
+            Expr::Eql(box (e1, e2))
+            | Expr::NEq(box (e1, e2))
+            | Expr::LEq(box (e1, e2))
+            | Expr::LTh(box (e1, e2))
+            | Expr::GTh(box (e1, e2))
+            | Expr::GEq(box (e1, e2))
+            | Expr::And(box (e1, e2))
+            | Expr::Or(box (e1, e2))
+            | Expr::Add(box (e1, e2))
+            | Expr::Sub(box (e1, e2))
+            | Expr::Mul(box (e1, e2))
+            | Expr::Div(box (e1, e2))
+            | Expr::Rem(box (e1, e2)) => {
+                e1.aggregates(found);
+                e2.aggregates(found);
+            }
+            Expr::Match(box value, arms) => {
+                value.aggregates(found);
+                arms.iter().for_each(|(_, arm)| arm.aggregates(found));
+            }
+            Expr::Aggregate(_, _) => found.push(self),
+
+            // The aggregate functions in subqueries are computed for the rows of the subqueries
+            Expr::Exists(_) | Expr::Subquery(_) => {}
+        }
+    }
+
+    /// Find the subqueries in an expression, not including the ones nested in other subqueries
+    pub fn subqueries<'e>(&'e self, found: &mut Vec<&'e Select<'a>>) {
+        match self {
+            Expr::Ident(_) | Expr::Value(_) | Expr::Aggregate(_, None) => {}
+            Expr::Sum(_, _, args) => args.iter().for_each(|arg| arg.subqueries(found)),
+            Expr::Record(fields) => fields.iter().for_each(|(_, e)| e.subqueries(found)),
+            Expr::Field(box record, _)
+            | Expr::Tag(box record)
+            | Expr::Not(box record)
+            | Expr::Neg(box record)
+            | Expr::Cast(box record, _)
+            | Expr::Aggregate(_, Some(box record)) => record.subqueries(found),
+            Expr::Eql(box (e1, e2))
+            | Expr::NEq(box (e1, e2))
+            | Expr::LEq(box (e1, e2))
+            | Expr::LTh(box (e1, e2))
+            | Expr::GTh(box (e1, e2))
+            | Expr::GEq(box (e1, e2))
+            | Expr::And(box (e1, e2))
+            | Expr::Or(box (e1, e2))
+            | Expr::Add(box (e1, e2))
+            | Expr::Sub(box (e1, e2))
+            | Expr::Mul(box (e1, e2))
+            | Expr::Div(box (e1, e2))
+            | Expr::Rem(box (e1, e2)) => {
+                e1.subqueries(found);
+                e2.subqueries(found);
+            }
+            Expr::Match(box value, arms) => {
+                value.subqueries(found);
+                arms.iter().for_each(|(_, arm)| arm.subqueries(found));
+            }
+            Expr::In(box value, box select) => {
+                value.subqueries(found);
+                found.push(select);
+            }
+            Expr::Exists(box select) | Expr::Subquery(box select) => found.push(select),
+        }
+    }
+}
+
+impl Display for TypeRef<'_> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        if !self.args.is_empty() {
+            write!(f, "<")?;
+            for (i, arg) in self.args.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}", arg)?;
+            }
+            write!(f, ">")?;
+        }
+        Ok(())
+    }
+}
+
 #[test]
 fn ast_grammar() {
     use crate::grammar::StmtParser;
@@ -180,6 +534,7 @@ fn ast_grammar() {
         r#"SELECT col FROM t1 LEFT JOIN t2 ON 3 = 5 LEFT JOIN t3 ON 3 < 4;"#,
         r#"SELECT col FROM t1 LEFT JOIN t2 LEFT JOIN t3;"#,
         r#"UPDATE feffe SET hair_length = short WHERE hej=3 AND true OR false;"#,
+        r#"UPDATE feffe SET hair = Some(length), bald = false WHERE hair: Some(length);"#,
         r#"SELECT c FROM t WHERE a AND b OR c AND d;"#,
         r#"SELECT y FROM t WHERE x: 1;"#,
         r#"SELECT y FROM t WHERE x: Val1(1, InnerVal2(true, _), y);"#,
@@ -189,17 +544,43 @@ fn ast_grammar() {
         r#"CREATE TYPE newCoolType AS VARIANT {};"#,
         r#"CREATE TABLE bananas ();"#,
         r#"CREATE TABLE bananas (col_a Integer, col_b Double);"#,
+        r#"CREATE TABLE bananas (col_a Maybe<Integer>, col_b Either<Maybe<Bool>, Double>);"#,
+        r#"CREATE TYPE Either<A, B> AS VARIANT { Left(A), Right(B) };"#,
+        r#"CREATE TYPE Tree<T> AS VARIANT { Leaf(), Node(Tree<T>, T, Tree<T>) };"#,
+        r#"CREATE TYPE Point AS RECORD { x Double, y Double };"#,
+        r#"CREATE TYPE Empty AS RECORD {};"#,
+        r#"CREATE TYPE Line AS RECORD { from Point, to Point } AND Point AS RECORD { x Double, y Double };"#,
+        r#"SELECT p.x FROM points WHERE p.y > 0.0;"#,
+        r#"SELECT l FROM lines WHERE l.from.x = l.to.x;"#,
+        r#"INSERT INTO points (p) VALUES ({ x: 1.0, y: 2.0 }), ({ y: 0.0, x: 0.0 });"#,
+        r#"UPDATE lines SET l = { from: l.to, to: l.from };"#,
         r#"CREATE TYPE newCoolType AS VARIANT {
             Var1(),
             Var1(Bool),
             Var1(newCoolType, alsoCoolType),
         };"#,
         r#"DROP TABLE bananas ;"#,
+        r#"CREATE VIEW ripe AS SELECT id FROM bananas WHERE ripe;"#,
+        r#"CREATE VIEW ripe AS WITH b AS (SELECT id FROM bananas) SELECT id FROM b;"#,
+        r#"DROP VIEW ripe;"#,
+        r#"ALTER TABLE bananas ADD COLUMN ripe Bool DEFAULT false;"#,
+        r#"ALTER TABLE bananas ADD COLUMN peel Optional<Peel> DEFAULT None();"#,
+        r#"ALTER TABLE bananas DROP COLUMN ripe;"#,
+        r#"ALTER TABLE bananas RENAME COLUMN ripe TO yellow;"#,
+        r#"ALTER TYPE Ripeness ADD VARIANT Rotten();"#,
+        r#"ALTER TYPE Ripeness ADD VARIANT Ripe(Integer, Maybe<Bool>);"#,
+        r#"ALTER TYPE Ripeness RENAME VARIANT Ripe TO Yellow;"#,
         r#"CREATE TYPE newCoolType AS VARIANT {
             Var1(),
             -- Var1(Bool), yeah, this is a comment line whatcha gonna do bout it
             Var1(newCoolType, alsoCoolType),
         };"#,
+        r#"CREATE TYPE Tree AS VARIANT {
+            Node(Integer, Forest),
+        } AND Forest AS VARIANT {
+            Nil(),
+            Cons(Tree, Forest),
+        };"#,
     ];
 
     let invalid_examples = vec![
@@ -209,6 +590,13 @@ fn ast_grammar() {
         r#"INSERT INTO empty;"#,
         r#"SELECT c FROM t1 INNER LEFT JOIN t2;"#,
         r#"SELECT c FROM t1 INNER OUTER JOIN t2;"#,
+        r#"CREATE TYPE A AS VARIANT {} AND;"#,
+        r#"CREATE TABLE bananas (col_a Maybe<>);"#,
+        r#"CREATE TYPE Maybe<> AS VARIANT { Just(T) };"#,
+        r#"CREATE TYPE Point AS RECORD { x: Double, y: Double };"#,
+        r#"CREATE TYPE Point AS RECORD { Double, Double };"#,
+        r#"SELECT p. FROM points;"#,
+        r#"INSERT INTO points (p) VALUES ({ x 1.0 });"#,
         r#"INSERT INTO empty (2) VALUES ();"#,
         r#"INSERT INTO empty () VALUES ,,;"#,
         r#"DELETE just;"#,
@@ -219,6 +607,13 @@ fn ast_grammar() {
         r#"DELETE FROM now, with, commas ;"#,
         r#"UPDATE SET xxsxsxsxsxsxsxs=2 ;"#,
         r#"DROP ;"#,
+        r#"CREATE VIEW ripe;"#,
+        r#"CREATE VIEW ripe AS bananas;"#,
+        r#"ALTER TABLE bananas ADD COLUMN ripe Bool;"#,
+        r#"ALTER TABLE bananas DROP COLUMN ripe, yellow;"#,
+        r#"ALTER TABLE bananas RENAME COLUMN ripe;"#,
+        r#"ALTER TYPE Ripeness ADD VARIANT Rotten;"#,
+        r#"ALTER TYPE Ripeness RENAME VARIANT Ripe;"#,
         r#"INSERT INTO empty 
         -- (a)
         -- VALUES (2)
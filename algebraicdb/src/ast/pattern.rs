@@ -1,7 +1,7 @@
 use crate::ast::Spanned;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub enum Pattern<'a> {
     /// Char literal
     Char(char),
@@ -15,6 +15,9 @@ pub enum Pattern<'a> {
     /// Floating-point literal
     Double(f64),
 
+    /// String literal
+    Str(&'a str),
+
     /// Actual pattern matching
     Variant {
         namespace: Option<Spanned<&'a str>>,
@@ -22,6 +25,13 @@ pub enum Pattern<'a> {
         sub_patterns: Vec<Spanned<Pattern<'a>>>,
     },
 
+    /// Matching the fields of a record, e.g. `{ x: 0.0, y }`
+    ///
+    /// Fields which are left out are not matched against.
+    Record {
+        fields: Vec<(Spanned<&'a str>, Spanned<Pattern<'a>>)>,
+    },
+
     /// _
     Ignore,
 
@@ -29,6 +39,25 @@ pub enum Pattern<'a> {
     Binding(&'a str),
 }
 
+impl<'a> Pattern<'a> {
+    /// Find the names of the variables bound by a pattern
+    pub fn bindings(&self, found: &mut Vec<&'a str>) {
+        match self {
+            Pattern::Binding(name) => found.push(name),
+            Pattern::Variant { sub_patterns, .. } => {
+                sub_patterns.iter().for_each(|p| p.bindings(found))
+            }
+            Pattern::Record { fields } => fields.iter().for_each(|(_, p)| p.bindings(found)),
+            Pattern::Char(_)
+            | Pattern::Int(_)
+            | Pattern::Bool(_)
+            | Pattern::Double(_)
+            | Pattern::Str(_)
+            | Pattern::Ignore => {}
+        }
+    }
+}
+
 #[test]
 fn pattern_grammar() {
     use crate::grammar::PatternParser;
@@ -40,10 +69,14 @@ fn pattern_grammar() {
         r#"123.321"#,
         r#"true"#,
         r#"false"#,
+        r#""hello""#,
         r#"Val1(1, InnerVal2(true, _), y)"#,
+        r#"{ x: 0.0, y }"#,
+        r#"{}"#,
+        r#"Val1({ x: Val2(), y: { z: _ } })"#,
     ];
 
-    let invalid_examples = vec![];
+    let invalid_examples = vec![r#"{ x: }"#, r#"{ 1 }"#];
 
     for ex in valid_examples {
         println!("Trying to parse {}", ex);
@@ -62,6 +62,14 @@ impl<T> AsMut<T> for Spanned<T> {
     }
 }
 
+/// Spans are not part of the value, so two expressions are equal even if they are written in
+/// different places, e.g. in the selected values and in the group-by clause.
+impl<T: PartialEq> PartialEq for Spanned<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value
+    }
+}
+
 impl<T: Display> Display for Spanned<T> {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         self.value.fmt(f)
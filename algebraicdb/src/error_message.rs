@@ -1,4 +1,6 @@
 use crate::ast::Span;
+use crate::executor::dbms::ExecError;
+use crate::pattern_analysis::PatternWarning;
 use crate::typechecker::TypeError;
 use crate::util::str::*;
 use lalrpop_util::ParseError;
@@ -44,9 +46,23 @@ impl ErrorMessage for TypeError {
             TypeError::AlreadyDefined { span, ident } => {
                 fmt_error_message(input, *span, &format!("\"{}\" is defined elsewhere", ident))
             }
+            TypeError::Misplaced { span, item } => {
+                fmt_error_message(input, *span, &format!("{} is not allowed here", item))
+            }
+            TypeError::NotGrouped { span, ident } => fmt_error_message(
+                input,
+                *span,
+                &format!(
+                    "\"{}\" must be grouped by or used in an aggregate function",
+                    ident
+                ),
+            ),
             TypeError::MissingColumn { span, name } => {
                 fmt_error_message(input, *span, &format!("\"{}\" needs to be defined", name))
             }
+            TypeError::MissingField { span, name } => {
+                fmt_error_message(input, *span, &format!("field \"{}\" is missing", name))
+            }
             TypeError::InvalidUnknownType { span, expected } => fmt_error_message(
                 input,
                 *span,
@@ -64,6 +80,16 @@ impl ErrorMessage for TypeError {
                     actual, expected
                 ),
             ),
+            TypeError::NonExhaustive { span, missing } => fmt_error_message(
+                input,
+                *span,
+                &format!("patterns not covered: {}", missing.join(", ")),
+            ),
+            TypeError::MissingInstance(name, _) => fmt_error_message(
+                input,
+                None,
+                &format!("an instance of {} has not been created", name),
+            ),
             TypeError::NotSupported(feature) => {
                 fmt_error_message(input, None, &format!("not supported: {}", feature))
             }
@@ -92,10 +118,52 @@ impl ErrorMessage for TypeError {
     }
 }
 
+impl ErrorMessage for ExecError {
+    fn display(&self, input: &str) -> String {
+        fmt_error_message(input, self.span(), &self.to_string())
+    }
+}
+
+impl ErrorMessage for PatternWarning {
+    fn display(&self, input: &str) -> String {
+        match self {
+            PatternWarning::Redundant { span } => fmt_warning_message(
+                input,
+                *span,
+                "unreachable pattern: already matched by earlier patterns",
+            ),
+            PatternWarning::NeverMatches { span } => fmt_warning_message(
+                input,
+                *span,
+                "pattern never matches together with the earlier patterns",
+            ),
+            PatternWarning::AlwaysMatches { span } => fmt_warning_message(
+                input,
+                *span,
+                "pattern always matches together with the earlier patterns",
+            ),
+            PatternWarning::NonExhaustive { span, missing } => fmt_warning_message(
+                input,
+                *span,
+                &format!("patterns not covered: {}", missing.join(", ")),
+            ),
+        }
+    }
+}
+
 /// Formats a pretty error message
 ///
 /// This function will format a pretty error message, highlighting the offending part of the input.
 pub fn fmt_error_message(input: &str, span: Option<Span>, message: &str) -> String {
+    fmt_message(input, span, "ERROR", message)
+}
+
+/// Formats a pretty warning message, like [fmt_error_message](fn.fmt_error_message.html)
+pub fn fmt_warning_message(input: &str, span: Option<Span>, message: &str) -> String {
+    fmt_message(input, span, "WARNING", message)
+}
+
+fn fmt_message(input: &str, span: Option<Span>, kind: &str, message: &str) -> String {
     let inner = || -> Result<String, fmt::Error> {
         let mut output = String::new();
 
@@ -104,7 +172,7 @@ pub fn fmt_error_message(input: &str, span: Option<Span>, message: &str) -> Stri
             let (_, end_line, _end_byte_offset) = byte_pos_to_line(input, end);
 
             if start_line == end_line {
-                writeln!(&mut output, "    --> ERROR")?;
+                writeln!(&mut output, "    --> {}", kind)?;
 
                 // Write the line containing the offending part of the input
                 writeln!(&mut output, "     |")?;
@@ -128,7 +196,7 @@ pub fn fmt_error_message(input: &str, span: Option<Span>, message: &str) -> Stri
                 (0..msg_offset).for_each(|_| output.push(' '));
                 writeln!(&mut output, "{}", message)?;
             } else {
-                writeln!(&mut output, "    --> ERROR")?;
+                writeln!(&mut output, "    --> {}", kind)?;
                 writeln!(&mut output, "     |")?;
 
                 for (i, line) in input
@@ -144,7 +212,7 @@ pub fn fmt_error_message(input: &str, span: Option<Span>, message: &str) -> Stri
                 writeln!(&mut output, "     * {}", message)?;
             }
         } else {
-            writeln!(&mut output, "    --> ERROR")?;
+            writeln!(&mut output, "    --> {}", kind)?;
             writeln!(&mut output, "     |")?;
 
             for (i, line) in input.lines().enumerate() {
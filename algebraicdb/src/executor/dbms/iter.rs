@@ -1,19 +1,31 @@
-use crate::ast::{Expr, Pattern, Spanned, WhereItem};
+use super::{execute_expr_in, Env};
+use crate::ast::{Expr, Pattern, Select, Spanned, WhereItem};
 use crate::table::{Cell, Schema, Table};
-use crate::types::{EnumTag, Type, TypeId, TypeMap};
-use bincode::serialize;
+use crate::types::{compare_doubles, EnumTag, HeapRef, Type, TypeId, TypeMap, Value};
+use bincode::{deserialize, serialize};
 use std::cmp::Ordering;
+use std::mem::size_of;
 use std::sync::Arc;
 
-pub enum ModIter<'a> {
-    Select(&'a [Spanned<Expr<'a>>]),
-    Where(&'a [WhereItem<'a>]),
+pub(super) enum ModIter<'a> {
+    Select(&'a Select<'a>),
+    Where(&'a [WhereItem<'a>], &'a Env<'a>),
+
+    /// Qualify the names of the columns by the name of their table
+    Qualify(&'a str),
+
+    /// Skip a number of rows, and limit the number of rows after them
+    Limit(usize, Option<usize>),
 }
 
-pub enum Rows<'a> {
+pub(super) enum Rows<'a> {
     Scan(RowIter<'a>),
     Materialized {
-        table: Table,
+        /// The rows, which may be shared with other rows, e.g. if they are the rows of a view
+        table: Arc<Table>,
+
+        /// The names of the tables which qualify the columns, if any
+        qualifiers: Vec<Option<String>>,
         mods: Vec<ModIter<'a>>,
     },
 }
@@ -26,7 +38,14 @@ impl<'a> From<RowIter<'a>> for Rows<'a> {
 
 impl From<Table> for Rows<'static> {
     fn from(table: Table) -> Self {
+        Rows::from(Arc::new(table))
+    }
+}
+
+impl From<Arc<Table>> for Rows<'static> {
+    fn from(table: Arc<Table>) -> Self {
         Rows::Materialized {
+            qualifiers: vec![None; table.schema().len()],
             table,
             mods: vec![],
         }
@@ -34,32 +53,55 @@ impl From<Table> for Rows<'static> {
 }
 
 impl<'a> Rows<'a> {
-    pub fn schema(&self) -> Schema {
+    /// The columns of the rows, without the names of the tables which qualify them
+    pub fn schema(&self, type_map: &TypeMap) -> Schema {
         // TODO: refactor this into something more efficient
-        match self {
-            Rows::Scan(iter) => Schema::new(
-                iter.bindings
-                    .iter()
-                    .map(|cr| (cr.name.to_owned(), cr.type_id))
-                    .collect(),
-            ),
-            Rows::Materialized { table, .. } => table.schema().clone(),
-        }
+        Schema::new(
+            self.iter(type_map)
+                .bindings
+                .iter()
+                .map(|cr| (cr.name.to_owned(), cr.type_id))
+                .collect(),
+        )
+    }
+
+    /// The names of the tables which qualify the columns of the rows, if any
+    pub fn qualifiers(&self, type_map: &TypeMap) -> Vec<Option<String>> {
+        self.iter(type_map)
+            .bindings
+            .iter()
+            .map(|cr| cr.table.map(str::to_owned))
+            .collect()
     }
 
     pub fn iter<'b>(&'b self, type_map: &'b TypeMap) -> RowIter<'b> {
         match self {
             Rows::Scan(iter) => iter.clone(),
-            Rows::Materialized { table, mods } => {
+            Rows::Materialized {
+                table,
+                qualifiers,
+                mods,
+            } => {
                 use super::full_table_scan;
                 let mut scan = full_table_scan(&table, type_map);
+                for (binding, qualifier) in
+                    Arc::make_mut(&mut scan.bindings).iter_mut().zip(qualifiers)
+                {
+                    binding.table = qualifier.as_deref();
+                }
                 for m in mods {
                     match m {
-                        ModIter::Select(selects) => {
-                            scan.select(&selects);
+                        ModIter::Select(select) => {
+                            scan.select(select);
+                        }
+                        ModIter::Where(clauses, env) => {
+                            scan.apply_pattern(&clauses, env);
                         }
-                        ModIter::Where(clauses) => {
-                            scan.apply_pattern(&clauses, type_map);
+                        &ModIter::Limit(offset, limit) => {
+                            scan.limit(offset, limit);
+                        }
+                        ModIter::Qualify(table) => {
+                            scan.qualify(table);
                         }
                     }
                 }
@@ -68,19 +110,54 @@ impl<'a> Rows<'a> {
         }
     }
 
-    pub fn select(&mut self, items: &'a [Spanned<Expr>]) {
+    pub fn select(&mut self, select: &'a Select<'a>) {
+        match self {
+            Rows::Scan(iter) => iter.select(select),
+            Rows::Materialized { mods, .. } => mods.push(ModIter::Select(select)),
+        }
+    }
+
+    pub fn qualify(&mut self, table: &'a str) {
+        match self {
+            Rows::Scan(iter) => iter.qualify(table),
+            Rows::Materialized { mods, .. } => mods.push(ModIter::Qualify(table)),
+        }
+    }
+
+    pub(super) fn apply_pattern(&mut self, patterns: &'a [WhereItem<'a>], env: &'a Env<'a>) {
         match self {
-            Rows::Scan(iter) => iter.select(items),
-            Rows::Materialized { mods, .. } => mods.push(ModIter::Select(items)),
+            Rows::Scan(iter) => iter.apply_pattern(patterns, env),
+            Rows::Materialized { mods, .. } => mods.push(ModIter::Where(patterns, env)),
         }
     }
 
-    pub fn apply_pattern(&mut self, patterns: &'a [WhereItem], type_map: &TypeMap) {
+    pub fn limit(&mut self, offset: usize, limit: Option<usize>) {
         match self {
-            Rows::Scan(iter) => iter.apply_pattern(patterns, type_map),
-            Rows::Materialized { mods, .. } => mods.push(ModIter::Where(patterns)),
+            Rows::Scan(iter) => iter.limit(offset, limit),
+            Rows::Materialized { mods, .. } => mods.push(ModIter::Limit(offset, limit)),
         }
     }
+
+    /// Copy the rows into a new table, unless they are a whole table which isn't shared already
+    pub fn into_table(self, type_map: &TypeMap) -> Table {
+        let rows = match self {
+            Rows::Materialized { table, mods, .. } if mods.is_empty() => {
+                match Arc::try_unwrap(table) {
+                    Ok(table) => return table,
+                    Err(table) => Rows::from(table),
+                }
+            }
+            rows => rows,
+        };
+
+        let mut table = Table::new(rows.schema(type_map), type_map);
+        for row in rows.iter(type_map) {
+            for (_, _, cell) in row {
+                cell.copy_into(&mut table.data, &mut table.heap);
+            }
+        }
+        table
+    }
 }
 
 #[derive(Clone)]
@@ -92,10 +169,19 @@ pub struct RowIter<'a> {
     /// Filter rows
     pub matches: Arc<Vec<CellFilter<'a>>>,
 
+    /// Filter rows using boolean expressions
+    pub conditions: Arc<Vec<ExprFilter<'a>>>,
+
     pub type_map: &'a TypeMap,
 
     /// The current row
     pub row: Option<usize>,
+
+    /// The number of matching rows to skip before returning any rows
+    pub skip: usize,
+
+    /// The number of rows left to return, if the rows are limited
+    pub remaining: Option<usize>,
 }
 
 #[derive(Clone)]
@@ -106,40 +192,85 @@ pub struct CellIter<'a> {
     pub cell: usize,
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone)]
 pub struct CellRef<'a> {
     /// Source of the data (e.g. a slice of an entire table)
     pub source: &'a [u8],
 
+    /// The heap which the source refers to
+    pub heap: &'a [u8],
+
     /// The variable name bound to this cell
     pub name: &'a str,
 
+    /// The name of the table the cell is from, if its name can be qualified by it
+    pub table: Option<&'a str>,
+
     /// The data type of this cell
     pub type_id: TypeId,
 
     /// The size of a row in the source
     pub row_size: usize,
 
-    /// The byte count offset from the start of the row, e.g. index of the start of a column.
-    pub offset: usize,
+    /// The location of the cell in a row
+    pub location: CellLocation,
 
     /// The size of the cell in bytes
     pub size: usize,
 }
 
+#[derive(Clone)]
+pub struct CellLocation {
+    /// The byte count offset from the start of the row, e.g. index of the start of a column.
+    pub offset: usize,
+
+    /// The offsets into the heap data, one for each heap reference to follow.
+    ///
+    /// Used for cells within values of recursive types, which are stored in the heap.
+    pub indirections: Vec<usize>,
+}
+
 #[derive(Clone)]
 pub struct CellFilter<'a> {
     /// Source of the data (e.g. a slice of an entire table)
     source: &'a [u8],
 
+    /// The heap which the source refers to
+    heap: &'a [u8],
+
     /// The size of a row in the source
     row_size: usize,
 
-    /// The byte count offset from the start of the row, e.g. index of the start of a column.
-    offset: usize,
+    /// The location of the cell in a row
+    location: CellLocation,
 
     /// The value to equal the and:ed row
-    value: Arc<[u8]>,
+    value: FilterValue,
+}
+
+#[derive(Clone)]
+enum FilterValue {
+    /// The bytes of the cell must equal these bytes
+    Bytes(Arc<[u8]>),
+
+    /// The cell must be a reference to this string
+    Str(Arc<str>),
+
+    /// The cell must be a double which is equal to this one, which it may be without having the
+    /// same bytes, e.g. -0.0 and 0.0
+    Double(f64),
+}
+
+#[derive(Clone)]
+pub struct ExprFilter<'a> {
+    /// The expression which must evaluate to true for a row to match
+    expr: &'a Expr<'a>,
+
+    /// The cells which are in scope for the expression
+    bindings: Arc<Vec<CellRef<'a>>>,
+
+    /// The env which the expression is evaluated in
+    env: &'a Env<'a>,
 }
 
 struct JoinIter {
@@ -155,6 +286,11 @@ impl<'a> Iterator for RowIter<'a> {
     type Item = CellIter<'a>;
 
     fn next(&mut self) -> Option<Self::Item> {
+        // Stop scanning once the limit has been reached
+        if self.remaining == Some(0) {
+            self.row = None;
+        }
+
         if let Some(row) = self.row.as_mut() {
             'rows: loop {
                 for source in self.bindings.iter() {
@@ -165,6 +301,12 @@ impl<'a> Iterator for RowIter<'a> {
                     }
                 }
 
+                // Without any cells there is a single row
+                if self.bindings.is_empty() && *row > 0 {
+                    self.row = None;
+                    return None;
+                }
+
                 // Check that all rows matches the filters
                 for m in self.matches.iter() {
                     if m.check(*row) != Ordering::Equal {
@@ -173,6 +315,22 @@ impl<'a> Iterator for RowIter<'a> {
                     }
                 }
 
+                for c in self.conditions.iter() {
+                    if !c.check(*row) {
+                        *row += 1;
+                        continue 'rows;
+                    }
+                }
+
+                if self.skip > 0 {
+                    self.skip -= 1;
+                    *row += 1;
+                    continue 'rows;
+                }
+                if let Some(remaining) = self.remaining.as_mut() {
+                    *remaining -= 1;
+                }
+
                 let cr = CellIter {
                     bindings: self.bindings.clone(),
                     type_map: self.type_map,
@@ -191,17 +349,20 @@ impl<'a> Iterator for RowIter<'a> {
 }
 
 impl<'a> Iterator for CellIter<'a> {
-    type Item = (&'a str, Cell<'a, 'a>);
+    type Item = (Option<&'a str>, &'a str, Cell<'a, 'a>);
 
     fn next(&mut self) -> Option<Self::Item> {
         if let Some(cell) = self.bindings.get(self.cell) {
-            let start = self.row * cell.row_size + cell.offset;
-            let end = start + cell.size;
-            let data = &cell.source[start..end];
+            let row_start = self.row * cell.row_size;
+            let data = &cell.location.resolve(cell.source, cell.heap, row_start)[..cell.size];
 
             self.cell += 1;
 
-            Some((cell.name, Cell::new(cell.type_id, data, self.type_map)))
+            Some((
+                cell.table,
+                cell.name,
+                Cell::new(cell.type_id, data, cell.heap, self.type_map),
+            ))
         } else {
             None
         }
@@ -209,33 +370,126 @@ impl<'a> Iterator for CellIter<'a> {
 }
 
 impl<'a> RowIter<'a> {
-    pub fn select(&mut self, items: &'a [Spanned<Expr>]) {
-        let mut bindings = vec![];
-        'outer: for item in items {
-            match item.as_ref() {
-                &Expr::Ident(name) => {
-                    for &binding in self.bindings.iter() {
-                        if &binding.name == name.as_ref() {
-                            bindings.push(binding);
-                            continue 'outer;
+    /// Skip the first `offset` matching rows, and stop after `limit` rows after them
+    pub fn limit(&mut self, offset: usize, limit: Option<usize>) {
+        // The rows which are already skipped or limited come first
+        let remaining = self
+            .remaining
+            .map(|remaining| remaining.saturating_sub(offset));
+        self.skip = self.skip.saturating_add(offset);
+        self.remaining = match (remaining, limit) {
+            (Some(remaining), Some(limit)) => Some(remaining.min(limit)),
+            (remaining, limit) => remaining.or(limit),
+        };
+    }
+
+    /// Select the cells referred to by the selected values, named by the names of the columns
+    pub fn select(&mut self, select: &'a Select<'a>) {
+        let bindings = select
+            .items
+            .iter()
+            .zip(select.column_names())
+            .map(|(item, name)| {
+                let binding = match item.as_ref() {
+                    Expr::Ident(name) => self
+                        .find_binding(item)
+                        .unwrap_or_else(|| panic!("No matching bindings: \"{}\"", name)),
+                    Expr::Field(_, _) => self
+                        .find_binding(item)
+                        .unwrap_or_else(|| panic!("No matching bindings for field")),
+                    // Other expressions are evaluated by projecting the rows into a new table
+                    expr => unreachable!("Selecting a computed value from a scan: {:?}", expr),
+                };
+                CellRef {
+                    name,
+                    table: None,
+                    ..binding
+                }
+            })
+            .collect();
+
+        self.bindings = Arc::new(bindings);
+    }
+
+    /// Qualify the names of all cells by the name of a table, replacing any previous qualifiers
+    pub fn qualify(&mut self, table: &'a str) {
+        for binding in Arc::make_mut(&mut self.bindings) {
+            binding.table = Some(table);
+        }
+    }
+
+    /// Find the cell which an expression refers to, i.e. a binding or a field of a binding
+    fn find_binding(&self, expr: &'a Expr<'a>) -> Option<CellRef<'a>> {
+        match expr {
+            Expr::Ident(name) => self
+                .bindings
+                .iter()
+                .find(|binding| &binding.name == name.as_ref())
+                .cloned(),
+            // A field of an undefined identifier is a column qualified by the name of its table
+            Expr::Field(
+                box Spanned {
+                    value: Expr::Ident(table),
+                    ..
+                },
+                field,
+            ) if self
+                .bindings
+                .iter()
+                .all(|binding| binding.name != table.value) =>
+            {
+                self.bindings
+                    .iter()
+                    .find(|binding| {
+                        binding.table == Some(table.value) && binding.name == field.value
+                    })
+                    .cloned()
+            }
+            Expr::Field(box record, field) => {
+                let record = self.find_binding(record)?;
+                let fields = match &self.type_map[&record.type_id] {
+                    Type::Product(fields) => fields,
+                    _ => return None,
+                };
+
+                // Fields are stored one after another, in the order they were declared in
+                let mut location = record.location.clone();
+                for (name, type_id) in fields {
+                    if name == field.as_ref() {
+                        // Members of recursive types are stored in the heap
+                        if self.type_map.is_boxed(*type_id) {
+                            location = location.deref();
                         }
+
+                        return Some(CellRef {
+                            name: field.value,
+                            type_id: *type_id,
+                            location,
+                            size: self.type_map[type_id].size_of(self.type_map),
+                            ..record
+                        });
                     }
-                    panic!("No matching bindings: \"{}\"", name);
+                    location.advance(self.type_map.member_size_of(*type_id));
                 }
-                _expr => unimplemented!("Selecting non-ident expressions"),
+                None
             }
+            _ => None,
         }
-
-        self.bindings = bindings.into();
     }
 
-    pub fn apply_pattern(&mut self, patterns: &'a [WhereItem], type_map: &TypeMap) {
+    /// Filter the rows by the items of a where-clause
+    ///
+    /// The expressions are evaluated in `env`, so they may refer to the enclosing selects.
+    pub(super) fn apply_pattern(&mut self, patterns: &'a [WhereItem<'a>], env: &'a Env<'a>) {
+        let type_map = env.types;
+
         fn build_pattern<'a>(
             pattern: &'a Pattern,
-            mut byte_index: usize,
+            mut location: CellLocation,
             type_map: &TypeMap,
             type_id: TypeId,
             data: &'a [u8],
+            heap: &'a [u8],
             row_size: usize,
             bindings: &mut Arc<Vec<CellRef<'a>>>,
             matches: &mut Arc<Vec<CellFilter<'a>>>,
@@ -244,33 +498,46 @@ impl<'a> RowIter<'a> {
                 Pattern::Char(v) => {
                     Arc::make_mut(matches).push(CellFilter {
                         source: data,
+                        heap,
                         row_size,
-                        offset: byte_index,
-                        value: serialize(v).unwrap().into(),
+                        location: location.clone(),
+                        value: FilterValue::Bytes(serialize(v).unwrap().into()),
                     });
                 }
                 Pattern::Int(v) => {
                     Arc::make_mut(matches).push(CellFilter {
                         source: data,
+                        heap,
                         row_size,
-                        offset: byte_index,
-                        value: serialize(v).unwrap().into(),
+                        location: location.clone(),
+                        value: FilterValue::Bytes(serialize(v).unwrap().into()),
                     });
                 }
                 Pattern::Bool(v) => {
                     Arc::make_mut(matches).push(CellFilter {
                         source: data,
+                        heap,
                         row_size,
-                        offset: byte_index,
-                        value: serialize(v).unwrap().into(),
+                        location: location.clone(),
+                        value: FilterValue::Bytes(serialize(v).unwrap().into()),
                     });
                 }
                 Pattern::Double(v) => {
                     Arc::make_mut(matches).push(CellFilter {
                         source: data,
+                        heap,
                         row_size,
-                        offset: byte_index,
-                        value: serialize(v).unwrap().into(),
+                        location: location.clone(),
+                        value: FilterValue::Double(*v),
+                    });
+                }
+                Pattern::Str(v) => {
+                    Arc::make_mut(matches).push(CellFilter {
+                        source: data,
+                        heap,
+                        row_size,
+                        location: location.clone(),
+                        value: FilterValue::Str((*v).into()),
                     });
                 }
                 Pattern::Ignore => {}
@@ -278,9 +545,11 @@ impl<'a> RowIter<'a> {
                     let t = type_map.get_by_id(type_id);
                     Arc::make_mut(bindings).push(CellRef {
                         source: data,
+                        heap,
                         name: ident,
+                        table: None,
                         type_id,
-                        offset: byte_index,
+                        location: location.clone(),
                         size: t.size_of(type_map),
                         row_size,
                     });
@@ -299,46 +568,100 @@ impl<'a> RowIter<'a> {
 
                         Arc::make_mut(matches).push(CellFilter {
                             source: data,
+                            heap,
                             row_size,
-                            offset: byte_index,
-                            value: serialize(&i).unwrap().into(),
+                            location: location.clone(),
+                            value: FilterValue::Bytes(serialize(&i).unwrap().into()),
                         });
 
-                        byte_index += std::mem::size_of::<EnumTag>();
+                        location.advance(size_of::<EnumTag>());
                         for (type_id, pattern) in sub_types.iter().zip(sub_patterns.iter()) {
-                            let t = &type_map[type_id];
+                            // Members of recursive types are stored in the heap
+                            let member_location = if type_map.is_boxed(*type_id) {
+                                location.deref()
+                            } else {
+                                location.clone()
+                            };
                             build_pattern(
-                                pattern, byte_index, type_map, *type_id, data, row_size, bindings,
+                                pattern,
+                                member_location,
+                                type_map,
+                                *type_id,
+                                data,
+                                heap,
+                                row_size,
+                                bindings,
                                 matches,
                             );
-                            byte_index += t.size_of(type_map);
+                            location.advance(type_map.member_size_of(*type_id));
                         }
                     } else {
                         panic!("not a sum-type")
                     }
                 }
+                Pattern::Record { fields } => {
+                    if let Type::Product(field_types) = &type_map[&type_id] {
+                        for (name, pattern) in fields {
+                            let mut field_location = location.clone();
+                            let mut field_type = None;
+                            for (field, type_id) in field_types {
+                                if field == name.as_ref() {
+                                    field_type = Some(*type_id);
+                                    break;
+                                }
+                                field_location.advance(type_map.member_size_of(*type_id));
+                            }
+                            let type_id = field_type.expect("Field does not exist");
+
+                            // Members of recursive types are stored in the heap
+                            if type_map.is_boxed(type_id) {
+                                field_location = field_location.deref();
+                            }
+
+                            build_pattern(
+                                pattern,
+                                field_location,
+                                type_map,
+                                type_id,
+                                data,
+                                heap,
+                                row_size,
+                                bindings,
+                                matches,
+                            );
+                        }
+                    } else {
+                        panic!("not a product-type")
+                    }
+                }
             }
         }
 
+        // Apply all patterns before the expressions,
+        // since the expressions may use variables bound by the patterns.
         for select_item in patterns {
             match select_item {
-                WhereItem::Expr(_) => {} // Ignore expressions for now
-                WhereItem::Pattern(name, pattern) => {
+                WhereItem::Expr(_) => {}
+                WhereItem::Pattern(column, pattern) => {
+                    let column = super::column_ref(&column.value, |_| false)
+                        .unwrap_or_else(|| unreachable!("Pattern on a non-column"));
                     let mut i = 0;
                     while let Some(cell_ref) = self.bindings.get(i) {
                         i += 1;
-                        if &cell_ref.name == name.as_ref() {
-                            let byte_index = cell_ref.offset;
+                        if super::column_matches(column, cell_ref.table, cell_ref.name) {
+                            let location = cell_ref.location.clone();
                             let type_id = cell_ref.type_id;
                             let data = cell_ref.source;
+                            let heap = cell_ref.heap;
                             let row_size = cell_ref.row_size;
 
                             build_pattern(
                                 pattern,
-                                byte_index,
+                                location,
                                 type_map,
                                 type_id,
                                 data,
+                                heap,
                                 row_size,
                                 &mut self.bindings,
                                 &mut self.matches,
@@ -348,6 +671,66 @@ impl<'a> RowIter<'a> {
                 }
             }
         }
+
+        for select_item in patterns {
+            if let WhereItem::Expr(expr) = select_item {
+                Arc::make_mut(&mut self.conditions).push(ExprFilter {
+                    expr,
+                    bindings: self.bindings.clone(),
+                    env,
+                });
+            }
+        }
+    }
+}
+
+impl ExprFilter<'_> {
+    pub fn check(&self, row: usize) -> bool {
+        let cells = CellIter {
+            bindings: self.bindings.clone(),
+            type_map: self.env.types,
+            row,
+            cell: 0,
+        };
+
+        match execute_expr_in(self.expr, cells, self.env) {
+            Value::Bool(b) => b,
+            v => unreachable!("Non-boolean expression in where-clause: {:?}", v),
+        }
+    }
+}
+
+impl CellLocation {
+    pub fn new(offset: usize) -> Self {
+        CellLocation {
+            offset,
+            indirections: vec![],
+        }
+    }
+
+    /// Move the location a number of bytes forward
+    fn advance(&mut self, bytes: usize) {
+        match self.indirections.last_mut() {
+            Some(offset) => *offset += bytes,
+            None => self.offset += bytes,
+        }
+    }
+
+    /// Get the location of the heap data referred to at this location
+    fn deref(&self) -> Self {
+        let mut location = self.clone();
+        location.indirections.push(0);
+        location
+    }
+
+    /// Get the data starting at this location in a row
+    fn resolve<'a>(&self, source: &'a [u8], heap: &'a [u8], row_start: usize) -> &'a [u8] {
+        let mut data = &source[row_start + self.offset..];
+        for offset in &self.indirections {
+            let (heap_offset, _): HeapRef = deserialize(&data[..size_of::<HeapRef>()]).unwrap();
+            data = &heap[heap_offset + offset..];
+        }
+        data
     }
 }
 
@@ -355,22 +738,36 @@ impl CellFilter<'_> {
     pub fn check(&self, row: usize) -> Ordering {
         let CellFilter {
             source,
+            heap,
             row_size,
-            offset,
+            location,
             value,
         } = self;
 
-        let start = row * row_size + offset;
-        let end = start + value.len();
+        let data = location.resolve(source, heap, row * row_size);
 
-        let row = &source[start..end];
+        match value {
+            FilterValue::Bytes(value) => {
+                let row = &data[..value.len()];
 
-        for (row_b, value_b) in row.iter().zip(value.iter()) {
-            let cmp = row_b.cmp(value_b);
-            if cmp != Ordering::Equal {
-                return cmp;
+                for (row_b, value_b) in row.iter().zip(value.iter()) {
+                    let cmp = row_b.cmp(value_b);
+                    if cmp != Ordering::Equal {
+                        return cmp;
+                    }
+                }
+                Ordering::Equal
+            }
+            FilterValue::Str(value) => {
+                let (heap_offset, len): HeapRef =
+                    deserialize(&data[..size_of::<HeapRef>()]).unwrap();
+                let s = &heap[heap_offset..heap_offset + len];
+                s.cmp(value.as_bytes())
+            }
+            FilterValue::Double(value) => {
+                let d: f64 = deserialize(&data[..size_of::<f64>()]).unwrap();
+                compare_doubles(d, *value)
             }
         }
-        Ordering::Equal
     }
 }
@@ -3,21 +3,31 @@ mod iter;
 use self::iter::*;
 use crate::ast::*;
 use crate::error_message::ErrorMessage;
-use crate::grammar::StmtParser;
+use crate::grammar::{SelectParser, StmtParser};
+use crate::pattern_analysis::PatternWarning;
 use crate::persistence::WriteToWal;
 use crate::pre_typechecker;
-use crate::state::{DbState, DbmsState, ResourcesGuard};
-use crate::table::{Cell, Schema, Table};
-use crate::typechecker;
-use crate::types::{Type, TypeId, TypeMap, Value};
+use crate::state::{DbState, DbmsState, DropError, ResourcesGuard, RW};
+use crate::table::{Cell, Schema, Table, View};
+use crate::typechecker::{self, Grouping, TypeError};
+use crate::types::{
+    compare_doubles, BaseType, Template, TemplateType, Type, TypeId, TypeMap, Value, OPTIONAL_NONE,
+    OPTIONAL_NONE_TAG, OPTIONAL_SOME, OPTIONAL_SOME_TAG,
+};
+use bincode::serialize_into;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
 use std::error::Error;
-use std::fmt::Write;
+use std::fmt::{self, Display, Formatter, Write};
 use std::iter::empty;
-use std::sync::Arc;
+use std::mem::replace;
+use std::sync::{Arc, Mutex};
 use tokio::io::{AsyncWrite, AsyncWriteExt};
 
 lazy_static! {
     static ref PARSER: StmtParser = StmtParser::new();
+    static ref SELECT_PARSER: SelectParser = SelectParser::new();
 }
 
 pub(crate) async fn execute_query(
@@ -36,31 +46,67 @@ pub(crate) async fn execute_query(
     };
 
     // 2. determine resources
-    let request = pre_typechecker::get_resource_request(&ast);
+    let mut request = pre_typechecker::get_resource_request(&ast);
 
-    // 3. acquire resources
-    let response = s.acquire_resources(request).await;
-    let mut resources = match response {
-        Ok(resources) => resources,
-        Err(name) => {
-            return Ok(w
-                .write_all(format!("no such table: \"{}\"\n", name).as_bytes())
-                .await?)
-        }
-    };
-    let resources = resources.take().await;
+    loop {
+        // 3. acquire resources
+        let response = s.acquire_resources(request).await;
+        let mut resources = match response {
+            Ok(resources) => resources,
+            Err(name) => {
+                return Ok(w
+                    .write_all(format!("no such table: \"{}\"\n", name).as_bytes())
+                    .await?)
+            }
+        };
+        let mut resources = resources.take().await;
 
-    // 4. typecheck
-    match typechecker::check_stmt(&ast, &resources) {
-        Ok(()) => {}
-        Err(e) => {
-            w.write_all(e.display(input).as_bytes()).await?;
-            return Ok(());
+        // 4. typecheck
+        match typecheck(&ast, &mut resources) {
+            Ok(warnings) => {
+                for warning in warnings {
+                    w.write_all(warning.display(input).as_bytes()).await?;
+                }
+            }
+            Err(TypeError::MissingInstance(..)) => {
+                // The type map has to be locked for writing to create the type
+                request = pre_typechecker::get_resource_request(&ast);
+                request.type_map_perms = RW::Write;
+                continue;
+            }
+            Err(e) => {
+                w.write_all(e.display(input).as_bytes()).await?;
+                return Ok(());
+            }
         }
+
+        // 5. Execute query
+        let error = match execute_stmt(ast, s, resources, WriteToWal::Yes, w).await {
+            Ok(()) => return Ok(()),
+            Err(e) => e.downcast::<ExecError>()?,
+        };
+        w.write_all(error.display(input).as_bytes()).await?;
+        return Ok(());
     }
+}
 
-    // 5. Execute query
-    execute_stmt(ast, s, resources, WriteToWal::Yes, w).await
+/// Typecheck a statement, creating the instances of generic types which it needs
+///
+/// Instances, e.g. optional types, are created the first time they are needed, e.g. by an outer
+/// join, which requires the type map to be locked for writing. Otherwise `MissingInstance` is
+/// returned.
+fn typecheck(
+    ast: &Stmt,
+    resources: &mut ResourcesGuard<'_, Table>,
+) -> Result<Vec<PatternWarning>, TypeError> {
+    loop {
+        match typechecker::check_stmt(ast, resources) {
+            Err(TypeError::MissingInstance(name, args)) if resources.type_map.is_write() => {
+                resources.type_map.instantiate(&name, &args);
+            }
+            result => return result,
+        }
+    }
 }
 
 pub(crate) async fn execute_replay_query<'a>(
@@ -69,23 +115,36 @@ pub(crate) async fn execute_replay_query<'a>(
     w: &mut (dyn AsyncWrite + Send + Unpin),
 ) -> Result<(), Box<dyn Error>> {
     // 2. determine resources
-    let request = pre_typechecker::get_resource_request(&ast);
+    let mut request = pre_typechecker::get_resource_request(&ast);
+
+    loop {
+        // 3. acquire resources
+        let response = s.acquire_resources(request).await;
+        let mut resources = match response {
+            Ok(resources) => resources,
+            Err(name) => {
+                return Ok(w
+                    .write_all(format!("no such table: \"{}\"\n", name).as_bytes())
+                    .await?)
+            }
+        };
+        let mut resources = resources.take().await;
 
-    // 3. acquire resources
-    let response = s.acquire_resources(request).await;
-    let mut resources = match response {
-        Ok(resources) => resources,
-        Err(name) => {
-            return Ok(w
-                .write_all(format!("no such table: \"{}\"\n", name).as_bytes())
-                .await?)
+        // 4. typecheck, which creates the instances of generic types the statement needs
+        if let Err(TypeError::MissingInstance(..)) = typecheck(&ast, &mut resources) {
+            request = pre_typechecker::get_resource_request(&ast);
+            request.type_map_perms = RW::Write;
+            continue;
         }
-    };
-    let resources = resources.take().await;
 
-    // 5. Execute query
-    // TODO: Error checking
-    execute_stmt(ast, s, resources, WriteToWal::No, w).await
+        // 5. Execute query
+        // Statements which failed did so before changing anything, so they are skipped
+        // TODO: Error checking
+        return match execute_stmt(ast, s, resources, WriteToWal::No, w).await {
+            Err(e) if e.is::<ExecError>() => Ok(()),
+            result => result,
+        };
+    }
 }
 
 async fn execute_stmt(
@@ -102,6 +161,10 @@ async fn execute_stmt(
             | Stmt::Delete(_)
             | Stmt::Update(_)
             | Stmt::Drop(_)
+            | Stmt::CreateView(_)
+            | Stmt::DropView(_)
+            | Stmt::AlterTable(_)
+            | Stmt::AlterType(_)
             | Stmt::Insert(_) => wal.write(&ast).await?,
             Stmt::Select(_) => { /* We're only reading, so no logging required*/ }
         }
@@ -114,12 +177,20 @@ async fn execute_stmt(
         Stmt::Insert(insert) => execute_insert(insert, resources, w).await,
         Stmt::Select(select) => {
             let type_map = &resources.type_map;
-            let table = execute_select(&select, &resources);
-            print_table(table.iter(type_map), w).await
+            let env = Env::new(&resources);
+
+            // The rows are selected before any of them are printed, in case the select fails
+            let table = execute_select(&select, None, &env).into_table(type_map);
+            env.check()?;
+            print_table(full_table_scan(&table, type_map), w).await
         }
         Stmt::Drop(drop) => execute_drop_table(drop, s, w).await,
-        ast @ Stmt::Delete(_) => unimplemented!("Not implemented: {:?}", ast),
-        ast @ Stmt::Update(_) => unimplemented!("Not implemented: {:?}", ast),
+        Stmt::Update(update) => execute_update(update, resources, w).await,
+        Stmt::Delete(delete) => execute_delete(delete, resources, w).await,
+        Stmt::CreateView(create_view) => execute_create_view(create_view, s, resources, w).await,
+        Stmt::DropView(drop) => execute_drop_view(drop, s, w).await,
+        Stmt::AlterTable(alter_table) => execute_alter_table(alter_table, resources, w).await,
+        Stmt::AlterType(alter_type) => execute_alter_type(alter_type, resources, w).await,
     }
 }
 
@@ -135,7 +206,7 @@ async fn print_table(
         w.write_all(b"[").await?;
         let mut first = true;
 
-        for (_, cell) in row {
+        for (_, _, cell) in row {
             if !first {
                 w.write_all(b", ").await?;
             }
@@ -162,9 +233,11 @@ fn full_table_scan<'a>(table: &'a Table, type_map: &'a TypeMap) -> RowIter<'a> {
             let size = t.size_of(type_map);
             let cr = CellRef {
                 source: &table.data,
+                heap: &table.heap,
                 name,
+                table: None,
                 type_id: *type_id,
-                offset,
+                location: CellLocation::new(offset),
                 size,
                 row_size: table.row_size,
             };
@@ -178,82 +251,578 @@ fn full_table_scan<'a>(table: &'a Table, type_map: &'a TypeMap) -> RowIter<'a> {
     RowIter {
         bindings: Arc::new(bindings),
         matches: Arc::new(vec![]),
+        conditions: Arc::new(vec![]),
         type_map,
         row: Some(0),
+        skip: 0,
+        remaining: None,
     }
 }
 
-pub fn execute_select_from<'a>(
-    from: &'a SelectFrom<'a>,
-    resources: &'a ResourcesGuard<'a, Table>,
-) -> Rows<'a> {
-    let type_map = &resources.type_map;
+/// A scan over a single row without any cells, which is what is selected from without a
+/// from-clause
+fn single_row_scan(type_map: &TypeMap) -> RowIter<'_> {
+    RowIter {
+        bindings: Arc::new(vec![]),
+        matches: Arc::new(vec![]),
+        conditions: Arc::new(vec![]),
+        type_map,
+        row: Some(0),
+        skip: 0,
+        remaining: None,
+    }
+}
+
+fn execute_select_from<'a>(from: &'a SelectFrom<'a>, env: &'a Env<'a>) -> Rows<'a> {
+    let type_map = env.types;
     match from {
         SelectFrom::Table(table_name) => {
-            let table = resources.read_table(&table_name);
-            full_table_scan(table, type_map).into()
+            // Common tables shadow the tables and views of the database
+            let common_table = env
+                .tables
+                .iter()
+                .rev()
+                .find(|(name, _)| name == &table_name.value);
+            let mut rows: Rows = match common_table {
+                Some((_, table)) => full_table_scan(table, type_map).into(),
+                None => match env.read_view(table_name.value) {
+                    Some(view) => view.into(),
+                    None => full_table_scan(env.resources.read_table(&table_name), type_map).into(),
+                },
+            };
+            rows.qualify(table_name.value);
+            rows
+        }
+        SelectFrom::Select(select) => execute_select(select, None, env).into(),
+        SelectFrom::Alias(from, alias) => {
+            let mut rows = execute_select_from(from, env);
+            rows.qualify(alias);
+            rows
         }
-        SelectFrom::Select(select) => execute_select(select, resources).into(),
         SelectFrom::Join(join) => {
-            match join.join_type {
-                JoinType::Inner => { /* This is the only one supported for now...*/ }
-                JoinType::LeftOuter => unimplemented!("Left Outer Join"),
-                JoinType::RightOuter => unimplemented!("Right Outer Join"),
-                JoinType::FullOuter => unimplemented!("Full Outer Join"),
-            }
+            // Columns from a table which may be missing a matching row are made optional
+            let (optional_a, optional_b) = match join.join_type {
+                JoinType::Inner => (false, false),
+                JoinType::LeftOuter => (false, true),
+                JoinType::RightOuter => (true, false),
+                JoinType::FullOuter => (true, true),
+            };
 
-            let table_a = execute_select_from(&join.table_a, resources);
-            let table_b = execute_select_from(&join.table_b, resources);
+            let table_a = execute_select_from(&join.table_a, env);
+            let table_b = execute_select_from(&join.table_b, env);
 
-            let mut table_out = Table::new(table_a.schema().union(&table_b.schema()), type_map);
+            let schema_a = table_a.schema(type_map);
+            let schema_b = table_b.schema(type_map);
+            let mut qualifiers = table_a.qualifiers(type_map);
+            qualifiers.extend(table_b.qualifiers(type_map));
+            let wrap = |schema: &Schema, optional: bool| {
+                if optional {
+                    Schema::new(
+                        schema
+                            .columns
+                            .iter()
+                            .map(|(name, t)| (name.clone(), optional_of(*t, type_map)))
+                            .collect(),
+                    )
+                } else {
+                    schema.clone()
+                }
+            };
+
+            let mut table_out = Table::new(
+                wrap(&schema_a, optional_a).union(&wrap(&schema_b, optional_b)),
+                type_map,
+            );
+
+            let on_exprs = match &join.on_clause {
+                Some(on_clause) => conjuncts(&on_clause.value),
+                None => vec![],
+            };
+
+            let rows_b: Vec<CellIter> = table_b.iter(type_map).collect();
 
-            let default_expr = Expr::Value(Spanned::from(Value::Bool(true)));
-            let on_expr = join
-                .on_clause
-                .as_ref()
-                .map(|e| &e.value)
-                .unwrap_or(&default_expr);
+            // Equalities between columns of the two tables are used as keys for a hash join.
+            // The rest of the on-clause is checked for every pair of rows with matching keys.
+            let columns_a = columns_of(&table_a.iter(type_map));
+            let columns_b = columns_of(&table_b.iter(type_map));
+            let (keys, conditions) = join_keys(on_exprs, &columns_a, &columns_b, type_map);
+            let (keys_a, keys_b): (Vec<ColumnRef>, Vec<ColumnRef>) = keys.into_iter().unzip();
 
             let mut row_buf: Vec<u8> = vec![];
+            let mut key_buf: Vec<u8> = vec![];
+
+            // If there are no keys, we fall back on comparing every pair of rows
+            let all_rows_b: Vec<usize> = (0..rows_b.len()).collect();
+            let mut index: HashMap<Vec<u8>, Vec<usize>> = HashMap::new();
+            if !keys_b.is_empty() {
+                for (i, row_b) in rows_b.iter().enumerate() {
+                    write_key(row_b, &keys_b, &mut key_buf);
+                    index.entry(key_buf.clone()).or_default().push(i);
+                    key_buf.clear();
+                }
+            }
 
-            // The join algorithm is currently a basic n² loop.
-            // We probably want to implement a more efficient one.
+            // Keep track of which rows in table_b have been matched, for right and full joins
+            let mut matched_b = vec![false; rows_b.len()];
 
-            let table_b = table_b.iter(type_map);
             for row_a in table_a.iter(type_map) {
-                for row_b in table_b.clone() {
-                    let bindings = row_a.clone().chain(row_b.clone());
-                    let matches = match execute_expr(on_expr, bindings) {
-                        Value::Bool(b) => b,
-                        v => panic!("Tried joining on something other than a bool: {:?}", v),
-                    };
+                let mut matched_a = false;
 
-                    if matches {
-                        let row_a = row_a.clone();
+                let candidates = if keys_a.is_empty() {
+                    &all_rows_b[..]
+                } else {
+                    write_key(&row_a, &keys_a, &mut key_buf);
+                    let candidates = index.get(&key_buf).map(|c| &c[..]).unwrap_or(&[]);
+                    key_buf.clear();
+                    candidates
+                };
 
-                        for (_, cell) in row_a.chain(row_b) {
-                            row_buf.extend_from_slice(cell.data);
+                for &i in candidates {
+                    let row_b = &rows_b[i];
+                    let matches = conditions.iter().all(|condition| {
+                        let bindings = row_a.clone().chain(row_b.clone());
+                        match execute_expr_in(condition, bindings, env) {
+                            Value::Bool(b) => b,
+                            v => panic!("Tried joining on something other than a bool: {:?}", v),
                         }
+                    });
+
+                    if matches {
+                        matched_a = true;
+                        matched_b[i] = true;
+
+                        write_cells(
+                            row_a.clone(),
+                            optional_a,
+                            &mut row_buf,
+                            &mut table_out.heap,
+                            type_map,
+                        );
+                        write_cells(
+                            row_b.clone(),
+                            optional_b,
+                            &mut row_buf,
+                            &mut table_out.heap,
+                            type_map,
+                        );
+                        table_out.push_row_bytes(&row_buf);
+                        row_buf.clear();
+                    }
+                }
+
+                if !matched_a && optional_b {
+                    write_cells(
+                        row_a,
+                        optional_a,
+                        &mut row_buf,
+                        &mut table_out.heap,
+                        type_map,
+                    );
+                    write_nones(&schema_b, &mut row_buf, type_map);
+                    table_out.push_row_bytes(&row_buf);
+                    row_buf.clear();
+                }
+            }
+
+            if optional_a {
+                for (row_b, matched) in rows_b.into_iter().zip(matched_b) {
+                    if !matched {
+                        write_nones(&schema_a, &mut row_buf, type_map);
+                        write_cells(
+                            row_b,
+                            optional_b,
+                            &mut row_buf,
+                            &mut table_out.heap,
+                            type_map,
+                        );
                         table_out.push_row_bytes(&row_buf);
                         row_buf.clear();
                     }
                 }
             }
 
-            Rows::from(table_out)
+            // The joined columns keep the names of the tables they came from
+            Rows::Materialized {
+                table: Arc::new(table_out),
+                qualifiers,
+                mods: vec![],
+            }
+        }
+    }
+}
+
+/// Split an expression into the terms of a conjunction
+fn conjuncts<'e, 'a>(expr: &'e Expr<'a>) -> Vec<&'e Expr<'a>> {
+    match expr {
+        Expr::And(box (e1, e2)) => {
+            let mut exprs = conjuncts(&e1.value);
+            exprs.extend(conjuncts(&e2.value));
+            exprs
+        }
+        expr => vec![expr],
+    }
+}
+
+/// The columns of rows, as the names of their tables, their names and their types
+fn columns_of<'a>(rows: &RowIter<'a>) -> Vec<(Option<&'a str>, &'a str, TypeId)> {
+    rows.bindings
+        .iter()
+        .map(|binding| (binding.table, binding.name, binding.type_id))
+        .collect()
+}
+
+/// Find the equalities in an on-clause which can be used as keys for a hash join
+///
+/// Returns the key columns in table a and b, and the expressions which are not keys.
+fn join_keys<'e, 'a>(
+    exprs: Vec<&'e Expr<'a>>,
+    columns_a: &[(Option<&str>, &str, TypeId)],
+    columns_b: &[(Option<&str>, &str, TypeId)],
+    type_map: &TypeMap,
+) -> (Vec<(ColumnRef<'a>, ColumnRef<'a>)>, Vec<&'e Expr<'a>>) {
+    let mut keys = vec![];
+    let mut conditions = vec![];
+
+    let columns = || columns_a.iter().chain(columns_b);
+    let is_column = |ident: &str| columns().any(|&(_, name, _)| name == ident);
+
+    // The side of the join which a column is from, and its type
+    let side = |expr: &Expr<'a>| {
+        let column = column_ref(expr, is_column)?;
+        let find = |columns: &[(Option<&str>, &str, TypeId)]| {
+            let mut found = columns
+                .iter()
+                .filter(|&&(table, name, _)| column_matches(column, table, name));
+            match (found.next(), found.next()) {
+                (Some(&(_, _, type_id)), None) => Some(type_id),
+                _ => None,
+            }
+        };
+        match (find(columns_a), find(columns_b)) {
+            (Some(t), None) => Some((column, true, t)),
+            (None, Some(t)) => Some((column, false, t)),
+            _ => None,
+        }
+    };
+
+    for expr in exprs {
+        let key = match expr {
+            Expr::Eql(box (e1, e2)) => match (side(e1), side(e2)) {
+                (Some((c1, true, t)), Some((c2, false, _))) if is_byte_comparable(t, type_map) => {
+                    Some((c1, c2))
+                }
+                (Some((c1, false, t)), Some((c2, true, _))) if is_byte_comparable(t, type_map) => {
+                    Some((c2, c1))
+                }
+                _ => None,
+            },
+            _ => None,
+        };
+
+        match key {
+            Some(key) => keys.push(key),
+            None => conditions.push(expr),
+        }
+    }
+
+    (keys, conditions)
+}
+
+/// Check whether values of a type are equal exactly when their canonical bytes are equal
+///
+/// This is not the case for doubles, e.g. 0.0 == -0.0 and NaN != NaN.
+fn is_byte_comparable(type_id: TypeId, type_map: &TypeMap) -> bool {
+    fn check(type_id: TypeId, type_map: &TypeMap, visited: &mut Vec<TypeId>) -> bool {
+        // Recursive types may refer to themselves
+        if visited.contains(&type_id) {
+            return true;
         }
+        visited.push(type_id);
+
+        match &type_map[&type_id] {
+            Type::Integer | Type::Bool | Type::Char | Type::String => true,
+            Type::Double => false,
+            Type::Sum(variants) => variants
+                .iter()
+                .flat_map(|(_, sub_types)| sub_types)
+                .all(|t| check(*t, type_map, visited)),
+            Type::Product(fields) => fields.iter().all(|(_, t)| check(*t, type_map, visited)),
+        }
+    }
+
+    check(type_id, type_map, &mut vec![])
+}
+
+/// Write the canonical bytes of the key columns in a row to a buffer
+fn write_key(row: &CellIter, keys: &[ColumnRef], buf: &mut Vec<u8>) {
+    for &key in keys {
+        find_cell(key, row.clone()).write_canonical(buf);
+    }
+}
+
+/// Write the data of all cells in a row to a buffer, optionally wrapping them in `Some`
+fn write_cells(
+    row: CellIter,
+    optional: bool,
+    buf: &mut Vec<u8>,
+    heap: &mut Vec<u8>,
+    type_map: &TypeMap,
+) {
+    for (_, _, cell) in row {
+        if optional {
+            write_some(&cell, buf, heap, type_map);
+        } else {
+            cell.copy_into(buf, heap);
+        }
+    }
+}
+
+/// Write the data of a cell to a buffer as a value of its optional type
+fn write_some(cell: &Cell, buf: &mut Vec<u8>, heap: &mut Vec<u8>, type_map: &TypeMap) {
+    serialize_into(&mut *buf, &OPTIONAL_SOME_TAG).unwrap();
+    if type_map.is_boxed(cell.type_id()) {
+        cell.copy_boxed_into(buf, heap);
+    } else {
+        cell.copy_into(buf, heap);
+    }
+}
+
+/// Get the optional type of a type, which has been created when the statement was typechecked
+fn optional_of(type_id: TypeId, type_map: &TypeMap) -> TypeId {
+    type_map
+        .optional_of(type_id)
+        .expect("Optional types are created when statements are typechecked")
+}
+
+/// Write `None` to a buffer for each column in the schema
+fn write_nones(schema: &Schema, buf: &mut Vec<u8>, type_map: &TypeMap) {
+    for (_, type_id) in &schema.columns {
+        let optional_type = &type_map[&optional_of(*type_id, type_map)];
+        let start = buf.len();
+        serialize_into(&mut *buf, &OPTIONAL_NONE_TAG).unwrap();
+
+        // pad with 0:s since None is smaller than Some
+        buf.resize(start + optional_type.size_of(type_map), 0);
     }
 }
 
+/// Execute a select
+///
+/// `types` are the types of the selected values, if they are known from where the select is
+/// used. Otherwise they are inferred from the selected values.
 fn execute_select<'a>(
     select: &'a Select<'a>,
-    resources: &'a ResourcesGuard<'a, Table>,
+    types: Option<&[TypeId]>,
+    env: &'a Env<'a>,
+) -> Rows<'a> {
+    let with = match &select.with {
+        Some(with) => with,
+        None => return execute_combined_select(select, types, env),
+    };
+
+    // The common tables only exist while the select is executed, so the rows are copied
+    let env = with_common_tables(with, select, env);
+    let rows = execute_combined_select(select, types, &env);
+    rows.into_table(env.types).into()
+}
+
+/// Execute a select and the selects which are combined with it, ignoring its with-clause
+fn execute_combined_select<'a>(
+    select: &'a Select<'a>,
+    types: Option<&[TypeId]>,
+    env: &'a Env<'a>,
+) -> Rows<'a> {
+    if !select.set_operations.is_empty() {
+        return combine(select, types, env);
+    }
+    select_rows(select, types, RowLimits::of(select), env)
+}
+
+/// Execute the common tables of a with-clause, and add them to an env
+///
+/// `reader` is the select which the with-clause belongs to.
+fn with_common_tables<'a>(with: &'a With<'a>, reader: &Select, env: &Env<'a>) -> Env<'a> {
+    let mut env = env.clone();
+    for table in &with.tables {
+        let rows = if with.recursive {
+            let needed = rows_needed(reader, table.name.value);
+            execute_recursive(&table.select, table.name, needed, &env)
+        } else {
+            execute_select(&table.select, None, &env).into_table(env.types)
+        };
+        env.tables.push((table.name.value, Arc::new(rows)));
+    }
+    env
+}
+
+/// The number of rows of a common table which a select needs, if it only reads the first rows
+/// of the table, e.g. `SELECT i FROM t LIMIT 3`
+fn rows_needed(select: &Select, table: &str) -> Option<usize> {
+    let from_table = match &select.from {
+        Some(SelectFrom::Table(name)) => name.value == table,
+        Some(SelectFrom::Alias(box SelectFrom::Table(name), _)) => name.value == table,
+        _ => false,
+    };
+    let only_scans = select.where_clause.is_none()
+        && select.order_by.is_empty()
+        && select.set_operations.is_empty()
+        && !typechecker::is_grouped(select);
+    if !from_table || !only_scans {
+        return None;
+    }
+    let limit = select.limit?;
+    Some(select.offset.unwrap_or(0).saturating_add(limit))
+}
+
+/// Execute the select of a view, which is read like a common table
+fn execute_view(view: &View, env: &Env) -> Table {
+    let select = SELECT_PARSER
+        .parse(&view.query)
+        .expect("Failed to parse the select of a view");
+    let types: Vec<TypeId> = view.schema.columns.iter().map(|(_, t)| *t).collect();
+    execute_select(&select, Some(&types), env).into_table(env.types)
+}
+
+/// Execute the select of a recursive common table
+///
+/// The table starts with the rows of the first select. Then the other selects are executed
+/// with the table holding only the rows which were added last, and their rows are added, until
+/// no more rows are added. Rows which are already in the table aren't added by a UNION.
+///
+/// `needed` is the number of rows which the select reading the table needs, if it is limited.
+/// Since the table may never stop growing, adding more than `MAX_RECURSIVE_ROWS` rows fails.
+fn execute_recursive<'a>(
+    select: &'a Select<'a>,
+    name: Spanned<&'a str>,
+    needed: Option<usize>,
+    env: &Env<'a>,
+) -> Table {
+    // Like in the typechecker, the select can only read the table if it only has unions
+    let unions_only = select
+        .set_operations
+        .iter()
+        .all(|(operation, _)| matches!(operation, SetOperation::Union | SetOperation::UnionAll));
+    if select.set_operations.is_empty() || !unions_only {
+        return execute_select(select, None, env).into_table(env.types);
+    }
+
+    let mut env = match &select.with {
+        Some(with) => with_common_tables(with, select, env),
+        None => env.clone(),
+    };
+    let type_map = env.types;
+
+    let first = select_rows(select, None, RowLimits::unlimited(), &env).into_table(type_map);
+    let column_types: Vec<TypeId> = first.schema.columns.iter().map(|(_, t)| *t).collect();
+    let names = select.column_names().into_iter().map(str::to_owned);
+    let schema = Schema::new(names.zip(column_types.iter().copied()).collect());
+    let by_bytes = column_types
+        .iter()
+        .all(|&type_id| is_byte_comparable(type_id, type_map));
+
+    // Rows are added by a UNION if they haven't been seen before, and always by a UNION ALL
+    let mut seen = HashSet::new();
+    let mut add = |operation: SetOperation, rows: RowIter, table: &mut Table| {
+        for row in rows {
+            let is_new = seen.insert(row_key(row.clone(), by_bytes));
+            if is_new || operation == SetOperation::UnionAll {
+                copy_row(row, table);
+            }
+        }
+    };
+
+    let mut added = Table::new(schema.clone(), type_map);
+    let (first_operation, _) = select.set_operations[0];
+    add(
+        first_operation,
+        full_table_scan(&first, type_map),
+        &mut added,
+    );
+
+    // Without an order-by clause, no more rows are needed once the limit of the table, or of
+    // the select reading it, is reached
+    let limits = RowLimits::of(select);
+    let needed = if limits.order_by.is_empty() {
+        limits
+            .limit
+            .into_iter()
+            .chain(needed)
+            .min()
+            .map(|needed| limits.offset.saturating_add(needed))
+    } else {
+        None
+    };
+
+    let mut table = Table::new(schema.clone(), type_map);
+    while added.row_count() > 0 {
+        table.push_table(&added, type_map);
+        if needed
+            .map(|needed| table.row_count() >= needed)
+            .unwrap_or(false)
+        {
+            break;
+        }
+        if table.row_count() > MAX_RECURSIVE_ROWS {
+            env.fail(ExecError::RecursionLimit {
+                span: name.span,
+                table: name.value.to_string(),
+            });
+        }
+        if env.check().is_err() {
+            break;
+        }
+
+        env.tables.push((name.value, Arc::new(added)));
+        added = Table::new(schema.clone(), type_map);
+        for (operation, operand) in &select.set_operations {
+            let rows = execute_select(operand, Some(&column_types), &env);
+            add(*operation, rows.iter(type_map), &mut added);
+        }
+        env.tables.pop();
+    }
+
+    order_combined(table, limits, &env).into_table(type_map)
+}
+
+/// How the rows of a select are sorted and limited
+#[derive(Clone, Copy)]
+struct RowLimits<'a> {
+    order_by: &'a [(Spanned<Expr<'a>>, Order)],
+    offset: usize,
+    limit: Option<usize>,
+}
+
+impl<'a> RowLimits<'a> {
+    fn of(select: &'a Select<'a>) -> Self {
+        RowLimits {
+            order_by: &select.order_by,
+            offset: select.offset.unwrap_or(0),
+            limit: select.limit,
+        }
+    }
+
+    /// All rows, in the order they are selected in
+    fn unlimited() -> Self {
+        RowLimits {
+            order_by: &[],
+            offset: 0,
+            limit: None,
+        }
+    }
+}
+
+/// Select the rows of a select, ignoring its set operations
+fn select_rows<'a>(
+    select: &'a Select<'a>,
+    types: Option<&[TypeId]>,
+    limits: RowLimits<'a>,
+    env: &'a Env<'a>,
 ) -> Rows<'a> {
-    let type_map = &resources.type_map;
+    let type_map = env.types;
 
     let rows = match &select.from {
-        Some(from) => execute_select_from(from, resources),
-        None => unimplemented!("Selecting from nothing"),
+        Some(from) => execute_select_from(from, env),
+        None => single_row_scan(type_map).into(),
     };
 
     let mut scan = rows;
@@ -263,181 +832,1885 @@ fn execute_select<'a>(
         .as_ref()
         .map(|wc| &wc.items[..])
         .unwrap_or(&[]);
-    scan.apply_pattern(where_items, type_map);
+    scan.apply_pattern(where_items, env);
 
-    scan.select(&select.items);
+    // Selected columns and fields are read directly from the cells they are stored in,
+    // but any other expressions have to be evaluated for every row, or for every group.
+    // Sorted rows are also evaluated, since they have to be reordered.
+    if typechecker::is_grouped(select) {
+        return aggregate(select, &scan, types, limits, env).into();
+    }
+
+    // Without an order-by clause, the scan can stop as soon as there are enough rows
+    if limits.order_by.is_empty() {
+        scan.limit(limits.offset, limits.limit);
+    }
 
-    scan
+    if limits.order_by.is_empty() && select.items.iter().all(|item| is_cell_ref(item)) {
+        scan.select(select);
+        scan
+    } else {
+        project(select, &scan, types, limits, env).into()
+    }
 }
 
-async fn execute_create_table(
-    create_table: CreateTable<'_>,
-    s: &DbmsState,
-    resources: ResourcesGuard<'_, Table>,
-    w: &mut (dyn AsyncWrite + Send + Unpin),
-) -> Result<(), Box<dyn Error>> {
-    let columns: Vec<_> = create_table
-        .columns
+/// Combine the rows of a select with the rows of the selects of its set operations, and then
+/// sort and limit the combined rows
+///
+/// Rows are compared by their canonical bytes, which are the same for equal values of a type,
+/// unless the values of a column can be equal with different bytes, like doubles.
+fn combine<'a>(select: &'a Select<'a>, types: Option<&[TypeId]>, env: &'a Env<'a>) -> Rows<'a> {
+    let type_map = env.types;
+
+    let first = select_rows(select, types, RowLimits::unlimited(), env);
+    let column_types: Vec<TypeId> = columns_of(&first.iter(type_map))
         .into_iter()
-        .map(|(column_name, column_type)| {
-            let t_id = resources
-                .type_map
-                .get_id(&column_type)
-                .expect("Type does not exist");
-            (column_name.to_string(), t_id)
-        })
+        .map(|(_, _, type_id)| type_id)
         .collect();
+    let names = select.column_names().into_iter().map(str::to_owned);
+    let schema = Schema::new(names.zip(column_types.iter().copied()).collect());
+    let by_bytes = column_types
+        .iter()
+        .all(|&type_id| is_byte_comparable(type_id, type_map));
 
-    let schema = Schema::new(columns);
-    let table = Table::new(schema, &resources.type_map);
+    let mut table = Table::new(schema.clone(), type_map);
+    for row in first.iter(type_map) {
+        copy_row(row, &mut table);
+    }
 
-    match s.create_table(create_table.table.to_string(), table).await {
-        Ok(()) => {
-            w.write_all(format!("table created: \"{}\"\n", create_table.table).as_bytes())
-                .await?
-        }
-        Err(()) => {
-            w.write_all(format!("table already exists: \"{}\"\n", create_table.table).as_bytes())
-                .await?
+    for (operation, operand) in &select.set_operations {
+        let operand = execute_select(operand, Some(&column_types), env);
+        let rows = operand.iter(type_map);
+
+        let mut combined = Table::new(schema.clone(), type_map);
+        let mut seen = HashSet::new();
+        match operation {
+            SetOperation::UnionAll => {
+                combined = table;
+                for row in rows {
+                    copy_row(row, &mut combined);
+                }
+            }
+            SetOperation::Union => {
+                let combined_rows = full_table_scan(&table, type_map).chain(rows);
+                push_distinct(combined_rows, &mut combined, &mut seen, by_bytes, |_| true);
+            }
+            SetOperation::Intersect | SetOperation::Except => {
+                let found: HashSet<Vec<u8>> = rows.map(|row| row_key(row, by_bytes)).collect();
+                let keep_found = *operation == SetOperation::Intersect;
+                let keep = |key: &[u8]| found.contains(key) == keep_found;
+                push_distinct(
+                    full_table_scan(&table, type_map),
+                    &mut combined,
+                    &mut seen,
+                    by_bytes,
+                    keep,
+                );
+            }
         }
-    };
-    Ok(())
+        table = combined;
+    }
+
+    order_combined(table, RowLimits::of(select), env)
 }
 
-async fn execute_create_type(
-    create_type: CreateType<'_>,
-    mut resources: ResourcesGuard<'_, Table>,
-    w: &mut (dyn AsyncWrite + Send + Unpin),
-) -> Result<(), Box<dyn Error>> {
-    let types = &mut resources.type_map;
+/// Sort and limit the combined rows of selects
+///
+/// The order-by clause may only refer to the columns of the combined rows.
+fn order_combined<'a>(table: Table, limits: RowLimits<'a>, env: &Env<'a>) -> Rows<'a> {
+    let type_map = env.types;
+    let schema = table.schema.clone();
+    let mut rows: Rows = table.into();
+    if limits.order_by.is_empty() {
+        rows.limit(limits.offset, limits.limit);
+        return rows;
+    }
 
-    match create_type {
-        CreateType::Variant { name, variants } => {
-            let variant_types: Vec<_> = variants
-                .into_iter()
-                .map(|(constructor, subtypes)| {
-                    let subtype_ids: Vec<TypeId> = subtypes
-                        .iter()
-                        .map(|type_name| types.get_id(type_name).unwrap())
-                        .collect();
+    let iter = rows.iter(type_map);
+    let columns = columns_of(&iter);
+    let order = RowOrder::new(limits.order_by, &columns, Grouping::None, env);
+    let mut sorted = SortedRows::new(&order, limits.offset, limits.limit);
+    for row in iter {
+        let keys = limits
+            .order_by
+            .iter()
+            .map(|(key, _)| execute_expr_in(key, row.clone(), env))
+            .collect();
+        let values = row.map(|(_, _, cell)| read_cell(cell)).collect();
+        sorted.push(keys, values);
+    }
 
-                    (constructor.to_string(), subtype_ids)
-                })
-                .collect();
+    let mut table = Table::new(schema, type_map);
+    sorted.write_to(&mut table, type_map);
+    table.into()
+}
 
-            w.write_all(b"type ").await?;
-            w.write_all(name.as_bytes()).await?;
-            w.write_all(b" created\n").await?;
-            types.insert(name.value, Type::Sum(variant_types));
+/// Copy the rows to a table, skipping the rows which have been seen and the rows whose keys
+/// are not kept
+fn push_distinct<'a, I>(
+    rows: I,
+    table: &mut Table,
+    seen: &mut HashSet<Vec<u8>>,
+    by_bytes: bool,
+    keep: impl Fn(&[u8]) -> bool,
+) where
+    I: Iterator<Item = CellIter<'a>>,
+{
+    for row in rows {
+        let key = row_key(row.clone(), by_bytes);
+        if keep(&key) && seen.insert(key) {
+            copy_row(row, table);
         }
     }
-    Ok(())
 }
 
-async fn execute_drop_table(
-    drop: Drop<'_>,
-    s: &DbmsState,
-    w: &mut (dyn AsyncWrite + Send + Unpin),
-) -> Result<(), Box<dyn Error>> {
-    match s.drop_table(drop.table).await {
-        Ok(()) => {
-            w.write_all(format!("table dropped: \"{}\"\n", drop.table).as_bytes())
-                .await?
-        }
-        Err(()) => {
-            w.write_all(format!("no such table: \"{}\"\n", drop.table).as_bytes())
-                .await?
+/// Write the cells of a row to the end of a table with the same columns
+fn copy_row(row: CellIter, table: &mut Table) {
+    for (_, _, cell) in row {
+        cell.copy_into(&mut table.data, &mut table.heap);
+    }
+}
+
+/// A key of the cells of a row, which is equal for rows with equal values
+///
+/// The key is the canonical bytes of the cells if `by_bytes`, which is faster than reading
+/// their values but only works for byte-comparable types.
+fn row_key(row: CellIter, by_bytes: bool) -> Vec<u8> {
+    let mut key = vec![];
+    for (_, _, cell) in row {
+        if by_bytes {
+            cell.write_canonical(&mut key);
+        } else {
+            read_cell(cell).write_key(&mut key);
         }
     }
-    Ok(())
+    key
 }
 
-async fn execute_insert(
-    insert: Insert<'_>,
-    mut resources: ResourcesGuard<'_, Table>,
-    w: &mut (dyn AsyncWrite + Send + Unpin),
-) -> Result<(), Box<dyn Error>> {
-    match insert.from {
-        // case !query
-        InsertFrom::Values(rows) => {
-            let (table, type_map) = resources.write_table(&insert.table);
-            let row_count = rows.len();
-            let mut values = vec![];
-            for row in rows.into_iter() {
-                row.iter()
-                    .map(|e| execute_expr(e, empty()))
-                    .for_each(|v| values.push(v));
-                table.push_row(&values, &type_map);
-                values.clear();
-            }
+/// Check whether an expression refers to a cell, i.e. a binding or a field of a binding
+fn is_cell_ref(expr: &Expr) -> bool {
+    match expr {
+        Expr::Ident(_) => true,
+        Expr::Field(box record, _) => is_cell_ref(record),
+        _ => false,
+    }
+}
 
-            w.write_all(format!("{} row(s) inserted\n", row_count).as_bytes())
-                .await?;
-        }
+/// Evaluate the selected values for every row, and collect the results in a new table
+fn project<'a>(
+    select: &'a Select<'a>,
+    rows: &Rows<'a>,
+    types: Option<&[TypeId]>,
+    limits: RowLimits<'a>,
+    env: &Env<'a>,
+) -> Table {
+    let type_map = env.types;
+    let iter = rows.iter(type_map);
 
-        //case query
-        InsertFrom::Select(select) => {
-            let mut data = vec![];
-            let mut row_count = 0;
-            for row in execute_select(&select, &resources).iter(&resources.type_map) {
-                row_count += 1;
-                for (_, cell) in row {
-                    data.extend_from_slice(&cell.data);
-                }
-            }
+    let columns = columns_of(&iter);
+    let types = env
+        .type_of_exprs(&select.items, types, &columns, Grouping::None)
+        .unwrap_or_else(|e| panic!("Failed to get the types of selected values: {:?}", e));
+    let names = select.column_names().into_iter().map(str::to_owned);
 
-            let (table, _) = resources.write_table(&insert.table);
-            table.data.extend_from_slice(&data);
+    let mut table = Table::new(Schema::new(names.zip(types).collect()), type_map);
+    // Unordered rows are limited by the scan instead
+    let (offset, limit) = if limits.order_by.is_empty() {
+        (0, None)
+    } else {
+        (limits.offset, limits.limit)
+    };
+    let order = RowOrder::new(limits.order_by, &columns, Grouping::None, env);
+    let mut sorted = SortedRows::new(&order, offset, limit);
+    for row in iter {
+        let eval = |expr| execute_expr_in(expr, row.clone(), env);
+        let values = select.items.iter().map(|item| eval(item)).collect();
+        let keys = limits.order_by.iter().map(|(key, _)| eval(key)).collect();
+        sorted.push(keys, values);
+    }
+    sorted.write_to(&mut table, type_map);
 
-            w.write_all(format!("{} row(s) inserted\n", row_count).as_bytes())
-                .await?;
+    table
+}
+
+/// The order of the rows of a select, given by its order-by clause
+struct RowOrder<'a> {
+    order_by: &'a [(Spanned<Expr<'a>>, Order)],
+    key_types: Vec<TypeId>,
+    type_map: &'a TypeMap,
+}
+
+impl<'a> RowOrder<'a> {
+    fn new(
+        order_by: &'a [(Spanned<Expr<'a>>, Order)],
+        columns: &[(Option<&str>, &str, TypeId)],
+        grouping: Grouping,
+        env: &Env<'a>,
+    ) -> Self {
+        let keys = order_by.iter().map(|(key, _)| key);
+        let key_types = env
+            .type_of_exprs(keys, None, columns, grouping)
+            .unwrap_or_else(|e| panic!("Failed to get the types of order-by values: {:?}", e));
+        RowOrder {
+            order_by,
+            key_types,
+            type_map: env.types,
         }
     }
 
-    Ok(())
+    /// Compare the values of the order-by clause for two rows
+    fn compare(&self, keys_1: &[Value], keys_2: &[Value]) -> Ordering {
+        let columns = keys_1
+            .iter()
+            .zip(keys_2)
+            .zip(self.order_by)
+            .zip(&self.key_types);
+        for (((k1, k2), (_, order)), type_id) in columns {
+            let ordering = k1.compare(k2, Some(*type_id), self.type_map);
+            match (ordering, order) {
+                (Ordering::Equal, _) => continue,
+                (ordering, Order::Asc) => return ordering,
+                (ordering, Order::Desc) => return ordering.reverse(),
+            }
+        }
+        Ordering::Equal
+    }
 }
 
-fn execute_expr<'a, I>(expr: &Expr<'_>, mut bs: I) -> Value<'static>
+/// Rows which are written to a table in order, skipping the rows outside of the limit
+///
+/// With a limit, only the first `offset + limit` rows are kept while rows are pushed, so
+/// that the rows don't have to be sorted all at once.
+struct SortedRows<'s> {
+    order: &'s RowOrder<'s>,
+    offset: usize,
+
+    /// The number of rows to keep, including the skipped ones
+    keep: Option<usize>,
+
+    /// A max-heap of the rows which have been kept so far
+    rows: BinaryHeap<SortedRow<'s>>,
+    pushed: usize,
+}
+
+/// A row which is ordered by its keys, and then by when it was pushed
+struct SortedRow<'s> {
+    order: &'s RowOrder<'s>,
+    index: usize,
+
+    /// The values of the order-by clause
+    keys: Vec<Value<'static>>,
+    values: Vec<Value<'static>>,
+}
+
+impl<'s> SortedRows<'s> {
+    fn new(order: &'s RowOrder<'s>, offset: usize, limit: Option<usize>) -> Self {
+        SortedRows {
+            order,
+            offset,
+            keep: limit.map(|limit| offset.saturating_add(limit)),
+            rows: BinaryHeap::new(),
+            pushed: 0,
+        }
+    }
+
+    fn push(&mut self, keys: Vec<Value<'static>>, values: Vec<Value<'static>>) {
+        self.rows.push(SortedRow {
+            order: self.order,
+            index: self.pushed,
+            keys,
+            values,
+        });
+        self.pushed += 1;
+
+        // The greatest row is the last one, which is outside of the limit if there are too many
+        if let Some(keep) = self.keep {
+            if self.rows.len() > keep {
+                self.rows.pop();
+            }
+        }
+    }
+
+    /// Sort the rows and write them to a table
+    ///
+    /// The sort is stable, so rows with equal keys keep the order they were pushed in.
+    fn write_to(self, table: &mut Table, type_map: &TypeMap) {
+        for row in self.rows.into_sorted_vec().into_iter().skip(self.offset) {
+            table.push_row(&row.values, type_map);
+        }
+    }
+}
+
+impl Ord for SortedRow<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.order
+            .compare(&self.keys, &other.keys)
+            .then(self.index.cmp(&other.index))
+    }
+}
+
+impl PartialOrd for SortedRow<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for SortedRow<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for SortedRow<'_> {}
+
+/// Group rows by the group-by clause, and evaluate the selected values once for every group
+fn aggregate<'a>(
+    select: &'a Select<'a>,
+    rows: &Rows<'a>,
+    types: Option<&[TypeId]>,
+    limits: RowLimits<'a>,
+    env: &Env<'a>,
+) -> Table {
+    let type_map = env.types;
+    let iter = rows.iter(type_map);
+
+    let columns = columns_of(&iter);
+
+    let mut aggregates = vec![];
+    for item in &select.items {
+        item.aggregates(&mut aggregates);
+    }
+    for (key, _) in limits.order_by {
+        key.aggregates(&mut aggregates);
+    }
+
+    let new_accumulators = || -> Vec<Accumulator> {
+        aggregates
+            .iter()
+            .map(|aggregate| match aggregate {
+                Expr::Aggregate(function, arg) => {
+                    let arg_type = arg.as_ref().map(|arg| {
+                        env.type_of_exprs(
+                            std::slice::from_ref(&**arg),
+                            None,
+                            &columns,
+                            Grouping::None,
+                        )
+                        .unwrap_or_else(|e| panic!("Failed to get the type of {:?}: {:?}", arg, e))
+                            [0]
+                    });
+                    Accumulator::new(*function, arg_type, type_map)
+                }
+                _ => unreachable!("Non-aggregate expression in aggregates: {:?}", aggregate),
+            })
+            .collect()
+    };
+
+    // The groups in the order they were first seen, and an index on their keys
+    let mut groups: Vec<(Option<CellIter>, Vec<Accumulator>)> = vec![];
+    let mut index: HashMap<Vec<u8>, usize> = HashMap::new();
+    let mut key_buf: Vec<u8> = vec![];
+
+    for row in iter {
+        for key in &select.group_by {
+            // Groups are found by keys which are the same for equal values
+            execute_expr_in(key, row.clone(), env).write_key(&mut key_buf);
+        }
+
+        let group = match index.get(&key_buf) {
+            Some(&group) => group,
+            None => {
+                groups.push((Some(row.clone()), new_accumulators()));
+                index.insert(key_buf.clone(), groups.len() - 1);
+                groups.len() - 1
+            }
+        };
+        key_buf.clear();
+
+        let (_, accumulators) = &mut groups[group];
+        for (aggregate, accumulator) in aggregates.iter().zip(accumulators.iter_mut()) {
+            let (value, span) = match aggregate {
+                Expr::Aggregate(_, Some(arg)) => {
+                    (Some(execute_expr_in(arg, row.clone(), env)), arg.span)
+                }
+                _ => (None, None),
+            };
+            if let Err(e) = accumulator.add(value, span, type_map) {
+                env.fail(e);
+            }
+        }
+    }
+
+    // Without a group-by clause all rows form a single group, even if there are no rows
+    let grouping = if select.group_by.is_empty() {
+        Grouping::Single
+    } else {
+        Grouping::GroupBy
+    };
+    if groups.is_empty() && grouping == Grouping::Single {
+        groups.push((None, new_accumulators()));
+    }
+
+    let types = env
+        .type_of_exprs(&select.items, types, &columns, grouping)
+        .unwrap_or_else(|e| panic!("Failed to get the types of selected values: {:?}", e));
+    let names = select.column_names().into_iter().map(str::to_owned);
+
+    let mut table = Table::new(Schema::new(names.zip(types).collect()), type_map);
+    let order = RowOrder::new(limits.order_by, &columns, grouping, env);
+    let mut sorted = SortedRows::new(&order, limits.offset, limits.limit);
+    for (row, accumulators) in groups {
+        let results: Vec<(&Expr, Value)> = aggregates
+            .iter()
+            .copied()
+            .zip(
+                accumulators
+                    .into_iter()
+                    .map(|accumulator| accumulator.finish(grouping == Grouping::Single)),
+            )
+            .collect();
+        let env = Env {
+            aggregates: &results,
+            ..env.clone()
+        };
+
+        // Outside of aggregate functions, the values are the same for every row in the group
+        let eval = |expr| match &row {
+            Some(row) => execute_expr_in(expr, row.clone(), &env),
+            None => execute_expr_in(expr, empty(), &env),
+        };
+        let values = select.items.iter().map(|item| eval(item)).collect();
+        let keys = limits.order_by.iter().map(|(key, _)| eval(key)).collect();
+        sorted.push(keys, values);
+    }
+    sorted.write_to(&mut table, type_map);
+
+    table
+}
+
+/// The state of an aggregate function, after some of the rows in a group
+enum Accumulator {
+    Count {
+        count: i32,
+
+        /// Whether the values are of an optional type
+        optional: bool,
+    },
+    Sum(Value<'static>),
+
+    /// The least or greatest value, along with the type of the values, which is needed to order
+    /// values of sum-types
+    Min(Option<Value<'static>>, Option<TypeId>),
+    Max(Option<Value<'static>>, Option<TypeId>),
+    Avg(f64, i32),
+}
+
+impl Accumulator {
+    fn new(function: Aggregate, arg_type: Option<TypeId>, type_map: &TypeMap) -> Self {
+        let is_type = |base| arg_type == Some(type_map.get_base_id(base));
+        match function {
+            Aggregate::Count => Accumulator::Count {
+                count: 0,
+                optional: arg_type.map(|t| type_map.is_optional(t)).unwrap_or(false),
+            },
+            Aggregate::Sum if is_type(BaseType::Double) => Accumulator::Sum(Value::Double(0.0)),
+            Aggregate::Sum => Accumulator::Sum(Value::Integer(0)),
+            Aggregate::Min => Accumulator::Min(None, arg_type),
+            Aggregate::Max => Accumulator::Max(None, arg_type),
+            Aggregate::Avg => Accumulator::Avg(0.0, 0),
+        }
+    }
+
+    /// Add the value of the argument for a row, which is None for `COUNT(*)`
+    ///
+    /// Sums of integers fail instead of overflowing, and `span` is the span of the argument.
+    fn add(
+        &mut self,
+        value: Option<Value<'static>>,
+        span: Option<Span>,
+        types: &TypeMap,
+    ) -> Result<(), ExecError> {
+        let v = match (&mut *self, value) {
+            // Missing values of optional types are not counted, like NULL in SQL
+            (Accumulator::Count { optional: true, .. }, Some(Value::Sum(_, variant, _)))
+                if variant == OPTIONAL_NONE =>
+            {
+                return Ok(())
+            }
+            (Accumulator::Count { count, .. }, _) => {
+                *count += 1;
+                return Ok(());
+            }
+            (_, Some(v)) => v,
+            (_, None) => unreachable!("Missing value for aggregate function"),
+        };
+
+        match self {
+            Accumulator::Count { .. } => unreachable!("Counts are updated above"),
+            Accumulator::Sum(sum) => match (sum, v) {
+                (Value::Integer(sum), Value::Integer(v)) => {
+                    *sum = sum.checked_add(v).ok_or(ExecError::Overflow {
+                        span,
+                        operation: "SUM",
+                    })?
+                }
+                (Value::Double(sum), Value::Double(v)) => *sum += v,
+                (_, v) => unreachable!("Invalid value for aggregate function: {:?}", v),
+            },
+            Accumulator::Min(min, type_id) => {
+                let less = |min: &Value| v.compare(min, *type_id, types) == Ordering::Less;
+                if min.as_ref().map(less).unwrap_or(true) {
+                    *min = Some(v);
+                }
+            }
+            Accumulator::Max(max, type_id) => {
+                let greater = |max: &Value| v.compare(max, *type_id, types) == Ordering::Greater;
+                if max.as_ref().map(greater).unwrap_or(true) {
+                    *max = Some(v);
+                }
+            }
+            Accumulator::Avg(sum, count) => {
+                *sum += match v {
+                    Value::Integer(v) => v as f64,
+                    Value::Double(v) => v,
+                    v => unreachable!("Invalid value for aggregate function: {:?}", v),
+                };
+                *count += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the result of the aggregate function
+    ///
+    /// If the group may be empty, the minimum, maximum and average are optional.
+    fn finish(self, optional: bool) -> Value<'static> {
+        let some = |v| {
+            if optional {
+                Value::Sum(None, Cow::Borrowed(OPTIONAL_SOME), vec![v])
+            } else {
+                v
+            }
+        };
+        let none = || {
+            assert!(optional, "Empty group");
+            Value::Sum(None, Cow::Borrowed(OPTIONAL_NONE), vec![])
+        };
+
+        match self {
+            Accumulator::Count { count, .. } => Value::Integer(count),
+            Accumulator::Sum(sum) => sum,
+            Accumulator::Min(v, _) | Accumulator::Max(v, _) => v.map(some).unwrap_or_else(none),
+            Accumulator::Avg(_, 0) => none(),
+            Accumulator::Avg(sum, count) => some(Value::Double(sum / count as f64)),
+        }
+    }
+}
+
+async fn execute_create_table(
+    create_table: CreateTable<'_>,
+    s: &DbmsState,
+    mut resources: ResourcesGuard<'_, Table>,
+    w: &mut (dyn AsyncWrite + Send + Unpin),
+) -> Result<(), Box<dyn Error>> {
+    let types = &mut resources.type_map;
+    let columns: Vec<_> = create_table
+        .columns
+        .into_iter()
+        .map(|(column_name, column_type)| {
+            (column_name.to_string(), resolve_type(&column_type, types))
+        })
+        .collect();
+
+    let schema = Schema::new(columns);
+    let table = Table::new(schema, &resources.type_map);
+
+    match s.create_table(create_table.table.to_string(), table).await {
+        Ok(()) => {
+            w.write_all(format!("table created: \"{}\"\n", create_table.table).as_bytes())
+                .await?
+        }
+        Err(()) => {
+            w.write_all(format!("table already exists: \"{}\"\n", create_table.table).as_bytes())
+                .await?
+        }
+    };
+    Ok(())
+}
+
+async fn execute_create_type(
+    create_types: Vec<CreateType<'_>>,
+    mut resources: ResourcesGuard<'_, Table>,
+    w: &mut (dyn AsyncWrite + Send + Unpin),
+) -> Result<(), Box<dyn Error>> {
+    let types = &mut resources.type_map;
+    let first_id = types.next_id();
+
+    // Declare all types before defining them, since they may refer to each other.
+    // Generic types are not declared, since they are only instantiated when they are used.
+    let ids: Vec<Option<TypeId>> = create_types
+        .iter()
+        .map(|create_type| match create_type {
+            CreateType::Variant { name, params, .. } if params.is_empty() => {
+                Some(types.declare(name.value))
+            }
+            CreateType::Variant { .. } => None,
+            CreateType::Record { name, .. } => Some(types.declare(name.value)),
+        })
+        .collect();
+
+    for create_type in &create_types {
+        match create_type {
+            CreateType::Variant {
+                name,
+                params,
+                variants,
+            } if !params.is_empty() => {
+                let variants = variants
+                    .iter()
+                    .map(|(constructor, members)| {
+                        let members = members
+                            .iter()
+                            .map(|member| template_type(member, params, types))
+                            .collect();
+                        (constructor.to_string(), members)
+                    })
+                    .collect();
+
+                let template = Template {
+                    params: params.len(),
+                    variants,
+                };
+                types.insert_template(name.value, template);
+            }
+            CreateType::Variant { .. } | CreateType::Record { .. } => {}
+        }
+    }
+
+    for (create_type, id) in create_types.into_iter().zip(ids.into_iter()) {
+        match create_type {
+            CreateType::Variant { name, variants, .. } => {
+                if let Some(id) = id {
+                    let variant_types: Vec<_> = variants
+                        .into_iter()
+                        .map(|(constructor, subtypes)| {
+                            let subtype_ids: Vec<TypeId> = subtypes
+                                .iter()
+                                .map(|subtype| resolve_type(subtype, types))
+                                .collect();
+
+                            (constructor.to_string(), subtype_ids)
+                        })
+                        .collect();
+
+                    types.define(id, Type::Sum(variant_types));
+                }
+
+                w.write_all(b"type ").await?;
+                w.write_all(name.as_bytes()).await?;
+                w.write_all(b" created\n").await?;
+            }
+            CreateType::Record { name, fields } => {
+                let fields = fields
+                    .iter()
+                    .map(|(field, t)| (field.to_string(), resolve_type(t, types)))
+                    .collect();
+
+                types.define(id.unwrap(), Type::Product(fields));
+
+                w.write_all(b"type ").await?;
+                w.write_all(name.as_bytes()).await?;
+                w.write_all(b" created\n").await?;
+            }
+        }
+    }
+
+    // This includes any instances of generic types created while defining the types
+    let created: Vec<TypeId> = (first_id..types.next_id()).collect();
+    types.mark_recursive(&created);
+    Ok(())
+}
+
+/// Get the id of a concrete type, instantiating generic types if needed
+fn resolve_type(t: &TypeRef, types: &mut TypeMap) -> TypeId {
+    if t.args.is_empty() {
+        types.get_id(t.name.value).expect("Type does not exist")
+    } else {
+        let args: Vec<TypeId> = t.args.iter().map(|arg| resolve_type(arg, types)).collect();
+        types.instantiate(t.name.value, &args)
+    }
+}
+
+/// Convert the type of a member of a generic type to a template
+fn template_type(t: &TypeRef, params: &[Spanned<&str>], types: &TypeMap) -> TemplateType {
+    if t.args.is_empty() {
+        match params.iter().position(|param| param.value == t.name.value) {
+            Some(i) => TemplateType::Param(i),
+            None => {
+                TemplateType::Concrete(types.get_id(t.name.value).expect("Type does not exist"))
+            }
+        }
+    } else {
+        let args = t
+            .args
+            .iter()
+            .map(|arg| template_type(arg, params, types))
+            .collect();
+        TemplateType::Instance(t.name.to_string(), args)
+    }
+}
+
+async fn execute_drop_table(
+    drop: Drop<'_>,
+    s: &DbmsState,
+    w: &mut (dyn AsyncWrite + Send + Unpin),
+) -> Result<(), Box<dyn Error>> {
+    match s.drop_table(drop.table).await {
+        Ok(()) => {
+            w.write_all(format!("table dropped: \"{}\"\n", drop.table).as_bytes())
+                .await?
+        }
+        Err(DropError::NoSuchName) => {
+            w.write_all(format!("no such table: \"{}\"\n", drop.table).as_bytes())
+                .await?
+        }
+        Err(DropError::ReadByView(view)) => {
+            w.write_all(
+                format!("view \"{}\" reads from table: \"{}\"\n", view, drop.table).as_bytes(),
+            )
+            .await?
+        }
+    }
+    Ok(())
+}
+
+async fn execute_alter_table(
+    alter_table: AlterTable<'_>,
+    mut resources: ResourcesGuard<'_, Table>,
+    w: &mut (dyn AsyncWrite + Send + Unpin),
+) -> Result<(), Box<dyn Error>> {
+    let name = alter_table.table;
+
+    // The views are checked against the altered columns before the rows are changed, since the
+    // cells of dropped columns can't be restored
+    let mut schema = resources.read_table(name).schema().clone();
+    match &alter_table.alteration {
+        Alteration::AddColumn {
+            column,
+            column_type,
+            ..
+        } => {
+            let type_id = resolve_type(column_type, &mut resources.type_map);
+            schema.columns.push((column.to_string(), type_id));
+        }
+        Alteration::DropColumn(column) => {
+            let col = schema.column_index(column).expect("Column does not exist");
+            schema.columns.remove(col);
+        }
+        Alteration::RenameColumn(from, to) => {
+            let col = schema.column_index(from).expect("Column does not exist");
+            schema.columns[col].0 = to.to_string();
+        }
+    }
+
+    let (table, _) = resources.write_table(name);
+    let old_schema = replace(&mut table.schema, schema);
+    let view = invalid_view(&resources);
+    let (table, _) = resources.write_table(name);
+    table.schema = old_schema;
+
+    if let Some(view) = view {
+        w.write_all(
+            format!(
+                "view \"{}\" is invalid for the altered table: \"{}\"\n",
+                view, name
+            )
+            .as_bytes(),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    match alter_table.alteration {
+        Alteration::AddColumn {
+            column,
+            column_type,
+            default,
+        } => {
+            // The default value is computed before the table is written, since subqueries may
+            // read it
+            let value = {
+                let env = Env::new(&resources);
+                let value = execute_expr_in(&default, empty(), &env);
+                env.check()?;
+                value
+            };
+            let type_id = resolve_type(&column_type, &mut resources.type_map);
+
+            let (table, type_map) = resources.write_table(name);
+            table.add_column(column.to_string(), type_id, &value, type_map);
+        }
+        Alteration::DropColumn(column) => {
+            let (table, type_map) = resources.write_table(name);
+            let col = table
+                .schema()
+                .column_index(&column)
+                .expect("Column does not exist");
+            table.drop_column(col, type_map);
+        }
+        Alteration::RenameColumn(from, to) => {
+            let (table, _) = resources.write_table(name);
+            let col = table
+                .schema()
+                .column_index(&from)
+                .expect("Column does not exist");
+            table.schema.columns[col].0 = to.to_string();
+        }
+    }
+
+    w.write_all(format!("table altered: \"{}\"\n", name).as_bytes())
+        .await?;
+    Ok(())
+}
+
+async fn execute_alter_type(
+    alter_type: AlterType<'_>,
+    mut resources: ResourcesGuard<'_, Table>,
+    w: &mut (dyn AsyncWrite + Send + Unpin),
+) -> Result<(), Box<dyn Error>> {
+    let name = alter_type.name.value;
+    let old_types = resources.type_map.clone();
+    let id = old_types.get_id(name).expect("Type does not exist");
+
+    match &alter_type.alteration {
+        TypeAlteration::AddVariant { variant, members } => {
+            let types = &mut resources.type_map;
+            let members = members.iter().map(|t| resolve_type(t, types)).collect();
+            types.add_variant(id, variant.to_string(), members);
+        }
+        TypeAlteration::RenameVariant(from, to) => {
+            resources.type_map.rename_variant(id, from, to);
+        }
+    }
+
+    // Views are stored as text, so they may refer to the variants, or match on all of them
+    if let Some(view) = invalid_view(&resources) {
+        *resources.type_map = old_types;
+        w.write_all(
+            format!(
+                "view \"{}\" is invalid for the altered type: \"{}\"\n",
+                view, name
+            )
+            .as_bytes(),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    // Renaming variants doesn't change the layout of their values, since they are stored by tag
+    if let TypeAlteration::AddVariant { .. } = alter_type.alteration {
+        let ResourcesGuard {
+            type_map, tables, ..
+        } = &mut resources;
+        let users = type_map.users_of(id);
+        for (_, table) in tables.iter_mut() {
+            let columns = &table.schema().columns;
+            if columns.iter().any(|(_, t)| users.contains(t)) {
+                table.reencode(&old_types, type_map);
+            }
+        }
+    }
+
+    w.write_all(format!("type {} altered\n", name).as_bytes())
+        .await?;
+    Ok(())
+}
+
+/// Find a view which is no longer well-typed, or whose columns no longer have their stored types
+///
+/// Views are stored as text, so they are checked again when the tables and types they read are
+/// altered.
+fn invalid_view(resources: &ResourcesGuard<'_, Table>) -> Option<String> {
+    resources
+        .views
+        .iter()
+        .find(|(_, view)| {
+            let select = SELECT_PARSER
+                .parse(&view.query)
+                .expect("Failed to parse the select of a view");
+            let stored_types = view.schema.columns.iter().map(|(_, t)| *t);
+            match typechecker::type_of_select(&select, resources) {
+                Ok(types) => !types.into_iter().eq(stored_types),
+                Err(_) => true,
+            }
+        })
+        .map(|(view, _)| view.to_string())
+}
+
+async fn execute_create_view(
+    create_view: CreateView<'_>,
+    s: &DbmsState,
+    resources: ResourcesGuard<'_, Table>,
+    w: &mut (dyn AsyncWrite + Send + Unpin),
+) -> Result<(), Box<dyn Error>> {
+    let select = &create_view.select;
+    let types = typechecker::type_of_select(select, &resources).expect("View is not well-typed");
+    let names = select.column_names().into_iter().map(str::to_owned);
+
+    let view = View {
+        query: create_view.query.to_string(),
+        schema: Schema::new(names.zip(types).collect()),
+        reads: pre_typechecker::get_read_tables(select),
+    };
+
+    let name = create_view.name.value;
+    match s.create_view(name.to_string(), view).await {
+        Ok(()) => {
+            w.write_all(format!("view created: \"{}\"\n", name).as_bytes())
+                .await?
+        }
+        Err(()) => {
+            w.write_all(format!("view already exists: \"{}\"\n", name).as_bytes())
+                .await?
+        }
+    };
+    Ok(())
+}
+
+async fn execute_drop_view(
+    drop: DropView<'_>,
+    s: &DbmsState,
+    w: &mut (dyn AsyncWrite + Send + Unpin),
+) -> Result<(), Box<dyn Error>> {
+    match s.drop_view(drop.view).await {
+        Ok(()) => {
+            w.write_all(format!("view dropped: \"{}\"\n", drop.view).as_bytes())
+                .await?
+        }
+        Err(DropError::NoSuchName) => {
+            w.write_all(format!("no such view: \"{}\"\n", drop.view).as_bytes())
+                .await?
+        }
+        Err(DropError::ReadByView(view)) => {
+            w.write_all(
+                format!("view \"{}\" reads from view: \"{}\"\n", view, drop.view).as_bytes(),
+            )
+            .await?
+        }
+    }
+    Ok(())
+}
+
+async fn execute_insert(
+    insert: Insert<'_>,
+    mut resources: ResourcesGuard<'_, Table>,
+    w: &mut (dyn AsyncWrite + Send + Unpin),
+) -> Result<(), Box<dyn Error>> {
+    match insert.from {
+        // case !query
+        InsertFrom::Values(rows) => {
+            // The values are computed before the table is written, since subqueries may read it
+            let env = Env::new(&resources);
+            let values: Vec<Vec<Value>> = rows
+                .iter()
+                .map(|row| {
+                    row.iter()
+                        .map(|e| execute_expr_in(e, empty(), &env))
+                        .collect()
+                })
+                .collect();
+            env.check()?;
+
+            let (table, type_map) = resources.write_table(&insert.table);
+            let row_count = rows.len();
+            for row in &values {
+                table.push_row(row, &type_map);
+            }
+
+            w.write_all(format!("{} row(s) inserted\n", row_count).as_bytes())
+                .await?;
+        }
+
+        //case query
+        InsertFrom::Select(select) => {
+            let type_map = &resources.type_map;
+            let schema = resources.read_table(&insert.table).schema().clone();
+
+            // The selected values have the types of the columns they are inserted into
+            let columns = &insert.columns;
+            let types: Vec<TypeId> = columns
+                .iter()
+                .map(|column| schema.column(column).expect("Column does not exist"))
+                .collect();
+
+            // The columns of the table may be in a different order than the selected values
+            let positions: Vec<usize> = schema
+                .columns
+                .iter()
+                .map(|(name, _)| {
+                    columns
+                        .iter()
+                        .position(|column| column.value == name)
+                        .expect("Column is missing")
+                })
+                .collect();
+
+            let mut rows = Table::new(schema, type_map);
+            let mut row_count = 0;
+            let mut cells = vec![];
+            let env = Env::new(&resources);
+            for row in execute_select(&select, Some(&types), &env).iter(type_map) {
+                row_count += 1;
+                cells.extend(row.map(|(_, _, cell)| cell));
+                for &i in &positions {
+                    cells[i].copy_into(&mut rows.data, &mut rows.heap);
+                }
+                cells.clear();
+            }
+            env.check()?;
+
+            let (table, type_map) = resources.write_table(&insert.table);
+            table.push_table(&rows, type_map);
+
+            w.write_all(format!("{} row(s) inserted\n", row_count).as_bytes())
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn execute_update(
+    update: Update<'_>,
+    mut resources: ResourcesGuard<'_, Table>,
+    w: &mut (dyn AsyncWrite + Send + Unpin),
+) -> Result<(), Box<dyn Error>> {
+    let where_items = update
+        .where_clause
+        .as_ref()
+        .map(|wc| &wc.items[..])
+        .unwrap_or(&[]);
+
+    // The scan borrows the table, so we compute all new values before writing any of them.
+    // This also means that every assignment sees the row as it was before the update.
+    let mut updates = vec![];
+    let mut row_count = 0;
+    {
+        let env = Env::new(&resources);
+        let table = resources.read_table(&update.table);
+
+        let columns: Vec<usize> = update
+            .ass
+            .iter()
+            .map(|ass| {
+                table
+                    .schema()
+                    .column_index(&ass.col)
+                    .expect("Column does not exist")
+            })
+            .collect();
+
+        let mut scan = full_table_scan(table, env.types);
+        scan.apply_pattern(where_items, &env);
+
+        for row in scan {
+            row_count += 1;
+            for (ass, &col) in update.ass.iter().zip(columns.iter()) {
+                let value = execute_expr_in(&ass.expr, row.clone(), &env);
+                updates.push((row.row, col, value));
+            }
+        }
+        env.check()?;
+    }
+
+    let (table, type_map) = resources.write_table(&update.table);
+    for (row, col, value) in updates {
+        table.set_cell(row, col, &value, type_map);
+    }
+
+    // The old values of strings and boxed members are left in the heap
+    table.compact_heap(type_map);
+
+    w.write_all(format!("{} row(s) updated\n", row_count).as_bytes())
+        .await?;
+
+    Ok(())
+}
+
+async fn execute_delete(
+    delete: Delete<'_>,
+    mut resources: ResourcesGuard<'_, Table>,
+    w: &mut (dyn AsyncWrite + Send + Unpin),
+) -> Result<(), Box<dyn Error>> {
+    let where_items = delete
+        .where_clause
+        .as_ref()
+        .map(|wc| &wc.items[..])
+        .unwrap_or(&[]);
+
+    // The scan borrows the table, so we find all matching rows before removing any of them.
+    let rows: Vec<usize> = {
+        let env = Env::new(&resources);
+        let table = resources.read_table(&delete.table);
+        let mut scan = full_table_scan(table, env.types);
+        scan.apply_pattern(where_items, &env);
+        let rows = scan.map(|row| row.row).collect();
+        env.check()?;
+        rows
+    };
+
+    let (table, type_map) = resources.write_table(&delete.table);
+    table.remove_rows(&rows, type_map);
+
+    w.write_all(format!("{} row(s) deleted\n", rows.len()).as_bytes())
+        .await?;
+
+    Ok(())
+}
+
+/// The maximum number of rows of a recursive common table
+const MAX_RECURSIVE_ROWS: usize = 100_000;
+
+/// An error which stops a statement while it is executed, e.g. an overflowing sum
+#[derive(Debug, Clone)]
+pub enum ExecError {
+    /// The result of an integer operation doesn't fit in an Integer
+    Overflow {
+        span: Option<Span>,
+        operation: &'static str,
+    },
+
+    /// An integer was divided by zero, where `span` is the span of the divisor
+    DivisionByZero { span: Option<Span> },
+
+    /// A subquery used as a value selected more than one row, where `span` is the span of the
+    /// selected value
+    MultipleRows { span: Option<Span> },
+
+    /// A recursive common table had too many rows, where `span` is the span of its name
+    RecursionLimit { span: Option<Span>, table: String },
+}
+
+impl ExecError {
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            ExecError::Overflow { span, .. }
+            | ExecError::DivisionByZero { span }
+            | ExecError::MultipleRows { span }
+            | ExecError::RecursionLimit { span, .. } => *span,
+        }
+    }
+}
+
+impl Display for ExecError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ExecError::Overflow { operation, .. } => write!(f, "integer overflow in {}", operation),
+            ExecError::DivisionByZero { .. } => write!(f, "division by zero"),
+            ExecError::MultipleRows { .. } => write!(f, "subquery selected more than one row"),
+            ExecError::RecursionLimit { table, .. } => write!(
+                f,
+                "recursive table \"{}\" has more than {} rows",
+                table, MAX_RECURSIVE_ROWS
+            ),
+        }
+    }
+}
+
+impl Error for ExecError {}
+
+/// Values which are in scope when evaluating an expression, besides the cells of the row
+#[derive(Clone)]
+struct Env<'e> {
+    types: &'e TypeMap,
+
+    /// The tables which subqueries may read
+    resources: &'e ResourcesGuard<'e, Table>,
+
+    /// The values bound by the patterns of match arms, along with their types if they are known
+    locals: Vec<(&'e str, Value<'static>, Option<TypeId>)>,
+
+    /// The results of the aggregate functions in the expression, for the current group
+    aggregates: &'e [(&'e Expr<'e>, Value<'static>)],
+
+    /// The columns of the rows of enclosing queries, in the order they are looked up in
+    outer: Vec<(Option<&'e str>, &'e str, Value<'static>, Option<TypeId>)>,
+
+    /// The common tables of enclosing selects, where the innermost table is last
+    tables: Vec<(&'e str, Arc<Table>)>,
+
+    /// The views which may be read, along with their rows once they have been executed
+    ///
+    /// The rows are shared with the envs of subqueries, so every view is executed at most once.
+    views: Arc<Vec<(&'e str, &'e View, Mutex<Option<Arc<Table>>>)>>,
+
+    /// The first error of the statement, which is shared with the envs of subqueries and views
+    error: Arc<Mutex<Option<ExecError>>>,
+}
+
+impl<'e> Env<'e> {
+    fn new(resources: &'e ResourcesGuard<'e, Table>) -> Self {
+        let views = resources
+            .views
+            .iter()
+            .map(|&(name, view)| (name, view, Mutex::new(None)))
+            .collect();
+
+        Env {
+            types: &resources.type_map,
+            resources,
+            locals: vec![],
+            aggregates: &[],
+            outer: vec![],
+            tables: vec![],
+            views: Arc::new(views),
+            error: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Stop the statement because of an error, keeping only the first one
+    ///
+    /// Expressions which fail still give some value, so the statement has to check for errors
+    /// with [check](#method.check) before it changes any tables or prints any rows.
+    fn fail(&self, error: ExecError) {
+        let mut first = self.error.lock().expect("Failed to lock the error");
+        first.get_or_insert(error);
+    }
+
+    /// Get the first error of the statement, if anything failed
+    fn check(&self) -> Result<(), ExecError> {
+        match &*self.error.lock().expect("Failed to lock the error") {
+            Some(error) => Err(error.clone()),
+            None => Ok(()),
+        }
+    }
+
+    /// The rows of a view, which is executed the first time it is read
+    fn read_view(&self, name: &str) -> Option<Arc<Table>> {
+        let (_, view, rows) = self.views.iter().find(|(view, _, _)| *view == name)?;
+        let mut rows = rows.lock().expect("Failed to lock the rows of a view");
+        let rows = rows.get_or_insert_with(|| {
+            // A view only reads tables and other views, so it doesn't see the rest of the env
+            let env = Env {
+                locals: vec![],
+                aggregates: &[],
+                outer: vec![],
+                tables: vec![],
+                ..self.clone()
+            };
+            Arc::new(execute_view(view, &env))
+        });
+        Some(rows.clone())
+    }
+
+    /// The env of a subquery, evaluated for the row with the cells `bs`
+    ///
+    /// The variables of match arms and the cells of the row become the outer columns of the
+    /// subquery, and shadow the outer columns of this env.
+    fn subquery<'a: 'x, 'x, I>(&self, bs: I) -> Env<'x>
+    where
+        I: Iterator<Item = (Option<&'a str>, &'a str, Cell<'a, 'a>)>,
+        'e: 'x,
+    {
+        let types = self.types;
+        let locals = self.locals.iter().rev().map(|(name, value, type_id)| {
+            let type_id = type_id.or_else(|| base_type_of(value, types));
+            (None, *name, value.clone(), type_id)
+        });
+        let cells: Vec<_> = bs
+            .map(|(table, name, cell)| {
+                let type_id = cell.type_id();
+                (table, name, read_cell(cell), Some(type_id))
+            })
+            .collect();
+
+        Env {
+            types,
+            resources: self.resources,
+            locals: vec![],
+            aggregates: &[],
+            outer: locals
+                .chain(cells)
+                .chain(self.outer.iter().cloned())
+                .collect(),
+            tables: self.tables.clone(),
+            views: self.views.clone(),
+            error: self.error.clone(),
+        }
+    }
+
+    /// Find the types of expressions over the columns, which may also refer to the outer columns
+    fn type_of_exprs(
+        &self,
+        exprs: impl IntoIterator<Item = &'e Spanned<Expr<'e>>>,
+        expected: Option<&[TypeId]>,
+        columns: &[(Option<&str>, &str, TypeId)],
+        grouping: Grouping,
+    ) -> Result<Vec<TypeId>, typechecker::TypeError> {
+        let outer: Vec<_> = self
+            .outer
+            .iter()
+            .filter_map(|(table, name, _, type_id)| type_id.map(|type_id| (*table, *name, type_id)))
+            .collect();
+        typechecker::type_of_exprs(exprs, expected, columns, &outer, grouping, self.resources)
+    }
+}
+fn execute_expr_in<'a, 'e, I>(expr: &'e Expr<'e>, bs: I, env: &Env<'e>) -> Value<'static>
 where
-    I: Iterator<Item = (&'a str, Cell<'a, 'a>)> + Clone,
+    I: Iterator<Item = (Option<&'a str>, &'a str, Cell<'a, 'a>)> + Clone,
 {
-    fn cmp<'a, I, F>(e1: &Expr, e2: &Expr, bs: I, f: F) -> Value<'static>
+    fn eq<'a, 'e, I>(e1: &'e Expr<'e>, e2: &'e Expr<'e>, bs: I, env: &Env<'e>) -> bool
+    where
+        I: Iterator<Item = (Option<&'a str>, &'a str, Cell<'a, 'a>)> + Clone,
+    {
+        let v1 = execute_expr_in(e1, bs.clone(), env);
+        let v2 = execute_expr_in(e2, bs, env);
+        v1.equals(&v2, env.types)
+    }
+
+    fn ord<'a, 'e, I, F>(
+        e1: &'e Expr<'e>,
+        e2: &'e Expr<'e>,
+        bs: I,
+        env: &Env<'e>,
+        f: F,
+    ) -> Value<'static>
     where
-        F: for<'l, 'r> FnOnce(&'l Value<'l>, &'r Value<'r>) -> bool,
-        I: Iterator<Item = (&'a str, Cell<'a, 'a>)> + Clone,
+        F: FnOnce(Ordering) -> bool,
+        I: Iterator<Item = (Option<&'a str>, &'a str, Cell<'a, 'a>)> + Clone,
     {
-        let v1 = execute_expr(e1, bs.clone());
-        let v2 = execute_expr(e2, bs);
-        Value::Bool(f(&v1, &v2))
+        // Sum-type values need their types to be ordered, and both sides have the same type
+        let type_id = type_of(e1, bs.clone(), env, &mut vec![])
+            .or_else(|| type_of(e2, bs.clone(), env, &mut vec![]));
+        let v1 = execute_expr_in(e1, bs.clone(), env);
+        let v2 = execute_expr_in(e2, bs, env);
+        Value::Bool(f(v1.compare(&v2, type_id, env.types)))
+    }
+
+    /// Integer operations give None if the divisor is zero, which stops the statement
+    fn arithmetic<'a, 'e, I, F, G>(
+        e1: &'e Expr<'e>,
+        e2: &'e Spanned<Expr<'e>>,
+        bs: I,
+        env: &Env<'e>,
+        integer: F,
+        double: G,
+    ) -> Value<'static>
+    where
+        F: FnOnce(i32, i32) -> Option<i32>,
+        G: FnOnce(f64, f64) -> f64,
+        I: Iterator<Item = (Option<&'a str>, &'a str, Cell<'a, 'a>)> + Clone,
+    {
+        match (
+            execute_expr_in(e1, bs.clone(), env),
+            execute_expr_in(e2, bs, env),
+        ) {
+            (Value::Integer(v1), Value::Integer(v2)) => match integer(v1, v2) {
+                Some(v) => Value::Integer(v),
+                None => {
+                    env.fail(ExecError::DivisionByZero { span: e2.span });
+                    Value::Integer(0)
+                }
+            },
+            (Value::Double(v1), Value::Double(v2)) => Value::Double(double(v1, v2)),
+            (v1, v2) => unreachable!("Arithmetic on non-numeric values: {:?}, {:?}", v1, v2),
+        }
     }
 
     match expr {
         Expr::Value(v) => v.deep_clone(),
-        Expr::Eql(box (e1, e2)) => cmp(e1, e2, bs, |v1, v2| v1 == v2),
-        Expr::NEq(box (e1, e2)) => cmp(e1, e2, bs, |v1, v2| v1 != v2),
-        Expr::LEq(box (e1, e2)) => cmp(e1, e2, bs, |v1, v2| v1 <= v2),
-        Expr::LTh(box (e1, e2)) => cmp(e1, e2, bs, |v1, v2| v1 < v2),
-        Expr::GTh(box (e1, e2)) => cmp(e1, e2, bs, |v1, v2| v1 > v2),
-        Expr::GEq(box (e1, e2)) => cmp(e1, e2, bs, |v1, v2| v1 >= v2),
-        Expr::And(box (e1, e2)) => match execute_expr(e1, bs.clone()) {
-            Value::Bool(true) => execute_expr(e2, bs),
+        Expr::Sum(namespace, variant, args) => Value::Sum(
+            namespace.map(|ns| Cow::Owned(ns.to_string())),
+            Cow::Owned(variant.to_string()),
+            args.iter()
+                .map(|e| execute_expr_in(e, bs.clone(), env))
+                .collect(),
+        ),
+        Expr::Record(fields) => {
+            let mut values: Vec<_> = fields
+                .iter()
+                .map(|(name, e)| {
+                    (
+                        Cow::<str>::Owned(name.to_string()),
+                        execute_expr_in(e, bs.clone(), env),
+                    )
+                })
+                .collect();
+
+            // Record values are sorted by field name
+            values.sort_by(|(a, _), (b, _)| a.cmp(b));
+            Value::Product(values)
+        }
+        Expr::Field(
+            box Spanned {
+                value: Expr::Ident(table),
+                ..
+            },
+            field,
+        ) if !is_defined(table, bs.clone(), env) => {
+            read_column((Some(table.value), field.value), bs, env)
+        }
+        Expr::Field(box record, field) => match execute_expr_in(record, bs, env) {
+            Value::Product(values) => values
+                .into_iter()
+                .find(|(name, _)| name == field.as_ref())
+                .map(|(_, v)| v)
+                .unwrap_or_else(|| unreachable!("Field did not exist")),
+            v => unreachable!("Non-record expression in Expr::Field: {:?}", v),
+        },
+        Expr::Eql(box (e1, e2)) => Value::Bool(eq(e1, e2, bs, env)),
+        Expr::NEq(box (e1, e2)) => Value::Bool(!eq(e1, e2, bs, env)),
+        Expr::LEq(box (e1, e2)) => ord(e1, e2, bs, env, |o| o != Ordering::Greater),
+        Expr::LTh(box (e1, e2)) => ord(e1, e2, bs, env, |o| o == Ordering::Less),
+        Expr::GTh(box (e1, e2)) => ord(e1, e2, bs, env, |o| o == Ordering::Greater),
+        Expr::GEq(box (e1, e2)) => ord(e1, e2, bs, env, |o| o != Ordering::Less),
+        Expr::And(box (e1, e2)) => match execute_expr_in(e1, bs.clone(), env) {
+            Value::Bool(true) => execute_expr_in(e2, bs, env),
             Value::Bool(false) => Value::Bool(false),
             v => unreachable!("Non-boolean expression in Expr::And: {:?}", v),
         },
-        Expr::Or(box (e1, e2)) => match execute_expr(e1, bs.clone()) {
+        Expr::Or(box (e1, e2)) => match execute_expr_in(e1, bs.clone(), env) {
             Value::Bool(true) => Value::Bool(true),
-            Value::Bool(false) => execute_expr(e2, bs),
+            Value::Bool(false) => execute_expr_in(e2, bs, env),
             v => unreachable!("Non-boolean expression in Expr::And: {:?}", v),
         },
+        Expr::Not(box value) => match execute_expr_in(value, bs, env) {
+            Value::Bool(b) => Value::Bool(!b),
+            v => unreachable!("Non-boolean expression in Expr::Not: {:?}", v),
+        },
+        Expr::Add(box (e1, e2)) => arithmetic(
+            e1,
+            e2,
+            bs,
+            env,
+            |a, b| Some(a.wrapping_add(b)),
+            |a, b| a + b,
+        ),
+        Expr::Sub(box (e1, e2)) => arithmetic(
+            e1,
+            e2,
+            bs,
+            env,
+            |a, b| Some(a.wrapping_sub(b)),
+            |a, b| a - b,
+        ),
+        Expr::Mul(box (e1, e2)) => arithmetic(
+            e1,
+            e2,
+            bs,
+            env,
+            |a, b| Some(a.wrapping_mul(b)),
+            |a, b| a * b,
+        ),
+        Expr::Div(box (e1, e2)) => arithmetic(e1, e2, bs, env, integer_div, |a, b| a / b),
+        Expr::Rem(box (e1, e2)) => arithmetic(e1, e2, bs, env, integer_rem, |a, b| a % b),
+        Expr::Neg(box value) => match execute_expr_in(value, bs, env) {
+            Value::Integer(v) => Value::Integer(v.wrapping_neg()),
+            Value::Double(v) => Value::Double(-v),
+            v => unreachable!("Non-numeric expression in Expr::Neg: {:?}", v),
+        },
+        Expr::Cast(box value, target) => match (execute_expr_in(value, bs, env), target.value) {
+            // Casting a double saturates at the bounds of Integer, and NaN becomes 0. Casts with
+            // `as` only saturate in later versions of Rust, so the bounds are checked here.
+            (Value::Double(v), "Integer") if v.is_nan() => Value::Integer(0),
+            (Value::Double(v), "Integer") => {
+                Value::Integer(v.max(i32::MIN as f64).min(i32::MAX as f64) as i32)
+            }
+            (Value::Integer(v), "Double") => Value::Double(v as f64),
+            (v, _) => v,
+        },
         Expr::Ident(ident) => {
-            let (_, cell) = bs
-                .find(|(name, _)| name == ident.as_ref())
-                .unwrap_or_else(|| unreachable!("Ident did not exist"));
+            // Variables bound in match arms shadow the columns
+            if let Some((_, value, _)) = env
+                .locals
+                .iter()
+                .rev()
+                .find(|(name, _, _)| name == ident.as_ref())
+            {
+                return value.clone();
+            }
+
+            read_column((None, ident.value), bs, env)
+        }
+        Expr::Match(box value, arms) => {
+            let value_type = type_of(value, bs.clone(), env, &mut vec![]);
+            let value = execute_expr_in(value, bs.clone(), env);
+
+            let mut bindings = vec![];
+            for (pattern, arm) in arms {
+                if match_value(pattern, value.clone(), &mut bindings) {
+                    let mut bound = vec![];
+                    bind_types(pattern, value_type, env.types, &mut bound);
+
+                    let mut arm_env = env.clone();
+                    arm_env.locals.extend(
+                        bindings
+                            .into_iter()
+                            .zip(bound)
+                            .map(|((name, value), (_, type_id))| (name, value, type_id)),
+                    );
+                    return execute_expr_in(arm, bs, &arm_env);
+                }
+                bindings.clear();
+            }
+
+            unreachable!("No matching arm in Expr::Match: {:?}", value)
+        }
+        Expr::Tag(box value) => {
+            let variant = match value.as_ref() {
+                // The tag of a column is read from the row, without deserializing the members
+                Expr::Ident(ident)
+                    if env.locals.iter().all(|(name, _, _)| name != ident.as_ref())
+                        && bs.clone().any(|(_, name, _)| name == ident.value) =>
+                {
+                    find_cell((None, ident.value), bs)
+                        .variant_name()
+                        .to_string()
+                }
+                _ => match execute_expr_in(value, bs, env) {
+                    Value::Sum(_, variant, _) => variant.into_owned(),
+                    v => unreachable!("Non-sum expression in Expr::Tag: {:?}", v),
+                },
+            };
+            Value::Str(Cow::Owned(variant))
+        }
+        Expr::Aggregate(_, _) => env
+            .aggregates
+            .iter()
+            .find(|(aggregate, _)| std::ptr::eq(*aggregate, expr))
+            .map(|(_, value)| value.clone())
+            .unwrap_or_else(|| unreachable!("Aggregate function was not evaluated")),
+        Expr::In(box value, box select) => {
+            let value = execute_expr_in(value, bs.clone(), env);
+            let env = env.subquery(bs);
+            let rows = execute_select(select, None, &env);
+            let found = rows.iter(env.types).any(|mut row| {
+                let (_, _, cell) = row.next().expect("Subquery selected no values");
+                read_cell(cell).equals(&value, env.types)
+            });
+            Value::Bool(found)
+        }
+        Expr::Exists(box select) => {
+            let env = env.subquery(bs);
+            let rows = execute_select(select, None, &env);
+            let found = rows.iter(env.types).next().is_some();
+            Value::Bool(found)
+        }
+        Expr::Subquery(box select) => {
+            let env = env.subquery(bs);
+            let rows = execute_select(select, None, &env);
+            let mut iter = rows.iter(env.types);
+            let first = iter.next().map(|mut row| {
+                let (_, _, cell) = row.next().expect("Subquery selected no values");
+                read_cell(cell)
+            });
+            if iter.next().is_some() {
+                let span = select.items.first().and_then(|item| item.span);
+                env.fail(ExecError::MultipleRows { span });
+            }
 
-            let t: &Type = cell.type_map.get_by_id(cell.type_id());
+            // The value is optional unless the subquery always selects a row, like in the
+            // typechecker
+            match first {
+                Some(value) if typechecker::is_single_row(select) => value,
+                Some(value) => Value::Sum(None, Cow::Borrowed(OPTIONAL_SOME), vec![value]),
+                None => Value::Sum(None, Cow::Borrowed(OPTIONAL_NONE), vec![]),
+            }
+        }
+    }
+}
+
+/// Divide integers, where dividing by zero gives None and `i32::MIN / -1` wraps around
+fn integer_div(a: i32, b: i32) -> Option<i32> {
+    if b == 0 {
+        None
+    } else {
+        Some(a.wrapping_div(b))
+    }
+}
+
+/// The remainder of dividing integers, which is None when dividing by zero
+fn integer_rem(a: i32, b: i32) -> Option<i32> {
+    if b == 0 {
+        None
+    } else {
+        Some(a.wrapping_rem(b))
+    }
+}
 
-            t.from_bytes(&cell.data, cell.type_map)
-                .expect("Deserializing cell failed")
+/// Find the type of an expression, if it can be found without typechecking it again
+///
+/// Values of sum-types don't know their types, which they need to be ordered. The variables in
+/// `bound` are bound by the patterns of enclosing match arms, and shadow the locals of the env.
+fn type_of<'a, 'e, I>(
+    expr: &'e Expr<'e>,
+    bs: I,
+    env: &Env<'e>,
+    bound: &mut Vec<(&'e str, Option<TypeId>)>,
+) -> Option<TypeId>
+where
+    I: Iterator<Item = (Option<&'a str>, &'a str, Cell<'a, 'a>)> + Clone,
+{
+    match expr {
+        Expr::Ident(ident) => {
+            let local = bound
+                .iter()
+                .rev()
+                .map(|(name, type_id)| (*name, *type_id))
+                .chain(
+                    env.locals
+                        .iter()
+                        .rev()
+                        .map(|(name, _, type_id)| (*name, *type_id)),
+                )
+                .find(|(name, _)| name == ident.as_ref());
+            match local {
+                Some((_, type_id)) => type_id,
+                None => type_of_column((None, ident.value), bs, env),
+            }
+        }
+        Expr::Field(
+            box Spanned {
+                value: Expr::Ident(table),
+                ..
+            },
+            field,
+        ) if bound.iter().all(|(name, _)| name != table.as_ref())
+            && !is_defined(table, bs.clone(), env) =>
+        {
+            type_of_column((Some(table.value), field.value), bs, env)
+        }
+        Expr::Value(value) => base_type_of(value, env.types),
+        Expr::Sum(Some(namespace), _, _) if env.types.get_template(namespace).is_none() => {
+            env.types.get_id(namespace)
+        }
+        Expr::Sum(namespace, variant, args) => {
+            // The sum-type with the constructor, like the typechecker infers it
+            let arg_types: Vec<_> = args
+                .iter()
+                .map(|arg| type_of(arg, bs.clone(), env, bound))
+                .collect();
+            let mut candidates =
+                env.types
+                    .constructors_of(variant)?
+                    .iter()
+                    .filter(|&&type_id| {
+                        namespace.is_none() || env.types.template_of(type_id) == *namespace
+                    })
+                    .filter(|&type_id| match &env.types[type_id] {
+                        Type::Sum(variants) => variants.iter().any(|(name, members)| {
+                            name == variant
+                                && members.len() == arg_types.len()
+                                && members.iter().zip(&arg_types).all(|(member, arg)| {
+                                    arg.map(|arg| arg == *member).unwrap_or(true)
+                                })
+                        }),
+                        _ => false,
+                    });
+            match (candidates.next(), candidates.next()) {
+                (Some(&type_id), None) => Some(type_id),
+                _ => None,
+            }
+        }
+        Expr::Field(box record, field) => {
+            match type_of(record, bs, env, bound).map(|type_id| &env.types[&type_id]) {
+                Some(Type::Product(fields)) => fields
+                    .iter()
+                    .find(|(name, _)| name == field.as_ref())
+                    .map(|(_, type_id)| *type_id),
+                _ => None,
+            }
+        }
+        Expr::Match(box value, arms) => {
+            let value_type = type_of(value, bs.clone(), env, bound);
+            arms.iter().find_map(|(pattern, arm)| {
+                let scope = bound.len();
+                bind_types(pattern, value_type, env.types, bound);
+                let arm_type = type_of(arm, bs.clone(), env, bound);
+                bound.truncate(scope);
+                arm_type
+            })
+        }
+        _ => None,
+    }
+}
+
+/// The type of a value of a base type
+fn base_type_of(value: &Value, types: &TypeMap) -> Option<TypeId> {
+    let base_type = match value {
+        Value::Bool(_) => BaseType::Bool,
+        Value::Integer(_) => BaseType::Integer,
+        Value::Double(_) => BaseType::Double,
+        Value::Char(_) => BaseType::Char,
+        Value::Str(_) => BaseType::String,
+        _ => return None,
+    };
+    Some(types.get_base_id(base_type))
+}
+
+/// Find the types of the variables bound by a pattern, in the order [match_value] binds them
+fn bind_types<'e>(
+    pattern: &Pattern<'e>,
+    type_id: Option<TypeId>,
+    types: &TypeMap,
+    bound: &mut Vec<(&'e str, Option<TypeId>)>,
+) {
+    let t = type_id.map(|type_id| &types[&type_id]);
+    match pattern {
+        Pattern::Binding(name) => bound.push((name, type_id)),
+        Pattern::Variant {
+            name, sub_patterns, ..
+        } => {
+            let members = match t {
+                Some(Type::Sum(variants)) => variants
+                    .iter()
+                    .find(|(variant, _)| variant == name.value)
+                    .map(|(_, members)| &members[..]),
+                _ => None,
+            };
+            for (i, p) in sub_patterns.iter().enumerate() {
+                let member = members.and_then(|members| members.get(i).copied());
+                bind_types(p, member, types, bound);
+            }
+        }
+        Pattern::Record { fields } => {
+            for (field, p) in fields {
+                let field_type = match t {
+                    Some(Type::Product(field_types)) => field_types
+                        .iter()
+                        .find(|(name, _)| name == field.value)
+                        .map(|(_, type_id)| *type_id),
+                    _ => None,
+                };
+                bind_types(p, field_type, types, bound);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// A column referred to by an expression, optionally qualified by the name of its table
+type ColumnRef<'a> = (Option<&'a str>, &'a str);
+
+/// The column which an expression refers to, if it is an identifier or a qualified column
+///
+/// Like in the typechecker, a field of an identifier is only a qualified column, e.g. `u.id`,
+/// if the identifier is not defined.
+fn column_ref<'a>(expr: &Expr<'a>, is_defined: impl Fn(&str) -> bool) -> Option<ColumnRef<'a>> {
+    match expr {
+        Expr::Ident(name) => Some((None, name.value)),
+        Expr::Field(
+            box Spanned {
+                value: Expr::Ident(table),
+                ..
+            },
+            field,
+        ) if !is_defined(table.value) => Some((Some(table.value), field.value)),
+        _ => None,
+    }
+}
+
+/// Check whether a column reference refers to the cell with a name, from a table
+fn column_matches((qualifier, column): ColumnRef, table: Option<&str>, name: &str) -> bool {
+    column == name && (qualifier.is_none() || qualifier == table)
+}
+
+/// Check whether an identifier is a variable of a match arm or a column of the row, or of an
+/// enclosing query
+fn is_defined<'a, I>(ident: &str, mut bs: I, env: &Env) -> bool
+where
+    I: Iterator<Item = (Option<&'a str>, &'a str, Cell<'a, 'a>)>,
+{
+    env.locals.iter().any(|(name, _, _)| *name == ident)
+        || bs.any(|(_, name, _)| name == ident)
+        || env.outer.iter().any(|(_, name, _, _)| *name == ident)
+}
+
+/// Find the cell bound to a column
+fn find_cell<'a, I>(column: ColumnRef, mut bs: I) -> Cell<'a, 'a>
+where
+    I: Iterator<Item = (Option<&'a str>, &'a str, Cell<'a, 'a>)>,
+{
+    let (_, _, cell) = bs
+        .find(|&(table, name, _)| column_matches(column, table, name))
+        .unwrap_or_else(|| unreachable!("Ident did not exist"));
+    cell
+}
+
+/// Read the value of a column of the row, or of an enclosing query if the row doesn't have it
+fn read_column<'a, I>(column: ColumnRef, mut bs: I, env: &Env) -> Value<'static>
+where
+    I: Iterator<Item = (Option<&'a str>, &'a str, Cell<'a, 'a>)>,
+{
+    if let Some((_, _, cell)) = bs.find(|&(table, name, _)| column_matches(column, table, name)) {
+        return read_cell(cell);
+    }
+    env.outer
+        .iter()
+        .find(|&&(table, name, _, _)| column_matches(column, table, name))
+        .map(|(_, _, value, _)| value.clone())
+        .unwrap_or_else(|| unreachable!("Ident did not exist"))
+}
+
+/// Find the type of a column of the row, or of an enclosing query if the row doesn't have it
+fn type_of_column<'a, I>(column: ColumnRef, mut bs: I, env: &Env) -> Option<TypeId>
+where
+    I: Iterator<Item = (Option<&'a str>, &'a str, Cell<'a, 'a>)>,
+{
+    match bs.find(|&(table, name, _)| column_matches(column, table, name)) {
+        Some((_, _, cell)) => Some(cell.type_id()),
+        None => env
+            .outer
+            .iter()
+            .find(|&&(table, name, _, _)| column_matches(column, table, name))
+            .and_then(|&(_, _, _, type_id)| type_id),
+    }
+}
+
+/// Read the value of a cell
+fn read_cell(cell: Cell) -> Value<'static> {
+    let t: &Type = cell.type_map.get_by_id(cell.type_id());
+    t.from_bytes(&cell.data, cell.heap, cell.type_map)
+        .expect("Deserializing cell failed")
+}
+
+/// Match a value against a pattern, binding the variables in the pattern to parts of the value
+fn match_value<'e>(
+    pattern: &Pattern<'e>,
+    value: Value<'static>,
+    bindings: &mut Vec<(&'e str, Value<'static>)>,
+) -> bool {
+    match (pattern, &value) {
+        (Pattern::Ignore, _) => true,
+        (Pattern::Binding(name), _) => {
+            bindings.push((name, value));
+            true
+        }
+        (Pattern::Char(c), Value::Char(v)) => c == v,
+        (Pattern::Int(i), Value::Integer(v)) => i == v,
+        (Pattern::Bool(b), Value::Bool(v)) => b == v,
+        // Doubles are compared by value, like by `=`
+        (Pattern::Double(d), Value::Double(v)) => compare_doubles(*d, *v) == Ordering::Equal,
+        (Pattern::Str(s), Value::Str(v)) => s == v,
+        (
+            Pattern::Variant {
+                name, sub_patterns, ..
+            },
+            Value::Sum(_, variant, values),
+        ) => {
+            name.value == variant
+                && sub_patterns
+                    .iter()
+                    .zip(values)
+                    .all(|(p, v)| match_value(p, v.clone(), bindings))
         }
+        (Pattern::Record { fields }, Value::Product(values)) => fields.iter().all(|(name, p)| {
+            let (_, v) = values
+                .iter()
+                .find(|(field, _)| field == name.as_ref())
+                .unwrap_or_else(|| unreachable!("Field did not exist"));
+            match_value(p, v.clone(), bindings)
+        }),
+        (pattern, value) => unreachable!("Pattern {:?} can't match value {:?}", pattern, value),
     }
 }
@@ -18,6 +18,7 @@ pub mod client;
 mod error_message;
 pub mod executor;
 pub mod grammar;
+mod pattern_analysis;
 mod persistence;
 mod pre_typechecker;
 pub mod state;
@@ -0,0 +1,575 @@
+//! Exhaustiveness and redundancy analysis of patterns.
+//!
+//! Patterns are lowered to a simpler representation where every pattern is either a wildcard or
+//! a constructor applied to sub-patterns, and then checked using the "usefulness" algorithm
+//! described in "Warnings for pattern matching" by Luc Maranget.
+
+use crate::ast::{Pattern, Span, Spanned};
+use crate::types::{Type, TypeId, TypeMap};
+
+#[derive(Debug)]
+pub enum PatternWarning {
+    /// The arm is never chosen, since the arms before it match everything it matches
+    Redundant { span: Option<Span> },
+
+    /// The pattern can never match, since an earlier pattern on the same column excludes it
+    NeverMatches { span: Option<Span> },
+
+    /// The pattern matches everything which the patterns before it match
+    AlwaysMatches { span: Option<Span> },
+
+    /// Some values are not matched by any of the patterns
+    NonExhaustive {
+        span: Option<Span>,
+        missing: Vec<String>,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Pat {
+    Wild,
+    Ctor(Ctor, Vec<Pat>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Ctor {
+    /// A variant of a sum-type, given by its tag
+    Variant(usize),
+
+    /// The only constructor of a record, with the fields in the order they were declared in
+    Record,
+    Bool(bool),
+
+    /// Literals of types with too many values to enumerate
+    Int(i32),
+    Char(char),
+    Double(u64),
+    Str(String),
+}
+
+/// Find patterns which are redundant given the patterns before them,
+/// and the values which none of the patterns match.
+///
+/// Used for the arms of a match, where the first matching pattern is chosen.
+pub fn check_arms(
+    arms: &[&Spanned<Pattern>],
+    span: Option<Span>,
+    type_id: TypeId,
+    types: &TypeMap,
+) -> Vec<PatternWarning> {
+    let mut warnings = vec![];
+    let mut rows: Vec<Vec<Pat>> = vec![];
+
+    for arm in arms {
+        let pat = lower(arm, type_id, types);
+        if !is_useful(&rows, &[pat.clone()], &[type_id], types) {
+            warnings.push(PatternWarning::Redundant { span: arm.span });
+        }
+        rows.push(vec![pat]);
+    }
+
+    let missing = missing_patterns(&rows, type_id, types);
+    if !missing.is_empty() {
+        warnings.push(PatternWarning::NonExhaustive { span, missing });
+    }
+
+    warnings
+}
+
+/// Find patterns which can never match, or which never filter anything,
+/// given the patterns before them.
+///
+/// Used for patterns on the same column in a where-clause, which must all match.
+pub fn check_conjunction(
+    patterns: &[&Spanned<Pattern>],
+    type_id: TypeId,
+    types: &TypeMap,
+) -> Vec<PatternWarning> {
+    let mut warnings = vec![];
+
+    // The values matched by all patterns so far
+    let mut matched = Some(Pat::Wild);
+
+    for (i, pattern) in patterns.iter().enumerate() {
+        let pat = lower(pattern, type_id, types);
+        match &matched {
+            Some(m) => {
+                // A lone pattern which matches everything, e.g. `x: _`, is not worth a warning
+                if i > 0
+                    && !has_bindings(pattern)
+                    && !is_useful(&[vec![pat.clone()]], &[m.clone()], &[type_id], types)
+                {
+                    warnings.push(PatternWarning::AlwaysMatches { span: pattern.span });
+                }
+                matched = intersect(m, &pat);
+                if matched.is_none() {
+                    warnings.push(PatternWarning::NeverMatches { span: pattern.span });
+                }
+            }
+
+            // Only report the first pattern which makes the conjunction impossible
+            None => {}
+        }
+    }
+
+    warnings
+}
+
+/// Convert a pattern of a given type to the representation used by the analysis
+fn lower(pattern: &Pattern, type_id: TypeId, types: &TypeMap) -> Pat {
+    match pattern {
+        Pattern::Ignore | Pattern::Binding(_) => Pat::Wild,
+        Pattern::Bool(b) => Pat::Ctor(Ctor::Bool(*b), vec![]),
+        Pattern::Int(i) => Pat::Ctor(Ctor::Int(*i), vec![]),
+        Pattern::Char(c) => Pat::Ctor(Ctor::Char(*c), vec![]),
+        Pattern::Double(d) => Pat::Ctor(Ctor::Double(d.to_bits()), vec![]),
+        Pattern::Str(s) => Pat::Ctor(Ctor::Str(s.to_string()), vec![]),
+        Pattern::Variant {
+            name, sub_patterns, ..
+        } => match &types[&type_id] {
+            Type::Sum(variants) => {
+                let (tag, (_, members)) = variants
+                    .iter()
+                    .enumerate()
+                    .find(|(_, (variant, _))| variant == name.as_ref())
+                    .expect("Constructor does not exist");
+                let sub_pats = sub_patterns
+                    .iter()
+                    .zip(members.iter())
+                    .map(|(p, t)| lower(p, *t, types))
+                    .collect();
+                Pat::Ctor(Ctor::Variant(tag), sub_pats)
+            }
+            _ => panic!("not a sum-type"),
+        },
+        Pattern::Record { fields } => match &types[&type_id] {
+            Type::Product(field_types) => {
+                // Fields which are left out are matched by wildcards
+                let sub_pats = field_types
+                    .iter()
+                    .map(|(field, t)| {
+                        fields
+                            .iter()
+                            .find(|(name, _)| name.as_ref() == field)
+                            .map(|(_, p)| lower(p, *t, types))
+                            .unwrap_or(Pat::Wild)
+                    })
+                    .collect();
+                Pat::Ctor(Ctor::Record, sub_pats)
+            }
+            _ => panic!("not a product-type"),
+        },
+    }
+}
+
+fn has_bindings(pattern: &Pattern) -> bool {
+    match pattern {
+        Pattern::Binding(_) => true,
+        Pattern::Variant { sub_patterns, .. } => sub_patterns.iter().any(|p| has_bindings(p)),
+        Pattern::Record { fields } => fields.iter().any(|(_, p)| has_bindings(p)),
+        _ => false,
+    }
+}
+
+/// Get the pattern which matches exactly the values matched by both patterns, if any
+fn intersect(p: &Pat, q: &Pat) -> Option<Pat> {
+    match (p, q) {
+        (Pat::Wild, other) | (other, Pat::Wild) => Some(other.clone()),
+        (Pat::Ctor(c1, args1), Pat::Ctor(c2, args2)) => {
+            if c1 != c2 {
+                return None;
+            }
+            let args = args1
+                .iter()
+                .zip(args2.iter())
+                .map(|(a1, a2)| intersect(a1, a2))
+                .collect::<Option<_>>()?;
+            Some(Pat::Ctor(c1.clone(), args))
+        }
+    }
+}
+
+/// Get all constructors of a type, or None if there are too many to enumerate
+fn all_ctors(type_id: TypeId, types: &TypeMap) -> Option<Vec<Ctor>> {
+    match &types[&type_id] {
+        Type::Sum(variants) => Some((0..variants.len()).map(Ctor::Variant).collect()),
+        Type::Product(_) => Some(vec![Ctor::Record]),
+        Type::Bool => Some(vec![Ctor::Bool(false), Ctor::Bool(true)]),
+        Type::Integer | Type::Char | Type::Double | Type::String => None,
+    }
+}
+
+/// Get the types of the sub-patterns of a constructor
+fn ctor_types(ctor: &Ctor, type_id: TypeId, types: &TypeMap) -> Vec<TypeId> {
+    match (ctor, &types[&type_id]) {
+        (Ctor::Variant(tag), Type::Sum(variants)) => variants[*tag].1.clone(),
+        (Ctor::Record, Type::Product(fields)) => fields.iter().map(|(_, t)| *t).collect(),
+        _ => vec![],
+    }
+}
+
+/// Get the distinct constructors which the first column of the rows start with
+fn head_ctors(rows: &[Vec<Pat>]) -> Vec<&Ctor> {
+    let mut heads: Vec<&Ctor> = vec![];
+    for row in rows {
+        if let Pat::Ctor(ctor, _) = &row[0] {
+            if !heads.contains(&ctor) {
+                heads.push(ctor);
+            }
+        }
+    }
+    heads
+}
+
+/// Check whether the constructors in the first column cover every value of the type
+fn is_complete(heads: &[&Ctor], all: &Option<Vec<Ctor>>) -> bool {
+    match all {
+        Some(all) => !heads.is_empty() && all.iter().all(|c| heads.contains(&c)),
+        None => false,
+    }
+}
+
+/// Keep the rows which may start with a constructor, replacing it with its sub-patterns
+fn specialize(rows: &[Vec<Pat>], ctor: &Ctor, arity: usize) -> Vec<Vec<Pat>> {
+    rows.iter()
+        .filter_map(|row| match &row[0] {
+            Pat::Ctor(c, args) if c == ctor => {
+                Some(args.iter().chain(&row[1..]).cloned().collect())
+            }
+            Pat::Ctor(_, _) => None,
+            Pat::Wild => Some(
+                std::iter::repeat(Pat::Wild)
+                    .take(arity)
+                    .chain(row[1..].iter().cloned())
+                    .collect(),
+            ),
+        })
+        .collect()
+}
+
+/// Keep the rows which start with a wildcard, without the first column
+fn default_rows(rows: &[Vec<Pat>]) -> Vec<Vec<Pat>> {
+    rows.iter()
+        .filter(|row| row[0] == Pat::Wild)
+        .map(|row| row[1..].to_vec())
+        .collect()
+}
+
+/// Check whether there is a value which matches `pats`, but none of the rows
+fn is_useful(rows: &[Vec<Pat>], pats: &[Pat], type_ids: &[TypeId], types: &TypeMap) -> bool {
+    let (type_id, rest_types) = match type_ids.split_first() {
+        Some(split) => split,
+        None => return rows.is_empty(),
+    };
+
+    let sub_types = |ctor: &Ctor| -> Vec<TypeId> {
+        let mut sub_types = ctor_types(ctor, *type_id, types);
+        sub_types.extend_from_slice(rest_types);
+        sub_types
+    };
+
+    match &pats[0] {
+        Pat::Ctor(ctor, args) => {
+            let pats: Vec<Pat> = args.iter().chain(&pats[1..]).cloned().collect();
+            let rows = specialize(rows, ctor, args.len());
+            is_useful(&rows, &pats, &sub_types(ctor), types)
+        }
+        Pat::Wild => {
+            let heads = head_ctors(rows);
+            let all = all_ctors(*type_id, types);
+            if is_complete(&heads, &all) {
+                all.unwrap().iter().any(|ctor| {
+                    let sub_types = sub_types(ctor);
+                    let arity = sub_types.len() - rest_types.len();
+                    let pats: Vec<Pat> = std::iter::repeat(Pat::Wild)
+                        .take(arity)
+                        .chain(pats[1..].iter().cloned())
+                        .collect();
+                    is_useful(&specialize(rows, ctor, arity), &pats, &sub_types, types)
+                })
+            } else {
+                is_useful(&default_rows(rows), &pats[1..], rest_types, types)
+            }
+        }
+    }
+}
+
+/// Find values which none of the rows match, as patterns
+fn witness(rows: &[Vec<Pat>], type_ids: &[TypeId], types: &TypeMap) -> Option<Vec<Pat>> {
+    let (type_id, rest_types) = match type_ids.split_first() {
+        Some(split) => split,
+        None if rows.is_empty() => return Some(vec![]),
+        None => return None,
+    };
+
+    let heads = head_ctors(rows);
+    let all = all_ctors(*type_id, types);
+    if is_complete(&heads, &all) {
+        for ctor in all.unwrap() {
+            let mut sub_types = ctor_types(&ctor, *type_id, types);
+            let arity = sub_types.len();
+            sub_types.extend_from_slice(rest_types);
+
+            if let Some(mut pats) = witness(&specialize(rows, &ctor, arity), &sub_types, types) {
+                let rest = pats.split_off(arity);
+                let mut result = vec![Pat::Ctor(ctor, pats)];
+                result.extend(rest);
+                return Some(result);
+            }
+        }
+        None
+    } else {
+        let mut rest = witness(&default_rows(rows), rest_types, types)?;
+
+        // Prefer showing a constructor which is missing over a wildcard
+        let head = match all {
+            Some(all) if !heads.is_empty() => {
+                let ctor = all.into_iter().find(|c| !heads.contains(&c)).unwrap();
+                let arity = ctor_types(&ctor, *type_id, types).len();
+                Pat::Ctor(ctor, vec![Pat::Wild; arity])
+            }
+            _ => Pat::Wild,
+        };
+        rest.insert(0, head);
+        Some(rest)
+    }
+}
+
+/// List the values which none of the rows match, one for each missing constructor if possible
+fn missing_patterns(rows: &[Vec<Pat>], type_id: TypeId, types: &TypeMap) -> Vec<String> {
+    match all_ctors(type_id, types) {
+        Some(all) => all
+            .into_iter()
+            .filter_map(|ctor| {
+                let sub_types = ctor_types(&ctor, type_id, types);
+                let rows = specialize(rows, &ctor, sub_types.len());
+                witness(&rows, &sub_types, types).map(|args| Pat::Ctor(ctor, args))
+            })
+            .map(|pat| display(&pat, type_id, types))
+            .collect(),
+        None => witness(rows, &[type_id], types)
+            .map(|pats| display(&pats[0], type_id, types))
+            .into_iter()
+            .collect(),
+    }
+}
+
+/// Format a pattern using the same syntax as the query language
+fn display(pat: &Pat, type_id: TypeId, types: &TypeMap) -> String {
+    let (ctor, args) = match pat {
+        Pat::Wild => return "_".to_string(),
+        Pat::Ctor(ctor, args) => (ctor, args),
+    };
+
+    let sub_types = ctor_types(ctor, type_id, types);
+    let args: Vec<String> = args
+        .iter()
+        .zip(sub_types.iter())
+        .map(|(arg, t)| display(arg, *t, types))
+        .collect();
+
+    match (ctor, &types[&type_id]) {
+        (Ctor::Variant(tag), Type::Sum(variants)) => {
+            format!("{}({})", variants[*tag].0, args.join(", "))
+        }
+        (Ctor::Record, Type::Product(fields)) => {
+            let fields: Vec<String> = fields
+                .iter()
+                .zip(args.iter())
+                .map(|((name, _), arg)| format!("{}: {}", name, arg))
+                .collect();
+            format!("{{ {} }}", fields.join(", "))
+        }
+        (Ctor::Bool(b), _) => b.to_string(),
+        (Ctor::Int(i), _) => i.to_string(),
+        (Ctor::Char(c), _) => format!("'{}'", c),
+        (Ctor::Double(d), _) => f64::from_bits(*d).to_string(),
+        (Ctor::Str(s), _) => format!("\"{}\"", s),
+        (ctor, t) => panic!("Constructor {:?} does not belong to type {:?}", ctor, t),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grammar::PatternParser;
+    use crate::types::BaseType;
+
+    fn create_type_map() -> (TypeMap, TypeId) {
+        let mut types = TypeMap::new();
+        let int_id = types.get_base_id(BaseType::Integer);
+        let bool_id = types.get_base_id(BaseType::Bool);
+
+        let list_id = types.declare("List");
+        types.define(
+            list_id,
+            Type::Sum(vec![
+                ("Nil".into(), vec![]),
+                ("Cons".into(), vec![int_id, list_id]),
+            ]),
+        );
+        types.mark_recursive(&[list_id]);
+
+        let shape_id = types.insert(
+            "Shape",
+            Type::Sum(vec![
+                ("Circle".into(), vec![bool_id]),
+                ("Square".into(), vec![int_id]),
+                ("Line".into(), vec![list_id]),
+            ]),
+        );
+        (types, shape_id)
+    }
+
+    /// Get the indices of the redundant arms, and the missing patterns
+    fn analyse(arms: &[&str]) -> (Vec<usize>, Vec<String>) {
+        let (types, shape_id) = create_type_map();
+
+        // Use the index of each arm as its span, to find out which arm a warning is about
+        let patterns: Vec<Spanned<Pattern>> = arms
+            .iter()
+            .enumerate()
+            .map(|(i, arm)| Spanned {
+                span: Some(Span(i, i)),
+                value: PatternParser::new().parse(arm).unwrap(),
+            })
+            .collect();
+        let patterns: Vec<&Spanned<Pattern>> = patterns.iter().collect();
+
+        let mut redundant = vec![];
+        let mut missing = vec![];
+        for warning in check_arms(&patterns, None, shape_id, &types) {
+            match warning {
+                PatternWarning::Redundant {
+                    span: Some(Span(i, _)),
+                } => redundant.push(i),
+                PatternWarning::NonExhaustive { missing: m, .. } => missing = m,
+                warning => panic!("Unexpected warning: {:?}", warning),
+            }
+        }
+        (redundant, missing)
+    }
+
+    #[test]
+    fn exhaustive_arms() {
+        let examples = vec![
+            vec!["_"],
+            vec!["x"],
+            vec!["Circle(_)", "Square(_)", "Line(_)"],
+            vec!["Circle(true)", "Circle(false)", "Square(1)", "_"],
+            vec![
+                "Line(Nil())",
+                "Line(Cons(_, Nil()))",
+                "Line(Cons(_, Cons(_, _)))",
+                "_",
+            ],
+        ];
+
+        for arms in examples {
+            let (_, missing) = analyse(&arms);
+            assert!(missing.is_empty(), "{:?} is missing {:?}", arms, missing);
+        }
+    }
+
+    #[test]
+    fn non_exhaustive_arms() {
+        let examples = vec![
+            (vec![], vec!["Circle(_)", "Square(_)", "Line(_)"]),
+            (vec!["Circle(_)"], vec!["Square(_)", "Line(_)"]),
+            (
+                vec!["Circle(true)", "Square(_)", "Line(_)"],
+                vec!["Circle(false)"],
+            ),
+            (vec!["Circle(_)", "Square(1)", "Line(_)"], vec!["Square(_)"]),
+            (
+                vec!["Circle(_)", "Square(_)", "Line(Cons(_, _))"],
+                vec!["Line(Nil())"],
+            ),
+            (
+                vec![
+                    "Circle(_)",
+                    "Square(_)",
+                    "Line(Nil())",
+                    "Line(Cons(_, Nil()))",
+                ],
+                vec!["Line(Cons(_, Cons(_, _)))"],
+            ),
+        ];
+
+        for (arms, expected) in examples {
+            let (_, missing) = analyse(&arms);
+            assert_eq!(missing, expected, "for {:?}", arms);
+        }
+    }
+
+    #[test]
+    fn redundant_arms() {
+        let examples = vec![
+            (vec!["_", "Circle(_)"], vec![1]),
+            (vec!["Circle(_)", "Circle(true)", "_"], vec![1]),
+            (
+                vec!["Circle(true)", "Circle(false)", "Circle(x)", "_"],
+                vec![2],
+            ),
+            (vec!["Square(1)", "Square(1)", "Square(2)"], vec![1]),
+            (vec!["Line(_)", "Line(Cons(1, Nil()))"], vec![1]),
+            (vec!["Circle(_)", "Square(_)", "Line(_)", "_"], vec![3]),
+            (vec!["Circle(true)", "Line(Nil())", "_"], vec![]),
+        ];
+
+        for (arms, expected) in examples {
+            let (redundant, _) = analyse(&arms);
+            assert_eq!(redundant, expected, "for {:?}", arms);
+        }
+    }
+
+    #[test]
+    fn conjunctions() {
+        let (types, shape_id) = create_type_map();
+        let examples = vec![
+            (vec!["Circle(_)", "Circle(true)"], vec![], vec![]),
+            (vec!["Circle(_)", "Square(_)"], vec![], vec![1]),
+            (
+                vec!["Line(Cons(1, _))", "Line(Cons(_, Nil()))"],
+                vec![],
+                vec![],
+            ),
+            (
+                vec!["Line(Cons(1, _))", "Line(Cons(2, _))"],
+                vec![],
+                vec![1],
+            ),
+            (vec!["Circle(true)", "Circle(_)"], vec![1], vec![]),
+            (vec!["Circle(true)", "Circle(x)"], vec![], vec![]),
+            (vec!["_"], vec![], vec![]),
+            (vec!["Circle(_)", "_"], vec![1], vec![]),
+        ];
+
+        for (patterns, always, never) in examples {
+            let parsed: Vec<Spanned<Pattern>> = patterns
+                .iter()
+                .enumerate()
+                .map(|(i, p)| Spanned {
+                    span: Some(Span(i, i)),
+                    value: PatternParser::new().parse(p).unwrap(),
+                })
+                .collect();
+            let parsed: Vec<&Spanned<Pattern>> = parsed.iter().collect();
+
+            let (mut actual_always, mut actual_never) = (vec![], vec![]);
+            for warning in check_conjunction(&parsed, shape_id, &types) {
+                match warning {
+                    PatternWarning::AlwaysMatches {
+                        span: Some(Span(i, _)),
+                    } => actual_always.push(i),
+                    PatternWarning::NeverMatches {
+                        span: Some(Span(i, _)),
+                    } => actual_never.push(i),
+                    warning => panic!("Unexpected warning: {:?}", warning),
+                }
+            }
+            assert_eq!(actual_always, always, "for {:?}", patterns);
+            assert_eq!(actual_never, never, "for {:?}", patterns);
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use crate::persistence::TransactionNumber;
 use crate::state::DbData;
-use crate::table::Table;
+use crate::table::{Table, View};
 use crate::types::TypeMap;
 use std::collections::HashMap;
 use std::io;
@@ -10,7 +10,10 @@ use tokio::fs::{self, read_dir, read_to_string, remove_dir_all, remove_file};
 use tokio::stream::StreamExt;
 use tokio::sync::RwLock;
 
-use super::{DATA_DIR_FILES, TABLES_DIR_NAME, TMP_EXTENSION, TNUM_FILE_NAME, TYPE_MAP_FILE_NAME};
+use super::{
+    DATA_DIR_FILES, TABLES_DIR_NAME, TMP_EXTENSION, TNUM_FILE_NAME, TYPE_MAP_FILE_NAME,
+    VIEWS_FILE_NAME,
+};
 
 /// Load DbData from an initialized data directory
 ///
@@ -84,10 +87,12 @@ pub async fn load_db_data(data_dir: &PathBuf) -> io::Result<DbData> {
     }
 
     let type_map = read_type_map(&snapshot_dir).await?;
+    let views = read_views(&snapshot_dir).await?;
 
     Ok(DbData {
         transaction_number,
         tables,
+        views,
         type_map: Arc::new(RwLock::new(type_map)),
     })
 }
@@ -110,3 +115,17 @@ pub async fn read_type_map(snapshot_dir: &PathBuf) -> io::Result<TypeMap> {
     let binary = fs::read(snapshot_dir.join(TYPE_MAP_FILE_NAME)).await?;
     Ok(bincode::deserialize(&binary).unwrap())
 }
+
+/// Read the views of a snapshot, which may be missing if it was taken before views existed
+pub async fn read_views(snapshot_dir: &PathBuf) -> io::Result<HashMap<String, Arc<View>>> {
+    let binary = match fs::read(snapshot_dir.join(VIEWS_FILE_NAME)).await {
+        Ok(binary) => binary,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(e),
+    };
+    let views: Vec<(String, View)> = bincode::deserialize(&binary).unwrap();
+    Ok(views
+        .into_iter()
+        .map(|(name, view)| (name, Arc::new(view)))
+        .collect())
+}
@@ -1,6 +1,6 @@
 use crate::state::types::{Resource, Resources};
 use crate::state::{DbState, DbmsState};
-use crate::table::Table;
+use crate::table::{Table, View};
 use crate::types::TypeMap;
 use futures::future::join_all;
 use std::io;
@@ -12,6 +12,7 @@ use tokio::stream::StreamExt;
 
 use super::{
     TransactionNumber, TABLES_DIR_NAME, TMP_EXTENSION, TNUM_FILE_NAME, TYPE_MAP_FILE_NAME,
+    VIEWS_FILE_NAME,
 };
 
 pub async fn initialize_data_dir(data_dir: &PathBuf) -> io::Result<()> {
@@ -62,6 +63,8 @@ pub(super) async fn snapshot(
     debug!("creating {:?}", transaction_folder.join(TABLES_DIR_NAME));
     create_dir(transaction_folder.join(TABLES_DIR_NAME)).await?;
 
+    snapshot_views(&transaction_folder, &resources.views).await?;
+
     // Spawn tasks to flush the tables to disk
     let tasks: Vec<_> = resources
         .tables
@@ -113,6 +116,13 @@ async fn snapshot_type_map(folder: &PathBuf, type_map: Resource<'_, TypeMap>) ->
     flush_to_file(&file_path, &data, true).await
 }
 
+async fn snapshot_views(folder: &PathBuf, views: &[(&str, &View)]) -> io::Result<()> {
+    debug!("snapshotting views");
+    let data = bincode::serialize(views).unwrap();
+    let file_path = folder.join(VIEWS_FILE_NAME);
+    flush_to_file(&file_path, &data, true).await
+}
+
 async fn snapshot_table(
     folder: &PathBuf,
     name: &str,
@@ -5,79 +5,163 @@ pub fn get_resource_request(stmt: &Stmt) -> Acquire {
     Acquire {
         table_reqs: get_table_resource_requests(stmt),
         type_map_perms: get_type_map_resource_perm(stmt),
+
+        // The tables which use an altered type are re-encoded, and the views are checked again.
+        // Views may read from any table, so they are all locked when a table is altered as well.
+        all_tables: matches!(stmt, Stmt::AlterType(_) | Stmt::AlterTable(_)),
     }
 }
 
+/// The names of the tables and views which a select reads from
+pub fn get_read_tables(sel: &Select) -> Vec<String> {
+    let mut tables: Vec<String> = get_select(sel).into_iter().map(|req| req.table).collect();
+    tables.sort();
+    tables.dedup();
+    tables
+}
+
 fn get_type_map_resource_perm(stmt: &Stmt) -> RW {
     match stmt {
         Stmt::CreateType(_) => RW::Write,
+        Stmt::AlterType(_) => RW::Write,
+
+        // Columns may have generic types which need to be instantiated
+        Stmt::CreateTable(_) => RW::Write,
+        Stmt::AlterTable(AlterTable {
+            alteration: Alteration::AddColumn { .. },
+            ..
+        }) => RW::Write,
         _ => RW::Read,
     }
 }
 
 fn get_table_resource_requests(stmt: &Stmt) -> Vec<TableRequest> {
-    match stmt {
-        Stmt::Select(sel) => get_option_select(&sel.from),
-        Stmt::Update(upd) => vec![TableRequest {
-            table: upd.table.to_string(),
-            rw: RW::Write,
-        }],
-        Stmt::Insert(ins) => {
-            let mut req = match &ins.from {
-                InsertFrom::Values(_) => vec![],
+    let write = |table: &str| TableRequest {
+        table: table.to_string(),
+        rw: RW::Write,
+    };
 
-                InsertFrom::Select(select) => get_option_select(&select.from),
-            };
-
-            req.sort();
-            match req.binary_search_by(|r| r.table.as_str().cmp(ins.table)) {
-                Ok(i) => req[i].rw = RW::Write,
-                Err(i) => req.insert(
-                    i,
-                    TableRequest {
-                        table: ins.table.to_string(),
-                        rw: RW::Write,
-                    },
-                ),
+    let mut reqs = match stmt {
+        Stmt::Select(sel) => get_select(sel),
+        Stmt::Update(upd) => {
+            let mut reqs = vec![write(upd.table)];
+            for ass in &upd.ass {
+                reqs.extend(get_expr(&ass.expr));
             }
-
-            req
+            reqs.extend(get_where_clause(&upd.where_clause));
+            reqs
+        }
+        Stmt::Insert(ins) => {
+            let mut reqs = vec![write(ins.table)];
+            match &ins.from {
+                InsertFrom::Values(rows) => {
+                    for value in rows.iter().flat_map(|row| row.iter()) {
+                        reqs.extend(get_expr(value));
+                    }
+                }
+                InsertFrom::Select(select) => reqs.extend(get_select(select)),
+            }
+            reqs
+        }
+        Stmt::Delete(del) => {
+            let mut reqs = vec![write(del.table)];
+            reqs.extend(get_where_clause(&del.where_clause));
+            reqs
         }
-        Stmt::Delete(del) => vec![TableRequest {
-            table: del.table.to_string(),
-            rw: RW::Write,
-        }],
         Stmt::CreateType(_) => vec![],
+        Stmt::AlterType(_) => vec![],
         Stmt::CreateTable(_) => vec![],
-        Stmt::Drop(drop) => vec![TableRequest {
-            table: drop.table.to_string(),
-            rw: RW::Write,
-        }],
-    }
+        Stmt::Drop(drop) => vec![write(drop.table)],
+        Stmt::CreateView(create_view) => get_select(&create_view.select),
+
+        // Statements which are reading the view have their own copy of it
+        Stmt::DropView(_) => vec![],
+        Stmt::AlterTable(alter) => {
+            let mut reqs = vec![write(alter.table)];
+            if let Alteration::AddColumn { default, .. } = &alter.alteration {
+                reqs.extend(get_expr(default));
+            }
+            reqs
+        }
+    };
+    normalize_requests(&mut reqs);
+    reqs
 }
 
-fn get_option_select<'a>(sel: &'a Option<SelectFrom>) -> Vec<TableRequest> {
-    match sel {
-        Some(from) => get_select(&from),
+/// The tables read by a select, including the ones read by its subqueries
+fn get_select(sel: &Select) -> Vec<TableRequest> {
+    let mut reqs = match &sel.from {
+        Some(from) => get_select_from(from),
         None => vec![],
+    };
+
+    let order_by = sel.order_by.iter().map(|(expr, _)| expr);
+    for expr in sel.items.iter().chain(&sel.group_by).chain(order_by) {
+        reqs.extend(get_expr(expr));
+    }
+    reqs.extend(get_where_clause(&sel.where_clause));
+
+    for (_, operand) in &sel.set_operations {
+        reqs.extend(get_select(operand));
     }
+
+    // Common tables aren't locked, but the tables they read from are
+    if let Some(with) = &sel.with {
+        let is_common = |tables: &[CommonTable], req: &TableRequest| {
+            tables.iter().any(|table| table.name.value == req.table)
+        };
+        reqs.retain(|req| !is_common(&with.tables, req));
+
+        for (i, table) in with.tables.iter().enumerate() {
+            let visible = if with.recursive {
+                &with.tables[..=i]
+            } else {
+                &with.tables[..i]
+            };
+            let mut table_reqs = get_select(&table.select);
+            table_reqs.retain(|req| !is_common(visible, req));
+            reqs.extend(table_reqs);
+        }
+    }
+    reqs
 }
 
-fn get_select(sel: &SelectFrom) -> Vec<TableRequest> {
+fn get_select_from(sel: &SelectFrom) -> Vec<TableRequest> {
     match &sel {
-        SelectFrom::Select(nsel) => get_option_select(&nsel.from),
+        SelectFrom::Select(nsel) => get_select(&nsel),
         SelectFrom::Table(tab) => vec![TableRequest {
-            table: tab.to_string(),
+            table: tab.value.to_string(),
             rw: RW::Read,
         }],
         SelectFrom::Join(jon) => {
-            let mut vec = get_select(&jon.table_a);
-            vec.extend(get_select(&jon.table_b));
+            let mut vec = get_select_from(&jon.table_a);
+            vec.extend(get_select_from(&jon.table_b));
+            if let Some(on_clause) = &jon.on_clause {
+                vec.extend(get_expr(on_clause));
+            }
             vec
         }
+        SelectFrom::Alias(from, _) => get_select_from(from),
     }
 }
 
+fn get_where_clause(clause: &Option<WhereClause>) -> Vec<TableRequest> {
+    let items = clause.iter().flat_map(|clause| &clause.items);
+    items
+        .flat_map(|item| match item {
+            WhereItem::Expr(expr) => get_expr(expr),
+            WhereItem::Pattern(_, _) => vec![],
+        })
+        .collect()
+}
+
+/// The tables read by the subqueries in an expression
+fn get_expr(expr: &Expr) -> Vec<TableRequest> {
+    let mut subqueries = vec![];
+    expr.subqueries(&mut subqueries);
+    subqueries.into_iter().flat_map(get_select).collect()
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -105,4 +189,63 @@ mod tests {
         assert!(ex2r[2].table == "foffe" && ex2r[2].rw == RW::Read);
         assert!(ex2r[1].table == "feffe" && ex2r[1].rw == RW::Read && ex2r.len() == 3)
     }
+
+    #[test]
+    fn test_get_subquery_permissions() {
+        let parser = StmtParser::new();
+
+        let ex1 = parser
+            .parse(r#"SELECT a, (SELECT MAX(b) FROM foffe) FROM feffe WHERE a IN (SELECT c FROM faffe);"#)
+            .unwrap();
+        let ex2 = parser
+            .parse(r#"UPDATE feffe SET a = 1 WHERE EXISTS (SELECT b FROM feffe JOIN faffe);"#)
+            .unwrap();
+
+        let ex1r = get_table_resource_requests(&ex1);
+        let ex2r = get_table_resource_requests(&ex2);
+
+        let tables = |reqs: &[TableRequest]| -> Vec<(String, RW)> {
+            reqs.iter().map(|req| (req.table.clone(), req.rw)).collect()
+        };
+        assert_eq!(
+            tables(&ex1r),
+            vec![
+                ("faffe".to_string(), RW::Read),
+                ("feffe".to_string(), RW::Read),
+                ("foffe".to_string(), RW::Read),
+            ]
+        );
+        assert_eq!(
+            tables(&ex2r),
+            vec![
+                ("faffe".to_string(), RW::Read),
+                ("feffe".to_string(), RW::Write)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_get_common_table_permissions() {
+        let parser = StmtParser::new();
+
+        let ex1 = parser
+            .parse(r#"WITH a AS (SELECT x FROM feffe), b AS (SELECT x FROM a) SELECT x FROM b;"#)
+            .unwrap();
+        let ex2 = parser
+            .parse(r#"WITH foffe AS (SELECT x FROM foffe) SELECT x FROM foffe;"#)
+            .unwrap();
+        let ex3 = parser
+            .parse(r#"WITH RECURSIVE r AS (SELECT x FROM faffe UNION SELECT x FROM r) SELECT x FROM r;"#)
+            .unwrap();
+
+        let tables = |stmt: &Stmt| -> Vec<String> {
+            get_table_resource_requests(stmt)
+                .into_iter()
+                .map(|req| req.table)
+                .collect()
+        };
+        assert_eq!(tables(&ex1), vec!["feffe".to_string()]);
+        assert_eq!(tables(&ex2), vec!["foffe".to_string()]);
+        assert_eq!(tables(&ex3), vec!["faffe".to_string()]);
+    }
 }
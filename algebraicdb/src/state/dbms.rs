@@ -4,7 +4,7 @@ use crate::api::config::DbmsConfig;
 use crate::executor::execute_replay_query;
 use crate::persistence::TransactionNumber;
 use crate::persistence::{initialize_data_dir, load_db_data, spawn_snapshotter, WriteAheadLog};
-use crate::table::Table;
+use crate::table::{Table, View};
 use crate::types::TypeMap;
 use async_trait::async_trait;
 use std::collections::HashMap;
@@ -28,6 +28,9 @@ pub struct DbData {
     /// in order, sorted by their name. If not, we will have deadlocks.
     pub tables: HashMap<String, Arc<RwLock<Table>>>,
 
+    /// All views in the database, which share their names with the tables
+    pub views: HashMap<String, Arc<View>>,
+
     /// A map of all types in the db
     pub type_map: Arc<RwLock<TypeMap>>,
 }
@@ -37,6 +40,7 @@ impl Default for DbData {
         Self {
             transaction_number: 0,
             tables: HashMap::new(),
+            views: HashMap::new(),
             type_map: Arc::new(RwLock::new(TypeMap::new())),
         }
     }
@@ -47,8 +51,38 @@ impl DbState<Table> for DbmsState {
     async fn acquire_resources(&self, acquire: Acquire) -> Result<Resources<Table>, String> {
         let state = self.state.lock().await;
         let type_map = state.type_map.clone();
-        let resources: Result<Vec<_>, _> = acquire
-            .table_reqs
+
+        let mut requested = acquire.table_reqs;
+        if acquire.all_tables {
+            let write = |table: &String| TableRequest {
+                table: table.clone(),
+                rw: RW::Write,
+            };
+            let read = |view: &String| TableRequest {
+                table: view.clone(),
+                rw: RW::Read,
+            };
+            requested.extend(state.tables.keys().map(write));
+
+            // The views are sorted, so that they are always checked in the same order
+            let mut views: Vec<&String> = state.views.keys().collect();
+            views.sort();
+            requested.extend(views.into_iter().map(read));
+        }
+
+        // Views are replaced by the tables they read from, which are locked along with the rest
+        let mut table_reqs = vec![];
+        let mut views = vec![];
+        for req in requested {
+            if state.views.contains_key(&req.table) && req.rw == RW::Read {
+                state.expand_view(&req.table, &mut table_reqs, &mut views);
+            } else {
+                table_reqs.push(req);
+            }
+        }
+        normalize_requests(&mut table_reqs);
+
+        let resources: Result<Vec<_>, _> = table_reqs
             .into_iter()
             .map(|req| {
                 if let Some(lock) = state.tables.get(&req.table) {
@@ -61,7 +95,12 @@ impl DbState<Table> for DbmsState {
             .collect();
 
         match resources {
-            Ok(tables) => Ok(Resources::new(type_map, acquire.type_map_perms, tables)),
+            Ok(tables) => Ok(Resources::new(
+                type_map,
+                acquire.type_map_perms,
+                tables,
+                views,
+            )),
             Err(err) => Err(err.to_string()),
         }
     }
@@ -79,12 +118,18 @@ impl DbState<Table> for DbmsState {
 
         tables.sort_by(|(_, name_a, _), (_, name_b, _)| name_a.cmp(name_b));
 
-        Resources::new(type_map, RW::Read, tables)
+        let mut views = vec![];
+        let mut table_reqs = vec![];
+        for name in state.views.keys() {
+            state.expand_view(name, &mut table_reqs, &mut views);
+        }
+
+        Resources::new(type_map, RW::Read, tables, views)
     }
 
     async fn create_table(&self, name: String, table: Table) -> Result<(), ()> {
         let mut state = self.state.lock().await;
-        if state.tables.contains_key(&name) {
+        if state.tables.contains_key(&name) || state.views.contains_key(&name) {
             Err(())
         } else {
             state
@@ -94,9 +139,73 @@ impl DbState<Table> for DbmsState {
         }
     }
 
-    async fn drop_table(&self, name: &str) -> Result<(), ()> {
+    async fn drop_table(&self, name: &str) -> Result<(), DropError> {
+        let mut state = self.state.lock().await;
+        if !state.tables.contains_key(name) {
+            return Err(DropError::NoSuchName);
+        }
+        if let Some(view) = state.view_reading(name) {
+            return Err(DropError::ReadByView(view.clone()));
+        }
+        state.tables.remove(name);
+        Ok(())
+    }
+
+    async fn create_view(&self, name: String, view: View) -> Result<(), ()> {
+        let mut state = self.state.lock().await;
+        if state.tables.contains_key(&name) || state.views.contains_key(&name) {
+            Err(())
+        } else {
+            state.views.insert(name, Arc::new(view));
+            Ok(())
+        }
+    }
+
+    async fn drop_view(&self, name: &str) -> Result<(), DropError> {
         let mut state = self.state.lock().await;
-        state.tables.remove(name).map(|_| ()).ok_or(())
+        if !state.views.contains_key(name) {
+            return Err(DropError::NoSuchName);
+        }
+        if let Some(view) = state.view_reading(name) {
+            return Err(DropError::ReadByView(view.clone()));
+        }
+        state.views.remove(name);
+        Ok(())
+    }
+}
+
+impl DbData {
+    /// Add a view to `views` after the views it reads from, and request the tables it reads from
+    fn expand_view(
+        &self,
+        name: &str,
+        table_reqs: &mut Vec<TableRequest>,
+        views: &mut Vec<(String, Arc<View>)>,
+    ) {
+        if views.iter().any(|(view, _)| view == name) {
+            return;
+        }
+
+        let view = &self.views[name];
+        for table in &view.reads {
+            if self.views.contains_key(table) {
+                self.expand_view(table, table_reqs, views);
+            } else {
+                table_reqs.push(TableRequest {
+                    table: table.clone(),
+                    rw: RW::Read,
+                });
+            }
+        }
+        views.push((name.to_string(), view.clone()));
+    }
+
+    /// Get the name of a view which reads from the table or view with the name
+    fn view_reading(&self, name: &str) -> Option<&String> {
+        self.views
+            .iter()
+            .find(|(_, view)| view.reads.iter().any(|table| table == name))
+            .map(|(view, _)| view)
     }
 }
 
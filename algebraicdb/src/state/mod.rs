@@ -6,7 +6,7 @@ mod tests;
 
 pub use self::dbms::*;
 pub use self::types::*;
-use crate::table::Schema;
+use crate::table::{Schema, View};
 use async_trait::async_trait;
 
 pub trait TTable {
@@ -21,5 +21,7 @@ where
     async fn acquire_resources(&self, acquire: Acquire) -> Result<Resources<T>, String>;
     async fn acquire_all_resources(&self) -> Resources<T>;
     async fn create_table(&self, name: String, table: T) -> Result<(), ()>;
-    async fn drop_table(&self, name: &str) -> Result<(), ()>;
+    async fn drop_table(&self, name: &str) -> Result<(), DropError>;
+    async fn create_view(&self, name: String, view: View) -> Result<(), ()>;
+    async fn drop_view(&self, name: &str) -> Result<(), DropError>;
 }
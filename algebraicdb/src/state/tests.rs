@@ -60,6 +60,7 @@ async fn global_resources_contention() {
                     let request = Acquire {
                         table_reqs,
                         type_map_perms: RW::Read,
+                        all_tables: false,
                     };
 
                     //eprintln!("{} {} {} {:?}", "==".color(Color::Red), thread, "requesting".color(Color::Red), request);
@@ -1,3 +1,4 @@
+use crate::table::View;
 use crate::types::TypeMap;
 use std::ops::{Deref, DerefMut};
 use std::sync::Arc;
@@ -19,6 +20,10 @@ pub struct TableRequest {
 pub struct Acquire {
     pub table_reqs: Vec<TableRequest>,
     pub type_map_perms: RW,
+
+    /// Whether every table is locked for writing, along with every view, e.g. when a type
+    /// which they may use is changed
+    pub all_tables: bool,
 }
 
 #[derive(Debug)]
@@ -27,16 +32,29 @@ pub enum CreateTableResponse {
     TableAlreadyExists,
 }
 
+#[derive(Debug)]
+pub enum DropError {
+    /// There is nothing to drop with the name
+    NoSuchName,
+
+    /// A view reads from what is being dropped, with the name of the view
+    ReadByView(String),
+}
+
 pub struct Resources<T> {
     dirty: bool,
     type_map_perms: RW,
     type_map: Arc<RwLock<TypeMap>>,
     tables: Vec<(RW, String, Arc<RwLock<T>>)>,
+    views: Vec<(String, Arc<View>)>,
 }
 
 pub struct ResourcesGuard<'a, T> {
     pub type_map: Resource<'a, TypeMap>,
     pub tables: Vec<(&'a str, Resource<'a, T>)>,
+
+    /// The views which are read, where every view comes after the views it reads from
+    pub views: Vec<(&'a str, &'a View)>,
 }
 
 pub enum Resource<'a, T> {
@@ -44,17 +62,35 @@ pub enum Resource<'a, T> {
     Read(RwLockReadGuard<'a, T>),
 }
 
+/// Sort table requests, and merge the requests for the same table
+///
+/// Every table is locked once, in the same order for all statements, so that statements
+/// can't deadlock each other. A table which is both read and written is locked for writing.
+pub fn normalize_requests(reqs: &mut Vec<TableRequest>) {
+    reqs.sort();
+    reqs.dedup_by(|req, prev| {
+        if req.table == prev.table {
+            prev.rw = prev.rw.max(req.rw);
+            true
+        } else {
+            false
+        }
+    });
+}
+
 impl<T> Resources<T> {
     pub fn new(
         type_map: Arc<RwLock<TypeMap>>,
         type_map_perms: RW,
         tables: Vec<(RW, String, Arc<RwLock<T>>)>,
+        views: Vec<(String, Arc<View>)>,
     ) -> Self {
         Self {
             dirty: false,
             type_map,
             type_map_perms,
             tables,
+            views,
         }
     }
 
@@ -85,6 +121,11 @@ impl<T> Resources<T> {
                 RW::Write => Resource::Write(self.type_map.write().await),
             },
             tables,
+            views: self
+                .views
+                .iter()
+                .map(|(name, view)| (name.as_str(), view.as_ref()))
+                .collect(),
         }
     }
 }
@@ -100,6 +141,15 @@ impl<'a, T> Deref for Resource<'a, T> {
     }
 }
 
+impl<'a, T> Resource<'a, T> {
+    pub fn is_write(&self) -> bool {
+        match self {
+            Resource::Write(_) => true,
+            Resource::Read(_) => false,
+        }
+    }
+}
+
 /// Panics if Resource is read-only
 impl<'a, T> DerefMut for Resource<'a, T> {
     fn deref_mut(&mut self) -> &mut Self::Target {
@@ -1,26 +1,191 @@
-use crate::types::{EnumTag, Type, TypeId, TypeMap};
-use bincode::deserialize;
+use crate::types::{compare_doubles, EnumTag, HeapRef, Type, TypeId, TypeMap};
+use bincode::{deserialize, serialize_into};
 use std::cmp::{Ord, Ordering, PartialOrd};
 use std::fmt::{self, Display, Formatter};
+use std::str;
 
 pub struct Cell<'tb, 'ts> {
     type_id: TypeId,
     pub type_map: &'ts TypeMap,
     pub data: &'tb [u8],
+
+    /// The heap of the table which the cell belongs to
+    pub heap: &'tb [u8],
 }
 
 impl<'tb, 'ts> Cell<'tb, 'ts> {
-    pub fn new(type_id: TypeId, data: &'tb [u8], type_map: &'ts TypeMap) -> Self {
+    pub fn new(type_id: TypeId, data: &'tb [u8], heap: &'tb [u8], type_map: &'ts TypeMap) -> Self {
         Cell {
             type_id,
             type_map,
             data,
+            heap,
         }
     }
 
     pub fn type_id(&self) -> TypeId {
         self.type_id
     }
+
+    /// Get the string which a cell of type String refers to
+    pub fn as_str(&self) -> &'tb str {
+        let (offset, len): HeapRef = deserialize(self.data).unwrap();
+        str::from_utf8(&self.heap[offset..offset + len]).unwrap()
+    }
+
+    /// Create a cell for a member of a sum-type, following the heap reference if it is boxed
+    fn member(&self, type_id: TypeId, data: &'tb [u8]) -> Cell<'tb, 'ts> {
+        let data = if self.type_map.is_boxed(type_id) {
+            let (offset, len): HeapRef = deserialize(data).unwrap();
+            &self.heap[offset..offset + len]
+        } else {
+            data
+        };
+        Cell::new(type_id, data, self.heap, self.type_map)
+    }
+
+    /// Get the enum tag of a cell of a sum-type
+    pub fn tag(&self) -> EnumTag {
+        let tag_size = std::mem::size_of::<EnumTag>();
+        deserialize(&self.data[..tag_size]).unwrap()
+    }
+
+    /// Get the name of the constructor of a cell of a sum-type, without reading its members
+    pub fn variant_name(&self) -> &'ts str {
+        if let Type::Sum(variants) = &self.type_map[&self.type_id] {
+            let (name, _) = &variants[self.tag()];
+            name
+        } else {
+            panic!("not a sum-type")
+        }
+    }
+
+    /// Get the enum tag and the members of a cell of a sum-type
+    fn variant(&self) -> (EnumTag, Vec<Cell<'tb, 'ts>>) {
+        if let Type::Sum(variants) = &self.type_map[&self.type_id] {
+            let tag_size = std::mem::size_of::<EnumTag>();
+            let tag = self.tag();
+
+            let (_, members) = &variants[tag];
+            let mut cursor = tag_size;
+            let members = members
+                .iter()
+                .map(|&type_id| {
+                    let end = cursor + self.type_map.member_size_of(type_id);
+                    let cell = self.member(type_id, &self.data[cursor..end]);
+                    cursor = end;
+                    cell
+                })
+                .collect();
+
+            (tag, members)
+        } else {
+            panic!("not a sum-type")
+        }
+    }
+
+    /// Get the names and values of the fields of a cell of a product-type
+    pub fn fields(&self) -> Vec<(&'ts str, Cell<'tb, 'ts>)> {
+        if let Type::Product(fields) = &self.type_map[&self.type_id] {
+            let mut cursor = 0;
+            fields
+                .iter()
+                .map(|(name, type_id)| {
+                    let end = cursor + self.type_map.member_size_of(*type_id);
+                    let cell = self.member(*type_id, &self.data[cursor..end]);
+                    cursor = end;
+                    (name.as_str(), cell)
+                })
+                .collect()
+        } else {
+            panic!("not a product-type")
+        }
+    }
+
+    /// Write the cell to the data and heap of another table
+    pub fn copy_into(&self, data: &mut Vec<u8>, heap: &mut Vec<u8>) {
+        match &self.type_map[&self.type_id] {
+            Type::Integer | Type::Double | Type::Bool | Type::Char => {
+                data.extend_from_slice(self.data)
+            }
+            Type::String => {
+                let s = self.as_str();
+                let heap_ref: HeapRef = (heap.len(), s.len());
+                heap.extend_from_slice(s.as_bytes());
+                serialize_into(&mut *data, &heap_ref).unwrap();
+            }
+            Type::Sum(_) => {
+                let start = data.len();
+                let (tag, members) = self.variant();
+                serialize_into(&mut *data, &tag).unwrap();
+                for member in members {
+                    if self.type_map.is_boxed(member.type_id) {
+                        member.copy_boxed_into(data, heap);
+                    } else {
+                        member.copy_into(data, heap);
+                    }
+                }
+
+                // pad with 0:s for variants smaller than the largest variant
+                data.resize(start + self.data.len(), 0);
+            }
+            Type::Product(_) => {
+                for (_, field) in self.fields() {
+                    if self.type_map.is_boxed(field.type_id) {
+                        field.copy_boxed_into(data, heap);
+                    } else {
+                        field.copy_into(data, heap);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Format the cell as a member of another value, where strings are quoted
+    fn fmt_member(&self, f: &mut Formatter) -> fmt::Result {
+        match &self.type_map[&self.type_id] {
+            Type::String => write!(f, "{:?}", self.as_str()),
+            _ => self.fmt(f),
+        }
+    }
+
+    /// Write the cell to the heap of another table, and a reference to it to the data
+    pub fn copy_boxed_into(&self, data: &mut Vec<u8>, heap: &mut Vec<u8>) {
+        let mut bytes = Vec::with_capacity(self.data.len());
+        self.copy_into(&mut bytes, heap);
+
+        let heap_ref: HeapRef = (heap.len(), bytes.len());
+        heap.extend_from_slice(&bytes);
+        serialize_into(data, &heap_ref).unwrap();
+    }
+
+    /// Write a representation of the cell which is the same for all equal cells, e.g. for hashing
+    ///
+    /// Note that this does not hold for doubles, e.g. 0.0 and -0.0.
+    pub fn write_canonical(&self, out: &mut Vec<u8>) {
+        match &self.type_map[&self.type_id] {
+            Type::Integer | Type::Double | Type::Bool | Type::Char => {
+                out.extend_from_slice(self.data)
+            }
+            Type::String => {
+                let s = self.as_str();
+                serialize_into(&mut *out, &s.len()).unwrap();
+                out.extend_from_slice(s.as_bytes());
+            }
+            Type::Sum(_) => {
+                let (tag, members) = self.variant();
+                serialize_into(&mut *out, &tag).unwrap();
+                for member in members {
+                    member.write_canonical(out);
+                }
+            }
+            Type::Product(_) => {
+                for (_, field) in self.fields() {
+                    field.write_canonical(out);
+                }
+            }
+        }
+    }
 }
 
 impl<'tb, 'ts> Display for Cell<'tb, 'ts> {
@@ -29,9 +194,10 @@ impl<'tb, 'ts> Display for Cell<'tb, 'ts> {
         let t_size = t.size_of(self.type_map);
         match t {
             Type::Integer | Type::Double | Type::Char | Type::Bool => t
-                .from_bytes(&self.data[..t_size], self.type_map)
+                .from_bytes(&self.data[..t_size], self.heap, self.type_map)
                 .unwrap()
                 .fmt(f),
+            Type::String => write!(f, "{}", self.as_str()),
             Type::Sum(variants) => {
                 // Converting this to a Value would do heap-allocations.
                 // So we need to manually traverse the "tree"
@@ -50,16 +216,25 @@ impl<'tb, 'ts> Display for Cell<'tb, 'ts> {
                         write!(f, ", ")?;
                     }
                     first = false;
-                    let t = &self.type_map[t_id];
-                    let t_size = t.size_of(self.type_map);
-                    let end = cursor + t_size;
-                    let cell = Cell::new(*t_id, &self.data[cursor..end], self.type_map);
-                    write!(f, "{}", cell)?;
-                    cursor += t_size;
+                    let end = cursor + self.type_map.member_size_of(*t_id);
+                    let cell = self.member(*t_id, &self.data[cursor..end]);
+                    cell.fmt_member(f)?;
+                    cursor = end;
                 }
 
                 write!(f, ")")
             }
+            Type::Product(_) => {
+                write!(f, "{{")?;
+                for (i, (name, field)) in self.fields().into_iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: ", name)?;
+                    field.fmt_member(f)?;
+                }
+                write!(f, "}}")
+            }
         }
     }
 }
@@ -67,7 +242,14 @@ impl<'tb, 'ts> Display for Cell<'tb, 'ts> {
 impl PartialEq for Cell<'_, '_> {
     fn eq(&self, other: &Self) -> bool {
         debug_assert_eq!(self.type_id, other.type_id);
-        self.data == other.data
+
+        match &self.type_map[&self.type_id] {
+            // Strings may be stored in different places, so we can't just compare the bytes
+            Type::String | Type::Sum(_) | Type::Product(_) => {
+                self.partial_cmp(other) == Some(Ordering::Equal)
+            }
+            _ => self.data == other.data,
+        }
     }
 }
 
@@ -85,9 +267,11 @@ impl PartialOrd for Cell<'_, '_> {
             Type::Bool => deserialize::<bool>(self.data)
                 .unwrap()
                 .partial_cmp(&deserialize(other.data).unwrap()),
-            Type::Double => deserialize::<f64>(self.data)
-                .unwrap()
-                .partial_cmp(&deserialize(other.data).unwrap()),
+            Type::Double => Some(compare_doubles(
+                deserialize(self.data).unwrap(),
+                deserialize(other.data).unwrap(),
+            )),
+            Type::String => self.as_str().partial_cmp(other.as_str()),
             Type::Sum(variants) => {
                 let mut data1 = self.data;
                 let mut data2 = other.data;
@@ -103,18 +287,11 @@ impl PartialOrd for Cell<'_, '_> {
                     Ordering::Equal => {
                         let (_name, members) = &variants[tag1];
                         for &type_id in members {
-                            let t = &self.type_map[&type_id];
-                            let t_size = t.size_of(self.type_map);
-                            let member_cell1 = Cell {
-                                type_map: self.type_map,
-                                type_id,
-                                data: &data1[..t_size],
-                            };
-                            let member_cell2 = Cell {
-                                type_map: self.type_map,
-                                type_id,
-                                data: &data2[..t_size],
-                            };
+                            let t_size = self.type_map.member_size_of(type_id);
+                            let member_cell1 = self.member(type_id, &data1[..t_size]);
+                            let member_cell2 = other.member(type_id, &data2[..t_size]);
+                            data1 = &data1[t_size..];
+                            data2 = &data2[t_size..];
 
                             match member_cell1.partial_cmp(&member_cell2) {
                                 Some(Ordering::Equal) => continue,
@@ -126,6 +303,21 @@ impl PartialOrd for Cell<'_, '_> {
                     not_equal => Some(not_equal),
                 }
             }
+            Type::Product(_) => {
+                // Records are compared field by field, sorted by name, the same as record values
+                let mut fields1 = self.fields();
+                let mut fields2 = other.fields();
+                fields1.sort_by_key(|(name, _)| *name);
+                fields2.sort_by_key(|(name, _)| *name);
+
+                for ((_, field1), (_, field2)) in fields1.iter().zip(fields2.iter()) {
+                    match field1.partial_cmp(field2) {
+                        Some(Ordering::Equal) => continue,
+                        not_equal => return not_equal,
+                    }
+                }
+                Some(Ordering::Equal)
+            }
         }
     }
 }
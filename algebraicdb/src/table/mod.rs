@@ -2,11 +2,13 @@ mod cell;
 mod iter;
 mod row;
 mod schema;
+mod view;
 
 pub use self::cell::Cell;
 pub use self::iter::RowIter;
 pub use self::row::Row;
 pub use self::schema::Schema;
+pub use self::view::View;
 
 use crate::state::TTable;
 use crate::types::{TypeId, TypeMap, Value};
@@ -22,6 +24,11 @@ pub struct Column {
 pub struct Table {
     pub schema: Schema,
     pub data: Vec<u8>,
+
+    /// Storage for variable-length data, e.g. strings, which is referred to from `data`.
+    ///
+    /// Updates and deletes leave unreferenced data behind, which is removed by `compact_heap`.
+    pub heap: Vec<u8>,
     pub row_size: usize,
 }
 
@@ -35,6 +42,7 @@ impl Table {
     pub fn new(schema: Schema, types: &TypeMap) -> Self {
         Self {
             data: vec![],
+            heap: vec![],
             row_size: schema
                 .columns
                 .iter()
@@ -57,7 +65,7 @@ impl Table {
         let start = self.row_start(row);
         let end = start + self.row_size;
 
-        Row::new(&self.schema, &self.data[start..end])
+        Row::new(&self.schema, &self.data[start..end], &self.heap)
     }
 
     pub fn get_row_value(&self, row: usize, types: &TypeMap) -> Vec<Value> {
@@ -66,13 +74,69 @@ impl Table {
         for (_, t_id) in self.schema.columns.iter() {
             let t = &types[t_id];
             let t_size = t.size_of(types);
-            output.push(t.from_bytes(&data[..t_size], types).unwrap());
+            output.push(t.from_bytes(&data[..t_size], &self.heap, types).unwrap());
             data = &data[t_size..];
         }
 
         output
     }
 
+    /// Overwrite the value of a single cell
+    pub fn set_cell(&mut self, row: usize, col: usize, value: &Value, types: &TypeMap) {
+        let mut start = self.row_start(row);
+        for (_, t_id) in &self.schema.columns[..col] {
+            start += types[t_id].size_of(types);
+        }
+
+        let t = &types[&self.schema.columns[col].1];
+        let end = start + t.size_of(types);
+
+        let mut bytes = Vec::with_capacity(end - start);
+        value.to_bytes(&mut bytes, &mut self.heap, types, t);
+        self.data[start..end].copy_from_slice(&bytes);
+    }
+
+    /// Remove a set of rows from the table, along with the heap data which they refer to
+    ///
+    /// `rows` must be sorted in ascending order.
+    pub fn remove_rows(&mut self, rows: &[usize], types: &TypeMap) {
+        let mut rows = rows.iter().peekable();
+        let mut kept = 0;
+        for row in 0..self.row_count() {
+            if rows.peek() == Some(&&row) {
+                rows.next();
+                continue;
+            }
+
+            if kept != row {
+                let start = self.row_start(row);
+                let dest = self.row_start(kept);
+                self.data.copy_within(start..start + self.row_size, dest);
+            }
+            kept += 1;
+        }
+
+        self.data.truncate(self.row_start(kept));
+        self.compact_heap(types);
+    }
+
+    /// Copy the data which the rows refer to into a new heap, leaving the unreferenced data behind
+    pub fn compact_heap(&mut self, types: &TypeMap) {
+        if self.heap.is_empty() {
+            return;
+        }
+
+        let mut data = Vec::with_capacity(self.data.len());
+        let mut heap = vec![];
+        for row in self.iter() {
+            for (_, cell) in row.iter(types) {
+                cell.copy_into(&mut data, &mut heap);
+            }
+        }
+        self.data = data;
+        self.heap = heap;
+    }
+
     pub fn row_count(&self) -> usize {
         assert_eq!(self.data.len() % self.row_size, 0);
 
@@ -83,16 +147,85 @@ impl Table {
         row * self.row_size
     }
 
+    /// Push a row of raw bytes
+    ///
+    /// Any heap references in the row must refer to the heap of this table.
     pub fn push_row_bytes(&mut self, row: &[u8]) {
         assert_eq!(row.len(), self.row_size);
         self.data.extend_from_slice(row);
     }
 
+    /// Push all rows of another table with the same schema
+    pub fn push_table(&mut self, other: &Table, types: &TypeMap) {
+        assert_eq!(self.row_size, other.row_size);
+        if other.heap.is_empty() {
+            // No heap references which need to be moved
+            self.data.extend_from_slice(&other.data);
+        } else {
+            for row in other.iter() {
+                for (_, cell) in row.iter(types) {
+                    cell.copy_into(&mut self.data, &mut self.heap);
+                }
+            }
+        }
+    }
+
+    /// Add a column after the other columns, which has the same value in every row
+    pub fn add_column(&mut self, name: String, type_id: TypeId, value: &Value, types: &TypeMap) {
+        let mut cell = vec![];
+        value.to_bytes(&mut cell, &mut self.heap, types, &types[&type_id]);
+
+        let row_size = self.row_size + cell.len();
+        let mut data = Vec::with_capacity(self.row_count() * row_size);
+        for row in self.data.chunks(self.row_size) {
+            data.extend_from_slice(row);
+            data.extend_from_slice(&cell);
+        }
+
+        self.schema.columns.push((name, type_id));
+        self.data = data;
+        self.row_size = row_size;
+    }
+
+    /// Remove a column from every row
+    ///
+    /// The column must not be the only one, since rows can't be empty.
+    pub fn drop_column(&mut self, col: usize, types: &TypeMap) {
+        assert!(self.schema.len() > 1);
+
+        let size_of = |(_, t_id): &(String, TypeId)| types[t_id].size_of(types);
+        let start: usize = self.schema.columns[..col].iter().map(size_of).sum();
+        let end = start + size_of(&self.schema.columns[col]);
+
+        let row_size = self.row_size - (end - start);
+        let mut data = Vec::with_capacity(self.row_count() * row_size);
+        for row in self.data.chunks(self.row_size) {
+            data.extend_from_slice(&row[..start]);
+            data.extend_from_slice(&row[end..]);
+        }
+
+        self.schema.columns.remove(col);
+        self.data = data;
+        self.row_size = row_size;
+        self.compact_heap(types);
+    }
+
+    /// Encode every row again, after the layouts of the types of the columns have changed
+    ///
+    /// `old_types` are the types which the rows were encoded with.
+    pub fn reencode(&mut self, old_types: &TypeMap, types: &TypeMap) {
+        let mut table = Table::new(self.schema.clone(), types);
+        for row in 0..self.row_count() {
+            table.push_row(&self.get_row_value(row, old_types), types);
+        }
+        *self = table;
+    }
+
     pub fn push_row(&mut self, cells: &[Value], types: &TypeMap) {
         assert_eq!(self.data.len() % self.row_size, 0);
 
         for (t_id, value) in self.schema.columns.iter().map(|(_, t)| t).zip(cells.iter()) {
-            value.to_bytes(&mut self.data, types, &types[t_id])
+            value.to_bytes(&mut self.data, &mut self.heap, types, &types[t_id])
         }
 
         assert_eq!(self.data.len() % self.row_size, 0);
@@ -111,6 +244,7 @@ pub mod tests {
         int_or_nil_id: TypeId,
         big_type_id: TypeId,
         bigger_type_id: TypeId,
+        record_id: TypeId,
     }
 
     pub fn create_type_map() -> (TestTypeIds, TypeMap) {
@@ -136,6 +270,14 @@ pub mod tests {
                 ("Boolean".into(), vec![bool_id]),
             ]),
         );
+        let record_id = types.insert(
+            "Record",
+            Type::Product(vec![
+                ("int".into(), int_id),
+                ("big".into(), big_type_id),
+                ("double".into(), double_id),
+            ]),
+        );
         let ids = TestTypeIds {
             int_id,
             bool_id,
@@ -143,6 +285,7 @@ pub mod tests {
             int_or_nil_id,
             big_type_id,
             bigger_type_id,
+            record_id,
         };
         (ids, types)
     }
@@ -154,6 +297,7 @@ pub mod tests {
             ("i".into(), ids.int_id),
             ("b".into(), ids.bool_id),
             ("s".into(), ids.bigger_type_id),
+            ("r".into(), ids.record_id),
         ];
         let mut table = Table::new(Schema::new(schema.clone()), &types);
 
@@ -185,12 +329,84 @@ pub mod tests {
             for j in 0..row.cell_count() {
                 let cell = row.get_cell(&types, j);
                 let t = &types[&schema[j].1];
-                let v = t.from_bytes(cell.data, &types).unwrap();
+                let v = t.from_bytes(cell.data, cell.heap, &types).unwrap();
                 assert_eq!(v, rows[i][j]);
             }
         }
     }
 
+    #[test]
+    fn test_alter_columns() {
+        let (ids, types) = create_type_map();
+        let schema = Schema::new(vec![
+            ("i".into(), ids.int_id),
+            ("s".into(), ids.big_type_id),
+        ]);
+        let mut table = Table::new(schema, &types);
+
+        let rows: Vec<Vec<Value>> = (0..100)
+            .map(|_| {
+                vec![
+                    types[&ids.int_id].random_value(&types),
+                    types[&ids.big_type_id].random_value(&types),
+                ]
+            })
+            .collect();
+        for row in &rows {
+            table.push_row(row, &types);
+        }
+
+        table.add_column("b".into(), ids.bool_id, &Value::Bool(true), &types);
+        assert_eq!(table.row_count(), rows.len());
+        for (i, row) in rows.iter().enumerate() {
+            let mut expected = row.clone();
+            expected.push(Value::Bool(true));
+            assert_eq!(table.get_row_value(i, &types), expected);
+        }
+
+        table.drop_column(0, &types);
+        assert_eq!(table.row_count(), rows.len());
+        for (i, row) in rows.iter().enumerate() {
+            let expected = vec![row[1].clone(), Value::Bool(true)];
+            assert_eq!(table.get_row_value(i, &types), expected);
+        }
+    }
+
+    #[test]
+    fn test_compact_heap() {
+        let (ids, types) = create_type_map();
+        let string_id = types.get_base_id(BaseType::String);
+        let schema = Schema::new(vec![("i".into(), ids.int_id), ("s".into(), string_id)]);
+        let mut table = Table::new(schema, &types);
+
+        for i in 0..10 {
+            table.push_row(&[Value::Integer(i), Value::Str("initial".into())], &types);
+        }
+
+        // Every update leaves the old string behind, until the heap is compacted
+        for i in 0..100 {
+            let value = Value::Str(format!("updated {:02}", i).into());
+            for row in 0..table.row_count() {
+                table.set_cell(row, 1, &value, &types);
+            }
+            table.compact_heap(&types);
+            assert_eq!(table.heap.len(), 10 * "updated 00".len());
+        }
+
+        table.remove_rows(&[0, 2, 4, 6, 8], &types);
+        assert_eq!(table.heap.len(), 5 * "updated 00".len());
+        for (i, row) in (0..5)
+            .map(|row| table.get_row_value(row, &types))
+            .enumerate()
+        {
+            let expected = vec![
+                Value::Integer(i as i32 * 2 + 1),
+                Value::Str("updated 99".into()),
+            ];
+            assert_eq!(row, expected);
+        }
+    }
+
     #[test]
     fn test_ord_ints() {
         let (ids, types) = create_type_map();
@@ -5,12 +5,14 @@ use crate::types::TypeMap;
 pub struct Row<'tb> {
     schema: &'tb Schema,
     pub data: &'tb [u8],
+    pub heap: &'tb [u8],
 }
 
 #[derive(Clone, Copy)]
 pub struct CellIter<'tb, 'ts> {
     schema: &'tb Schema,
     pub data: &'tb [u8],
+    heap: &'tb [u8],
     type_map: &'ts TypeMap,
     cursor: usize,
     col: usize,
@@ -31,7 +33,7 @@ impl<'tb, 'ts> Iterator for CellIter<'tb, 'ts> {
 
         let end = start + t_size;
 
-        let cell = Cell::new(*t_id, &self.data[start..end], self.type_map);
+        let cell = Cell::new(*t_id, &self.data[start..end], self.heap, self.type_map);
 
         self.cursor = end;
         self.col += 1;
@@ -41,13 +43,14 @@ impl<'tb, 'ts> Iterator for CellIter<'tb, 'ts> {
 }
 
 impl<'tb> Row<'tb> {
-    pub fn new(schema: &'tb Schema, data: &'tb [u8]) -> Self {
-        Row { schema, data }
+    pub fn new(schema: &'tb Schema, data: &'tb [u8], heap: &'tb [u8]) -> Self {
+        Row { schema, data, heap }
     }
 
     pub fn iter<'ts>(&self, type_map: &'ts TypeMap) -> CellIter<'tb, 'ts> {
         CellIter {
             data: &self.data,
+            heap: &self.heap,
             schema: &self.schema,
             type_map,
             cursor: 0,
@@ -65,7 +68,12 @@ impl<'tb> Row<'tb> {
 
         let end = start + type_map[&self.schema.columns[col].1].size_of(type_map);
 
-        Cell::new(self.schema.columns[col].1, &self.data[start..end], type_map)
+        Cell::new(
+            self.schema.columns[col].1,
+            &self.data[start..end],
+            self.heap,
+            type_map,
+        )
     }
 
     pub fn cell_count(&self) -> usize {
@@ -22,6 +22,12 @@ impl Schema {
             .map(|(_, type_id)| *type_id)
     }
 
+    pub fn column_index(&self, name: &str) -> Option<usize> {
+        self.columns
+            .iter()
+            .position(|(entry_name, _)| entry_name == name)
+    }
+
     pub fn len(&self) -> usize {
         self.columns.len()
     }
@@ -0,0 +1,20 @@
+use super::Schema;
+use serde::{Deserialize, Serialize};
+
+/// A named select, which is read like a table
+///
+/// The rows of a view aren't stored. A statement which reads a view parses and executes its
+/// whole select once, before the rest of the statement filters or limits the rows.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct View {
+    /// The source text of the select, which is parsed again whenever the view is read
+    pub query: String,
+
+    /// The names and types of the selected values
+    pub schema: Schema,
+
+    /// The tables and views which the select reads from
+    ///
+    /// These can't be dropped while the view exists.
+    pub reads: Vec<String>,
+}
@@ -1,4 +1,5 @@
 use crate::ast::*;
+use crate::pattern_analysis::{self, PatternWarning};
 use crate::state::{ResourcesGuard, TTable};
 use crate::types::*;
 use std::collections::HashMap;
@@ -7,6 +8,33 @@ use std::collections::HashSet;
 pub struct Context<'ast, T> {
     pub globals: &'ast ResourcesGuard<'ast, T>,
     locals: Vec<Scope>,
+
+    /// Problems which don't stop the statement from being executed
+    pub warnings: Vec<PatternWarning>,
+
+    /// Whether aggregate functions may be used in the expression being checked
+    aggregates: Grouping,
+
+    /// The common tables in scope and their columns, where the innermost table is last
+    ///
+    /// The views which are read are the outermost tables, so common tables shadow them.
+    ///
+    /// The columns of a recursive table are unknown while its first select is checked, and
+    /// the table may only be read after that if it is combined with unions.
+    tables: Vec<(String, Option<Vec<(String, TypeId)>>)>,
+}
+
+/// How the rows are grouped, for the aggregate functions in the selected values
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Grouping {
+    /// Aggregate functions are not allowed, e.g. in where-clauses
+    None,
+
+    /// All rows form a single group, which may be empty
+    Single,
+
+    /// The rows are grouped by a group-by clause, so every group has at least one row
+    GroupBy,
 }
 
 type Scope = HashMap<String, Vec<TypeId>>;
@@ -27,10 +55,22 @@ pub enum TypeError {
         span: Option<Span>,
         ident: String,
     },
+    Misplaced {
+        span: Option<Span>,
+        item: &'static str,
+    },
+    NotGrouped {
+        span: Option<Span>,
+        ident: String,
+    },
     MissingColumn {
         span: Option<Span>,
         name: String,
     },
+    MissingField {
+        span: Option<Span>,
+        name: String,
+    },
     MismatchingTypes {
         span: Option<Span>,
         type_1: String,
@@ -50,12 +90,26 @@ pub enum TypeError {
         expected: usize,
         actual: usize,
     },
+    NonExhaustive {
+        span: Option<Span>,
+        missing: Vec<String>,
+    },
+
+    /// An instance of a generic type is needed, e.g. an optional type, but it hasn't been
+    /// created yet
+    ///
+    /// The statement should be checked again after the instance has been created.
+    MissingInstance(String, Vec<TypeId>),
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug)]
 pub enum DuckType<'ast> {
     Concrete(TypeId),
-    Variant(&'ast str, &'ast [Value<'ast>]),
+    Variant(&'ast str, Vec<DuckType<'ast>>),
+    Record(Vec<(&'ast Spanned<&'ast str>, DuckType<'ast>)>),
+
+    /// The type of whichever of the values is chosen, e.g. the arms of a match
+    OneOf(Vec<(DuckType<'ast>, Option<Span>)>),
 }
 
 impl From<TypeId> for DuckType<'static> {
@@ -69,12 +123,21 @@ impl<'ast, T: TTable> Context<'ast, T> {
         Context {
             globals,
             locals: vec![HashMap::new()],
+            warnings: vec![],
+            aggregates: Grouping::None,
+            tables: globals
+                .views
+                .iter()
+                .map(|(name, view)| (name.to_string(), Some(view.schema.columns.clone())))
+                .collect(),
         }
     }
 
     pub fn search_locals(&self, ident: &Spanned<&str>) -> Result<TypeId, TypeError> {
+        // Inner scopes shadow outer ones
         self.locals
             .iter()
+            .rev()
             .filter_map(|scope| scope.get(ident.value))
             .next()
             .map(|res| {
@@ -96,6 +159,41 @@ impl<'ast, T: TTable> Context<'ast, T> {
             })
     }
 
+    /// Find the type of a column qualified by the name of its table, e.g. `u.id`
+    ///
+    /// Columns and variables shadow the names of tables, so a field of an identifier is only a
+    /// qualified column if the identifier is undefined.
+    pub fn search_qualified(
+        &self,
+        record: &Spanned<Expr>,
+        field: &Spanned<&str>,
+    ) -> Option<Result<TypeId, TypeError>> {
+        let table = match &record.value {
+            Expr::Ident(table) => table,
+            _ => return None,
+        };
+
+        if !self.is_table(table) {
+            return None;
+        }
+
+        let name = qualified_name(table, field);
+        let span = match (record.span, field.span) {
+            (Some(start), Some(end)) => Some(start.union(end)),
+            _ => None,
+        };
+        Some(self.search_locals(&Spanned { span, value: &name }))
+    }
+
+    /// Check whether an identifier is the name of a table whose columns are in scope, rather than
+    /// a column or variable
+    pub fn is_table(&self, ident: &str) -> bool {
+        let prefix = qualified_name(ident, "");
+        let is_defined = |scope: &Scope| scope.contains_key(ident);
+        let is_table = |scope: &Scope| scope.keys().any(|name| name.starts_with(&prefix));
+        !self.locals.iter().any(is_defined) && self.locals.iter().any(is_table)
+    }
+
     pub fn push_locals_scope(&mut self) {
         self.locals.push(HashMap::new());
     }
@@ -124,38 +222,221 @@ impl<'ast, T: TTable> Context<'ast, T> {
             .push(type_id)
     }
 
+    /// Define a column, which may also be referred to by the name of its table
+    pub fn push_column(&mut self, table: Option<&str>, name: &str, type_id: TypeId) {
+        if let Some(table) = table {
+            self.push_local(qualified_name(table, name), type_id);
+        }
+        self.push_local(name.to_string(), type_id);
+    }
+
     pub fn locals(&self) -> &[Scope] {
         &self.locals[..]
     }
 }
 
-pub fn check_stmt<T: TTable>(stmt: &Stmt, globals: &ResourcesGuard<T>) -> Result<(), TypeError> {
+/// Type check a statement, returning any warnings about it
+pub fn check_stmt<T: TTable>(
+    stmt: &Stmt,
+    globals: &ResourcesGuard<T>,
+) -> Result<Vec<PatternWarning>, TypeError> {
     let mut ctx = Context::new(globals);
 
     match stmt {
-        Stmt::Select(select) => check_select(select, &mut ctx).map(|_| ()),
+        Stmt::Select(select) => check_select(select, None, &mut ctx).map(|_| ()),
         Stmt::Update(update) => check_update(update, &mut ctx),
         Stmt::Delete(delete) => check_delete(delete, &mut ctx),
         Stmt::Drop(_) => Ok(()), // Nothing to do here...
         Stmt::Insert(insert) => check_insert(insert, &mut ctx),
         Stmt::CreateTable(create_table) => check_create_table(create_table, &mut ctx),
         Stmt::CreateType(create_type) => check_create_type(create_type, &mut ctx),
-    }
+        Stmt::CreateView(create_view) => {
+            check_select(&create_view.select, None, &mut ctx).map(|_| ())
+        }
+        Stmt::DropView(_) => Ok(()),
+        Stmt::AlterTable(alter_table) => check_alter_table(alter_table, &mut ctx),
+        Stmt::AlterType(alter_type) => check_alter_type(alter_type, &mut ctx),
+    }?;
+
+    Ok(ctx.warnings)
+}
+
+/// The name of a column qualified by the name of its table
+fn qualified_name(table: &str, column: &str) -> String {
+    format!("{}.{}", table, column)
 }
 
-fn import_table_columns<T: TTable>(name: &str, ctx: &mut Context<T>) {
+/// Define the columns of a table, optionally qualified by the name of the table
+fn import_table_columns<T: TTable>(name: &str, qualifier: Option<&str>, ctx: &mut Context<T>) {
     let table = ctx.globals.read_table(name);
     let schema = table.get_schema();
 
     for (name, type_id) in &schema.columns {
-        ctx.push_local(name.clone(), *type_id);
+        ctx.push_column(qualifier, name, *type_id);
+    }
+}
+
+/// Define the columns of a common table or a view, qualified by the name of the table
+///
+/// Returns false if there is no such table with the name, which means that it refers to a
+/// table of the database.
+fn import_common_table_columns<T: TTable>(
+    name: &Spanned<&str>,
+    ctx: &mut Context<T>,
+) -> Result<bool, TypeError> {
+    let columns = match ctx
+        .tables
+        .iter()
+        .rev()
+        .find(|(table, _)| table == name.value)
+    {
+        Some((_, Some(columns))) => columns.clone(),
+        Some((_, None)) => {
+            return Err(TypeError::Misplaced {
+                span: name.span,
+                item: "a recursive reference",
+            })
+        }
+        None => return Ok(false),
+    };
+
+    for (column, type_id) in columns {
+        ctx.push_column(Some(name.value), &column, type_id);
     }
+    Ok(true)
 }
 
+/// Check a select, returning the types of the selected values
+///
+/// `expected` are the types which the selected values must have, if they are known from where
+/// the select is used.
 fn check_select<'ast, T: TTable>(
     select: &'ast Select<'ast>,
+    expected: Option<&[TypeId]>,
+    ctx: &mut Context<T>,
+) -> Result<Vec<TypeId>, TypeError> {
+    check_select_defining(select, expected, None, ctx)
+}
+
+/// Check a select, which may define the recursive common table at index `recursive`
+///
+/// The columns of the recursive table are the values selected by the first select, so the
+/// selects which are combined with it may read from the table.
+fn check_select_defining<'ast, T: TTable>(
+    select: &'ast Select<'ast>,
+    expected: Option<&[TypeId]>,
+    recursive: Option<usize>,
+    ctx: &mut Context<T>,
+) -> Result<Vec<TypeId>, TypeError> {
+    // The common tables are only visible inside of the select
+    let scope = ctx.tables.len();
+    let types = match &select.with {
+        Some(with) => check_with(with, ctx),
+        None => Ok(()),
+    }
+    .and_then(|()| check_combined_select(select, expected, recursive, ctx));
+    ctx.tables.truncate(scope);
+    types
+}
+
+/// Check the common tables of a with-clause, and define them
+fn check_with<'ast, T: TTable>(
+    with: &'ast With<'ast>,
+    ctx: &mut Context<T>,
+) -> Result<(), TypeError> {
+    for table in &with.tables {
+        let name = table.name.value.to_string();
+
+        // Only the selected values are visible outside of the select
+        ctx.push_locals_scope();
+        let types = if with.recursive {
+            ctx.tables.push((name.clone(), None));
+            let index = ctx.tables.len() - 1;
+            check_select_defining(&table.select, None, Some(index), ctx)
+        } else {
+            check_select(&table.select, None, ctx)
+        };
+        ctx.pop_locals_scope();
+
+        let names = table.select.column_names().into_iter().map(str::to_owned);
+        let columns = Some(names.zip(types?).collect());
+        if with.recursive {
+            ctx.tables.last_mut().unwrap().1 = columns;
+        } else {
+            ctx.tables.push((name, columns));
+        }
+    }
+    Ok(())
+}
+
+/// Check a select and the selects which are combined with it, ignoring its with-clause
+fn check_combined_select<'ast, T: TTable>(
+    select: &'ast Select<'ast>,
+    expected: Option<&[TypeId]>,
+    recursive: Option<usize>,
+    ctx: &mut Context<T>,
+) -> Result<Vec<TypeId>, TypeError> {
+    if select.set_operations.is_empty() {
+        return check_select_rows(select, &select.order_by, expected, ctx);
+    }
+
+    // Only the selected values of the combined selects are visible outside of them
+    ctx.push_locals_scope();
+    let types = check_select_rows(select, &[], expected, ctx);
+    ctx.pop_locals_scope();
+    let types = types?;
+
+    // Rows are only added to a recursive table by unions, like in the executor
+    let unions_only = select
+        .set_operations
+        .iter()
+        .all(|(operation, _)| matches!(operation, SetOperation::Union | SetOperation::UnionAll));
+    if let (Some(index), true) = (recursive, unions_only) {
+        let names = select.column_names().into_iter().map(str::to_owned);
+        ctx.tables[index].1 = Some(names.zip(types.iter().copied()).collect());
+    }
+
+    // The rows of every select must have the same types as the rows of the first one
+    for (_, operand) in &select.set_operations {
+        if operand.items.len() != types.len() {
+            return Err(TypeError::InvalidCount {
+                span: operand.span,
+                expected: types.len(),
+                actual: operand.items.len(),
+            });
+        }
+
+        ctx.push_locals_scope();
+        let operand_types = check_select(operand, Some(&types), ctx);
+        ctx.pop_locals_scope();
+        operand_types?;
+    }
+
+    // The combined rows are sorted by their columns, which are named by the first select
+    ctx.push_locals_scope();
+    for (name, &type_id) in select.column_names().into_iter().zip(&types) {
+        ctx.push_column(None, name, type_id);
+    }
+    let grouping = std::mem::replace(&mut ctx.aggregates, Grouping::None);
+    let order_types = select.order_by.iter().try_for_each(|(key, _)| {
+        let t = check_expr(key, ctx)?;
+        infer_type(t, None, key.span, &ctx.globals.type_map).map(|_| ())
+    });
+    ctx.aggregates = grouping;
+    ctx.pop_locals_scope();
+    order_types?;
+
+    Ok(types)
+}
+
+/// Check the rows of a select, which are sorted by `order_by`, returning the types of the
+/// selected values
+fn check_select_rows<'ast, T: TTable>(
+    select: &'ast Select<'ast>,
+    order_by: &'ast [(Spanned<Expr<'ast>>, Order)],
+    expected: Option<&[TypeId]>,
     ctx: &mut Context<T>,
-) -> Result<Vec<DuckType<'ast>>, TypeError> {
+) -> Result<Vec<TypeId>, TypeError> {
     if let Some(from) = &select.from {
         check_select_from(from, ctx)?;
     }
@@ -164,31 +445,237 @@ fn check_select<'ast, T: TTable>(
         check_where_clause(where_clause, ctx)?;
     }
 
+    for key in &select.group_by {
+        check_expr(key, ctx)?;
+    }
+
+    // Aggregate functions may only be used in the selected values and in the order-by clause
+    let grouping = if select.group_by.is_empty() {
+        Grouping::Single
+    } else {
+        Grouping::GroupBy
+    };
+    let outer_grouping = std::mem::replace(&mut ctx.aggregates, grouping);
+
     // Collect a Result<Vec<_>> from an Iter<Result<_>>
-    select
+    let types: Result<Vec<_>, _> = select
         .items
         .iter()
         .map(|expr| check_expr(expr, ctx))
+        .collect();
+    let order_types: Result<Vec<_>, _> = order_by
+        .iter()
+        .map(|(expr, _)| check_expr(expr, ctx))
+        .collect();
+    ctx.aggregates = outer_grouping;
+    let types = types?;
+
+    // When the rows are grouped, only the values which are the same for the whole group may be
+    // used outside of aggregate functions
+    if is_grouped(select) {
+        let order_exprs = order_by.iter().map(|(expr, _)| expr);
+        let is_table = |ident: &str| ctx.is_table(ident);
+        for item in select.items.iter().chain(order_exprs) {
+            check_grouped(item, &select.group_by, &mut vec![], &is_table)?;
+        }
+    }
+
+    // Values are ordered according to their types, and the selected values are stored in a
+    // table, so their types must be known
+    let type_map = &ctx.globals.type_map;
+    for (t, item) in order_types?.into_iter().zip(order_by) {
+        let key = &item.0;
+        infer_type(t, None, key.span, type_map)?;
+    }
+
+    types
+        .into_iter()
+        .zip(&select.items)
+        .enumerate()
+        .map(|(i, (t, item))| {
+            let expected = expected.map(|expected| expected[i]);
+            infer_type(t, expected, item.span, type_map)
+        })
         .collect()
 }
 
+/// Check whether a select always selects exactly one row, e.g. `SELECT COUNT(*) FROM t`
+pub fn is_single_row(select: &Select) -> bool {
+    // All rows form a single group when aggregate functions are used without a group-by clause
+    let single_group = is_grouped(select) && select.group_by.is_empty();
+    let without_rows = select.from.is_none() && select.where_clause.is_none();
+    (single_group || without_rows)
+        && select.set_operations.is_empty()
+        && select.offset.unwrap_or(0) == 0
+        && select.limit != Some(0)
+}
+
+/// Check whether the rows of a select are grouped, i.e. if the selected values are computed
+/// once per group rather than once per row
+pub fn is_grouped(select: &Select) -> bool {
+    let mut aggregates = vec![];
+    for item in &select.items {
+        item.aggregates(&mut aggregates);
+    }
+    // The order-by clause of set operations sorts the combined rows, which aren't grouped
+    if select.set_operations.is_empty() {
+        for (expr, _) in &select.order_by {
+            expr.aggregates(&mut aggregates);
+        }
+    }
+    !select.group_by.is_empty() || !aggregates.is_empty()
+}
+
+/// Get the name of a column, and of its table if qualified, which a key of a group refers to
+fn grouped_column<'e>(
+    expr: &Expr<'e>,
+    bound: &[&str],
+    is_table: &dyn Fn(&str) -> bool,
+) -> Option<(Option<&'e str>, &'e str)> {
+    match expr {
+        Expr::Ident(ident) => Some((None, ident.value)),
+        // Variables of match arms shadow the names of tables
+        Expr::Field(
+            box Spanned {
+                value: Expr::Ident(table),
+                ..
+            },
+            field,
+        ) if !bound.contains(&table.value) && is_table(table.value) => {
+            Some((Some(table.value), field.value))
+        }
+        _ => None,
+    }
+}
+
+/// Check that an expression only refers to the keys of a group outside of aggregate functions
+///
+/// Columns are the same key with or without the names of their tables, e.g. `a` and `t.a`.
+fn check_grouped<'a>(
+    expr: &'a Spanned<Expr<'a>>,
+    keys: &[Spanned<Expr>],
+    bound: &mut Vec<&'a str>,
+    is_table: &dyn Fn(&str) -> bool,
+) -> Result<(), TypeError> {
+    let column = grouped_column(&expr.value, bound, is_table);
+    let is_key = |key: &Spanned<Expr>| match (grouped_column(&key.value, bound, is_table), column) {
+        (Some((table_1, name_1)), Some((table_2, name_2))) => {
+            name_1 == name_2 && (table_1.is_none() || table_2.is_none() || table_1 == table_2)
+        }
+        _ => key == expr,
+    };
+    if keys.iter().any(is_key) {
+        return Ok(());
+    }
+    if let Some((Some(table), name)) = column {
+        return Err(TypeError::NotGrouped {
+            span: expr.span,
+            ident: qualified_name(table, name),
+        });
+    }
+
+    match &expr.value {
+        Expr::Ident(ident) => {
+            if bound.contains(&ident.value) {
+                Ok(())
+            } else {
+                Err(TypeError::NotGrouped {
+                    span: ident.span,
+                    ident: ident.to_string(),
+                })
+            }
+        }
+        Expr::Value(_) | Expr::Aggregate(_, _) => Ok(()),
+        Expr::Sum(_, _, args) => args
+            .iter()
+            .try_for_each(|arg| check_grouped(arg, keys, bound, is_table)),
+        Expr::Record(fields) => fields
+            .iter()
+            .try_for_each(|(_, e)| check_grouped(e, keys, bound, is_table)),
+        Expr::Field(box record, _)
+        | Expr::Tag(box record)
+        | Expr::Not(box record)
+        | Expr::Neg(box record)
+        | Expr::Cast(box record, _)
+        | Expr::In(box record, _) => check_grouped(record, keys, bound, is_table),
+
+        // Subqueries are evaluated for the first row of the group
+        Expr::Exists(_) | Expr::Subquery(_) => Ok(()),
+        Expr::Eql(box (e1, e2))
+        | Expr::NEq(box (e1, e2))
+        | Expr::LEq(box (e1, e2))
+        | Expr::LTh(box (e1, e2))
+        | Expr::GTh(box (e1, e2))
+        | Expr::GEq(box (e1, e2))
+        | Expr::And(box (e1, e2))
+        | Expr::Or(box (e1, e2))
+        | Expr::Add(box (e1, e2))
+        | Expr::Sub(box (e1, e2))
+        | Expr::Mul(box (e1, e2))
+        | Expr::Div(box (e1, e2))
+        | Expr::Rem(box (e1, e2)) => {
+            check_grouped(e1, keys, bound, is_table)?;
+            check_grouped(e2, keys, bound, is_table)
+        }
+        Expr::Match(box value, arms) => {
+            check_grouped(value, keys, bound, is_table)?;
+            for (pattern, arm) in arms {
+                // Variables bound by the pattern are parts of the matched value
+                let scope = bound.len();
+                pattern.bindings(bound);
+                check_grouped(arm, keys, bound, is_table)?;
+                bound.truncate(scope);
+            }
+            Ok(())
+        }
+    }
+}
+
 fn check_select_from<T: TTable>(from: &SelectFrom, ctx: &mut Context<T>) -> Result<(), TypeError> {
     match from {
         SelectFrom::Select(nsel) => {
-            check_select(&nsel, ctx)?;
+            // Only the selected values are visible outside of the select
+            ctx.push_locals_scope();
+            let types = check_select(&nsel, None, ctx);
+            ctx.pop_locals_scope();
+
+            for (name, type_id) in nsel.column_names().into_iter().zip(types?) {
+                ctx.push_column(None, name, type_id);
+            }
         }
         SelectFrom::Table(name) => {
-            import_table_columns(name, ctx);
+            // Common tables shadow the tables of the database
+            if !import_common_table_columns(name, ctx)? {
+                import_table_columns(name, Some(name), ctx);
+            }
+        }
+        SelectFrom::Alias(from, alias) => {
+            ctx.push_locals_scope();
+            check_select_from(from, ctx)?;
+            let scope = ctx.pop_locals_scope();
+
+            // The columns are only qualified by the new name
+            for (name, types) in scope {
+                if !name.contains('.') {
+                    for type_id in types {
+                        ctx.push_column(Some(alias), &name, type_id);
+                    }
+                }
+            }
         }
         SelectFrom::Join(join) => {
             ctx.push_locals_scope();
             check_select_from(&join.table_a, ctx)?;
-            let scope = ctx.pop_locals_scope();
+            let scope_a = ctx.pop_locals_scope();
 
             ctx.push_locals_scope();
             check_select_from(&join.table_b, ctx)?;
+            let scope_b = ctx.pop_locals_scope();
 
-            ctx.merge_scope(scope);
+            // The on-clause is checked against the un-wrapped columns of both tables
+            ctx.push_locals_scope();
+            ctx.merge_scope(scope_a.clone());
+            ctx.merge_scope(scope_b.clone());
 
             if let Some(on_clause) = &join.on_clause {
                 let clause_type = check_expr(on_clause, ctx)?;
@@ -200,6 +687,37 @@ fn check_select_from<T: TTable>(from: &SelectFrom, ctx: &mut Context<T>) -> Resu
                     type_map,
                 )?;
             }
+            ctx.pop_locals_scope();
+
+            // Columns from a table which may be missing a matching row are made optional
+            let (optional_a, optional_b) = match join.join_type {
+                JoinType::Inner => (false, false),
+                JoinType::LeftOuter => (false, true),
+                JoinType::RightOuter => (true, false),
+                JoinType::FullOuter => (true, true),
+            };
+
+            let type_map = &ctx.globals.type_map;
+            let wrap = |scope: Scope, optional: bool| -> Result<Scope, TypeError> {
+                if !optional {
+                    return Ok(scope);
+                }
+                scope
+                    .into_iter()
+                    .map(|(name, types)| {
+                        let types = types
+                            .into_iter()
+                            .map(|t| optional_of(t, type_map))
+                            .collect::<Result<_, _>>()?;
+                        Ok((name, types))
+                    })
+                    .collect()
+            };
+            let scope_a = wrap(scope_a, optional_a)?;
+            let scope_b = wrap(scope_b, optional_b)?;
+
+            ctx.merge_scope(scope_a);
+            ctx.merge_scope(scope_b);
         }
     }
     Ok(())
@@ -210,6 +728,10 @@ fn check_where_clause<T: TTable>(
     ctx: &mut Context<T>,
 ) -> Result<(), TypeError> {
     let type_map = &ctx.globals.type_map;
+
+    // The patterns in the clause, grouped by the column they match on
+    let mut columns: Vec<(&Expr, TypeId, Vec<&Spanned<Pattern>>)> = vec![];
+
     for item in &clause.items {
         match item {
             WhereItem::Expr(expr) => {
@@ -217,12 +739,44 @@ fn check_where_clause<T: TTable>(
                 let bool_id = type_map.get_base_id(BaseType::Bool);
                 assert_type_as(expr_type, bool_id, expr.span, type_map)?;
             }
-            WhereItem::Pattern(ident, pattern) => {
-                let type_id = ctx.search_locals(ident)?;
+            WhereItem::Pattern(column, pattern) => {
+                let column_type = match &column.value {
+                    Expr::Ident(ident) => Some(ctx.search_locals(ident)),
+                    Expr::Field(box record, field) => ctx.search_qualified(record, field),
+                    _ => None,
+                };
+                let type_id = match column_type {
+                    Some(type_id) => type_id?,
+                    None => {
+                        check_expr(column, ctx)?;
+                        return Err(TypeError::Misplaced {
+                            span: column.span,
+                            item: "a pattern on an expression other than a column",
+                        });
+                    }
+                };
                 check_pattern(pattern, type_id, ctx)?;
+
+                match columns
+                    .iter_mut()
+                    .find(|(name, _, _)| name == &&column.value)
+                {
+                    Some((_, _, patterns)) => patterns.push(pattern),
+                    None => columns.push((&column.value, type_id, vec![pattern])),
+                }
             }
         }
     }
+
+    // All patterns on the same column must match, so look for patterns which contradict
+    // each other, or which don't filter anything.
+
+    let type_map = &ctx.globals.type_map;
+    for (_, type_id, patterns) in columns {
+        let warnings = pattern_analysis::check_conjunction(&patterns, type_id, type_map);
+        ctx.warnings.extend(warnings);
+    }
+
     Ok(())
 }
 
@@ -265,15 +819,73 @@ fn check_pattern<T: TTable>(
                 type_map,
             )?;
         }
+        Pattern::Str(_) => {
+            assert_type_as(
+                type_map.get_base_id(BaseType::String),
+                type_id,
+                pattern.span,
+                type_map,
+            )?;
+        }
         Pattern::Ignore => {}
         Pattern::Binding(name) => ctx.push_local(name.to_string(), type_id),
+        Pattern::Record { fields } => {
+            let type_map = &ctx.globals.type_map;
+            let field_types = match &type_map[&type_id] {
+                Type::Product(field_types) => field_types,
+                _ => {
+                    return Err(TypeError::InvalidUnknownType {
+                        span: pattern.span,
+                        expected: type_map.get_name(type_id).unwrap().to_string(),
+                    })
+                }
+            };
+
+            let mut sub_patterns = vec![];
+            for (i, (name, sub_pattern)) in fields.iter().enumerate() {
+                if fields[..i]
+                    .iter()
+                    .any(|(other, _)| other.value == name.value)
+                {
+                    return Err(TypeError::AlreadyDefined {
+                        span: name.span,
+                        ident: name.to_string(),
+                    });
+                }
+
+                let (_, field_type) = field_types
+                    .iter()
+                    .find(|(field, _)| field == name.value)
+                    .ok_or_else(|| TypeError::Undefined {
+                        span: name.span,
+                        kind: "field",
+                        item: name.to_string(),
+                    })?;
+                sub_patterns.push((sub_pattern, *field_type));
+            }
+
+            for (sub_pattern, field_type) in sub_patterns {
+                check_pattern(sub_pattern, field_type, ctx)?;
+            }
+        }
         Pattern::Variant {
             namespace,
             name,
             sub_patterns,
         } => {
             let type_map = &ctx.globals.type_map;
+            // Instances of generic types are named by their generic type, e.g. `Maybe::Just(x)`
+            let namespace = namespace
+                .as_ref()
+                .filter(|namespace| type_map.template_of(type_id) != Some(namespace.value));
             if let Some(namespace) = namespace {
+                if type_map.get_template(namespace).is_some() {
+                    return Err(TypeError::InvalidType {
+                        span: pattern.span,
+                        expected: type_map.get_name(type_id).unwrap().to_string(),
+                        actual: namespace.to_string(),
+                    });
+                }
                 let actual_type_id =
                     type_map
                         .get_id(namespace)
@@ -344,11 +956,27 @@ fn check_pattern<T: TTable>(
 }
 
 fn check_update<T: TTable>(update: &Update, ctx: &mut Context<T>) -> Result<(), TypeError> {
-    import_table_columns(&update.table, ctx);
+    import_table_columns(&update.table, None, ctx);
+
+    // The where clause may bind variables which can be used in the assignments
+    if let Some(where_clause) = &update.where_clause {
+        check_where_clause(where_clause, ctx)?;
+    }
+
     let table = ctx.globals.read_table(&update.table);
     let schema = table.get_schema();
 
+    let mut assigned_columns: HashSet<&str> = HashSet::new();
+
     for assignment in &update.ass {
+        // Make sure the user doesn't assign to the same column twice
+        if !assigned_columns.insert(&assignment.col) {
+            return Err(TypeError::AlreadyDefined {
+                span: assignment.col.span,
+                ident: assignment.col.to_string(),
+            });
+        }
+
         match schema.column(&assignment.col) {
             None => {
                 return Err(TypeError::Undefined {
@@ -358,7 +986,6 @@ fn check_update<T: TTable>(update: &Update, ctx: &mut Context<T>) -> Result<(),
                 })
             }
             Some(expected_type_id) => {
-                ctx.push_local(assignment.col.to_string(), expected_type_id);
                 let expr_type = check_expr(&assignment.expr, ctx)?;
 
                 assert_type_as(
@@ -377,7 +1004,7 @@ fn check_update<T: TTable>(update: &Update, ctx: &mut Context<T>) -> Result<(),
 fn check_delete<T: TTable>(delete: &Delete, ctx: &mut Context<T>) -> Result<(), TypeError> {
     match &delete.where_clause {
         Some(clause) => {
-            import_table_columns(&delete.table, ctx);
+            import_table_columns(&delete.table, None, ctx);
             check_where_clause(clause, ctx)
         }
 
@@ -440,32 +1067,31 @@ fn check_insert<T: TTable>(insert: &Insert, ctx: &mut Context<T>) -> Result<(),
         }
 
         InsertFrom::Select(select) => {
-            let types = check_select(select, ctx)?;
-
             // Make sure there is a value for every specified column
-            if insert.columns.len() != types.len() {
+            if insert.columns.len() != select.items.len() {
                 return Err(TypeError::InvalidCount {
                     span: select.span,
                     expected: insert.columns.len(),
-                    actual: types.len(),
+                    actual: select.items.len(),
                 });
             }
 
-            for (column, actual_type) in insert.columns.iter().zip(types.into_iter()) {
-                // Make sure the types of the values match the types of the columns
-                let expected_type = schema.column(column).ok_or_else(|| TypeError::Undefined {
-                    span: column.span,
-                    kind: "column",
-                    item: column.to_string(),
-                })?;
+            let column_types = insert
+                .columns
+                .iter()
+                .map(|column| {
+                    schema.column(column).ok_or_else(|| TypeError::Undefined {
+                        span: column.span,
+                        kind: "column",
+                        item: column.to_string(),
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
 
-                assert_type_as(
-                    actual_type,
-                    expected_type,
-                    select.span,
-                    &ctx.globals.type_map,
-                )?;
+            // Make sure the types of the values match the types of the columns
+            check_select(select, Some(&column_types), ctx)?;
 
+            for column in insert.columns.iter() {
                 // Make sure the user doesn't assign to the same column twice
                 if !populated_columns.insert(column) {
                     return Err(TypeError::AlreadyDefined {
@@ -502,13 +1128,7 @@ fn check_create_table<T: TTable>(
 
     let columns = &create_table.columns;
     for (_, column_type) in columns {
-        if ctx.globals.type_map.get(column_type).is_none() {
-            return Err(TypeError::Undefined {
-                span: column_type.span,
-                kind: "type",
-                item: column_type.to_string(),
-            });
-        }
+        check_type_ref(column_type, &[], &HashMap::new(), ctx)?;
     }
 
     // Make sure no two columns has the same name
@@ -526,33 +1146,209 @@ fn check_create_table<T: TTable>(
     Ok(())
 }
 
+fn check_alter_table<T: TTable>(
+    alter_table: &AlterTable,
+    ctx: &mut Context<T>,
+) -> Result<(), TypeError> {
+    let schema = ctx.globals.read_table(alter_table.table).get_schema();
+    let undefined = |column: &Spanned<&str>| TypeError::Undefined {
+        span: column.span,
+        kind: "column",
+        item: column.to_string(),
+    };
+    let already_defined = |column: &Spanned<&str>| TypeError::AlreadyDefined {
+        span: column.span,
+        ident: column.to_string(),
+    };
+
+    match &alter_table.alteration {
+        Alteration::AddColumn {
+            column,
+            column_type,
+            default,
+        } => {
+            if schema.column(column).is_some() {
+                return Err(already_defined(column));
+            }
+            check_type_ref(column_type, &[], &HashMap::new(), ctx)?;
+
+            // The default value can only be checked against types which exist already
+            let type_map = &ctx.globals.type_map;
+            let type_id = existing_type(column_type, type_map)?;
+
+            let default_type = check_expr(default, ctx)?;
+            assert_type_as(default_type, type_id, default.span, &ctx.globals.type_map)?;
+        }
+        Alteration::DropColumn(column) => {
+            if schema.column(column).is_none() {
+                return Err(undefined(column));
+            }
+            if schema.len() == 1 {
+                return Err(TypeError::NotSupported(
+                    "Dropping the only column of a table",
+                ));
+            }
+        }
+        Alteration::RenameColumn(from, to) => {
+            if schema.column(from).is_none() {
+                return Err(undefined(from));
+            }
+            if schema.column(to).is_some() {
+                return Err(already_defined(to));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn check_alter_type<T: TTable>(alter_type: &AlterType, ctx: &Context<T>) -> Result<(), TypeError> {
+    let type_map = &ctx.globals.type_map;
+    let name = &alter_type.name;
+
+    // The instances of generic types would have to be altered along with them
+    if type_map.template_arity(name).is_some() {
+        return Err(TypeError::NotSupported("Altering generic types"));
+    }
+    let variants = match type_map.get(name) {
+        Some(Type::Sum(variants)) => variants,
+        Some(_) => {
+            return Err(TypeError::NotSupported(
+                "Altering types which are not sum-types",
+            ))
+        }
+        None => {
+            return Err(TypeError::Undefined {
+                span: name.span,
+                kind: "type",
+                item: name.to_string(),
+            })
+        }
+    };
+
+    let is_variant = |variant: &Spanned<&str>| variants.iter().any(|(v, _)| v == variant.value);
+    let already_defined = |variant: &Spanned<&str>| TypeError::AlreadyDefined {
+        span: variant.span,
+        ident: variant.to_string(),
+    };
+
+    match &alter_type.alteration {
+        TypeAlteration::AddVariant { variant, members } => {
+            if is_variant(variant) {
+                return Err(already_defined(variant));
+            }
+            for t in members {
+                check_type_ref(t, &[], &HashMap::new(), ctx)?;
+            }
+        }
+        TypeAlteration::RenameVariant(from, to) => {
+            if !is_variant(from) {
+                return Err(TypeError::Undefined {
+                    span: from.span,
+                    kind: "variant",
+                    item: from.to_string(),
+                });
+            }
+            if is_variant(to) {
+                return Err(already_defined(to));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Get the id of a type which has been created, without instantiating any generic types
+fn existing_type(t: &TypeRef, type_map: &TypeMap) -> Result<TypeId, TypeError> {
+    let not_created =
+        || TypeError::NotSupported("Adding columns of generic types which are not used yet");
+    if t.args.is_empty() {
+        return type_map.get_id(t.name.value).ok_or_else(not_created);
+    }
+
+    let args = t
+        .args
+        .iter()
+        .map(|arg| existing_type(arg, type_map))
+        .collect::<Result<Vec<TypeId>, _>>()?;
+
+    // Optional types are created when they are needed, like in other statements
+    if t.name.value == OPTIONAL {
+        return optional_of(args[0], type_map);
+    }
+    type_map
+        .get_instance(t.name.value, &args)
+        .ok_or_else(not_created)
+}
+
 fn check_create_type<T: TTable>(
-    create: &CreateType,
+    create: &[CreateType],
     ctx: &mut Context<T>,
 ) -> Result<(), TypeError> {
     // For a type:
     // MyVariant = Var1 TypeA | Var2 TypeB TypeC
     // We have to check that the type name MyVariant is not taken
-    // as well ass that Type{A,B,C} exists
-    // TODO: recursive types
-    match create {
-        CreateType::Variant { name, variants } => {
-            if ctx.globals.type_map.get_id(name).is_some() {
-                return Err(TypeError::AlreadyDefined {
-                    span: name.span,
-                    ident: name.to_string(),
-                });
+    // as well ass that Type{A,B,C} exists.
+    // Types created together may refer to each other, and to themselves.
+    let type_map = &ctx.globals.type_map;
+    let mut group: HashMap<&str, usize> = HashMap::new();
+    for create_type in create {
+        match create_type {
+            CreateType::Record { name, .. } => {
+                if type_map.get_id(name).is_some()
+                    || type_map.template_arity(name).is_some()
+                    || group.insert(name.value, 0).is_some()
+                {
+                    return Err(TypeError::AlreadyDefined {
+                        span: name.span,
+                        ident: name.to_string(),
+                    });
+                }
             }
+            CreateType::Variant { name, params, .. } => {
+                if type_map.get_id(name).is_some()
+                    || type_map.template_arity(name).is_some()
+                    || group.insert(name.value, params.len()).is_some()
+                {
+                    return Err(TypeError::AlreadyDefined {
+                        span: name.span,
+                        ident: name.to_string(),
+                    });
+                }
 
-            for (_variant, types) in variants {
-                for t_name in types {
-                    if ctx.globals.type_map.get_id(t_name).is_none() {
-                        return Err(TypeError::Undefined {
-                            span: t_name.span,
-                            kind: "type",
-                            item: t_name.to_string(),
+                for (i, param) in params.iter().enumerate() {
+                    if params[..i].iter().any(|p| p.value == param.value) {
+                        return Err(TypeError::AlreadyDefined {
+                            span: param.span,
+                            ident: param.to_string(),
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    for create_type in create {
+        match create_type {
+            CreateType::Variant {
+                params, variants, ..
+            } => {
+                for (_variant, types) in variants {
+                    for t in types {
+                        check_type_ref(t, params, &group, ctx)?;
+                        check_regular_recursion(t, params, &group)?;
+                    }
+                }
+            }
+            CreateType::Record { fields, .. } => {
+                for (i, (field, t)) in fields.iter().enumerate() {
+                    if fields[..i].iter().any(|(f, _)| f.value == field.value) {
+                        return Err(TypeError::AlreadyDefined {
+                            span: field.span,
+                            ident: field.to_string(),
                         });
                     }
+                    check_type_ref(t, &[], &group, ctx)?;
                 }
             }
         }
@@ -560,9 +1356,82 @@ fn check_create_type<T: TTable>(
     Ok(())
 }
 
+/// Check that a type exists, and that it is given the right number of type arguments
+///
+/// `params` are the type parameters in scope, and `group` maps the types created in the same
+/// statement to their number of type parameters.
+fn check_type_ref<T: TTable>(
+    t: &TypeRef,
+    params: &[Spanned<&str>],
+    group: &HashMap<&str, usize>,
+    ctx: &Context<T>,
+) -> Result<(), TypeError> {
+    let type_map = &ctx.globals.type_map;
+    let name = t.name.value;
+
+    let arity = if params.iter().any(|param| param.value == name) {
+        0
+    } else if let Some(&arity) = group.get(name) {
+        arity
+    } else if let Some(arity) = type_map.template_arity(name) {
+        arity
+    } else if type_map.get_id(name).is_some() {
+        0
+    } else {
+        return Err(TypeError::Undefined {
+            span: t.name.span,
+            kind: "type",
+            item: name.to_string(),
+        });
+    };
+
+    if arity != t.args.len() {
+        return Err(TypeError::InvalidCount {
+            span: t.name.span,
+            expected: arity,
+            actual: t.args.len(),
+        });
+    }
+
+    for arg in &t.args {
+        check_type_ref(arg, params, group, ctx)?;
+    }
+
+    Ok(())
+}
+
+/// Check that generic types created in the same statement don't refer to each other with nested
+/// type parameters, e.g. `Tree<Maybe<T>>`, since that would require infinitely many instances.
+fn check_regular_recursion(
+    t: &TypeRef,
+    params: &[Spanned<&str>],
+    group: &HashMap<&str, usize>,
+) -> Result<(), TypeError> {
+    fn has_params(t: &TypeRef, params: &[Spanned<&str>]) -> bool {
+        (t.args.is_empty() && params.iter().any(|param| param.value == t.name.value))
+            || t.args.iter().any(|arg| has_params(arg, params))
+    }
+
+    if group.contains_key(t.name.value)
+        && t.args
+            .iter()
+            .any(|arg| !arg.args.is_empty() && has_params(arg, params))
+    {
+        return Err(TypeError::NotSupported(
+            "Generic types which refer to each other with nested type parameters",
+        ));
+    }
+
+    for arg in &t.args {
+        check_regular_recursion(arg, params, group)?;
+    }
+
+    Ok(())
+}
+
 fn check_expr<'ast, T: TTable>(
     expr: &'ast Spanned<Expr<'ast>>,
-    ctx: &Context<T>,
+    ctx: &mut Context<T>,
 ) -> Result<DuckType<'ast>, TypeError> {
     let type_map = &ctx.globals.type_map;
     match &expr.value {
@@ -570,6 +1439,80 @@ fn check_expr<'ast, T: TTable>(
 
         Expr::Value(value) => type_of_value(&value, expr.span, type_map),
 
+        Expr::Sum(namespace, variant_name, args) => {
+            // The types of the arguments are checked against the types of the variant
+            // once we know which sum-type this constructor belongs to.
+            let arg_types: Vec<_> = args
+                .iter()
+                .map(|arg| check_expr(arg, ctx))
+                .collect::<Result<_, _>>()?;
+            if let Some(namespace) = namespace {
+                if type_map.get_template(namespace).is_some() {
+                    // The type arguments are inferred, or given by where the value is used
+                    let instance =
+                        infer_instance(namespace, variant_name, &arg_types, expr.span, type_map)?;
+                    return Ok(match instance {
+                        Some(type_id) => DuckType::Concrete(type_id),
+                        None => DuckType::Variant(variant_name, arg_types),
+                    });
+                }
+            }
+            let variant = DuckType::Variant(variant_name, arg_types);
+
+            if let Some(namespace) = namespace {
+                let type_id = type_map
+                    .get_id(namespace)
+                    .ok_or_else(|| TypeError::Undefined {
+                        span: expr.span,
+                        kind: "type",
+                        item: namespace.to_string(),
+                    })?;
+
+                assert_type_as(variant, type_id, expr.span, type_map)?;
+
+                Ok(DuckType::Concrete(type_id))
+            } else {
+                Ok(variant)
+            }
+        }
+
+        Expr::Record(fields) => {
+            // Like variants, the fields are checked once we know which record type this is
+            let field_types = fields
+                .iter()
+                .map(|(name, value)| Ok((name, check_expr(value, ctx)?)))
+                .collect::<Result<_, _>>()?;
+            Ok(DuckType::Record(field_types))
+        }
+
+        Expr::Field(box record, field) => {
+            if let Some(column) = ctx.search_qualified(record, field) {
+                return Ok(column?.into());
+            }
+
+            match check_expr(record, ctx)? {
+                DuckType::Concrete(type_id) => match &type_map[&type_id] {
+                    Type::Product(fields) => fields
+                        .iter()
+                        .find(|(name, _)| name == field.value)
+                        .map(|(_, t)| DuckType::Concrete(*t))
+                        .ok_or_else(|| TypeError::Undefined {
+                            span: field.span,
+                            kind: "field",
+                            item: field.to_string(),
+                        }),
+                    _ => Err(TypeError::Undefined {
+                        span: field.span,
+                        kind: "field",
+                        item: field.to_string(),
+                    }),
+                },
+                _ => Err(TypeError::NotSupported(
+                    "Accessing fields of untyped records",
+                )),
+            }
+        }
+
         // All types are currently Eq and Ord
         Expr::Eql(box (e1, e2))
         | Expr::NEq(box (e1, e2))
@@ -595,6 +1538,222 @@ fn check_expr<'ast, T: TTable>(
 
             Ok(bool_id.into())
         }
+
+        Expr::Not(box value) => {
+            let value_type = check_expr(value, ctx)?;
+            let bool_id = type_map.get_base_id(BaseType::Bool);
+            assert_type_as(value_type, bool_id, value.span, type_map)?;
+
+            Ok(bool_id.into())
+        }
+
+        // Both sides must have the same type, integers and doubles are never mixed implicitly
+        Expr::Add(box (e1, e2))
+        | Expr::Sub(box (e1, e2))
+        | Expr::Mul(box (e1, e2))
+        | Expr::Div(box (e1, e2))
+        | Expr::Rem(box (e1, e2)) => {
+            let type_1 = check_expr(e1, ctx)?;
+            let type_2 = check_expr(e2, ctx)?;
+            let type_1 = assert_numeric(type_1, e1.span, type_map)?;
+            let type_2 = assert_numeric(type_2, e2.span, type_map)?;
+            assert_type_eq(type_1, type_2, expr.span, type_map)
+        }
+
+        Expr::Neg(box value) => {
+            let value_type = check_expr(value, ctx)?;
+            Ok(assert_numeric(value_type, value.span, type_map)?.into())
+        }
+
+        Expr::Cast(box value, target) => {
+            let value_type = check_expr(value, ctx)?;
+            assert_numeric(value_type, value.span, type_map)?;
+
+            let target_id = type_map
+                .get_id(target)
+                .ok_or_else(|| TypeError::Undefined {
+                    span: target.span,
+                    kind: "type",
+                    item: target.to_string(),
+                })?;
+            assert_numeric(target_id, target.span, type_map)?;
+
+            Ok(target_id.into())
+        }
+
+        Expr::Match(box value, arms) => {
+            let value_type = check_expr(value, ctx)?;
+            let type_id = infer_type(value_type, None, value.span, type_map)?;
+
+            // Aggregate functions are evaluated for every row, not only the rows which match
+            let grouping = std::mem::replace(&mut ctx.aggregates, Grouping::None);
+
+            let mut arm_types = vec![];
+            for (pattern, arm) in arms {
+                // Variables bound by the pattern are only in scope in its arm
+                ctx.push_locals_scope();
+                check_pattern(pattern, type_id, ctx)?;
+                let arm_type = check_expr(arm, ctx)?;
+                ctx.pop_locals_scope();
+                arm_types.push((arm_type, arm.span));
+            }
+            ctx.aggregates = grouping;
+
+            let patterns: Vec<_> = arms.iter().map(|(pattern, _)| pattern).collect();
+            let type_map = &ctx.globals.type_map;
+            for warning in pattern_analysis::check_arms(&patterns, expr.span, type_id, type_map) {
+                match warning {
+                    // There is no value to evaluate to if none of the arms match
+                    PatternWarning::NonExhaustive { span, missing } => {
+                        return Err(TypeError::NonExhaustive { span, missing })
+                    }
+                    warning => ctx.warnings.push(warning),
+                }
+            }
+
+            // All arms must have the same type. If none of them has a known type, e.g. if they
+            // are all constructors, the type is given by where the match is used.
+            let known_type = arm_types.iter().find_map(|(arm_type, _)| match arm_type {
+                DuckType::Concrete(type_id) => Some(*type_id),
+                _ => None,
+            });
+            match known_type {
+                Some(type_id) => {
+                    for (arm_type, span) in arm_types {
+                        assert_type_as(arm_type, type_id, span, type_map)?;
+                    }
+                    Ok(type_id.into())
+                }
+                None => Ok(DuckType::OneOf(arm_types)),
+            }
+        }
+
+        Expr::Tag(box value) => match check_expr(value, ctx)? {
+            DuckType::Concrete(type_id) => match &type_map[&type_id] {
+                Type::Sum(_) => Ok(type_map.get_base_id(BaseType::String).into()),
+                _ => Err(TypeError::InvalidType {
+                    span: value.span,
+                    expected: "sum type".to_string(),
+                    actual: type_map.get_name(type_id).unwrap().to_string(),
+                }),
+            },
+            _ => Err(TypeError::NotSupported("Tags of values of unknown types")),
+        },
+
+        Expr::Aggregate(function, arg) => {
+            let grouping = ctx.aggregates;
+            if grouping == Grouping::None {
+                return Err(TypeError::Misplaced {
+                    span: expr.span,
+                    item: "aggregate function",
+                });
+            }
+
+            // Aggregate functions can't be nested
+            ctx.aggregates = Grouping::None;
+            let arg_type = arg.as_ref().map(|arg| check_expr(arg, ctx)).transpose();
+            ctx.aggregates = grouping;
+
+            let type_map = &ctx.globals.type_map;
+            let arg_type = match (arg_type?, arg) {
+                (None, _) => None,
+                (Some(DuckType::Concrete(type_id)), _) => Some(type_id),
+                (Some(_), _) => {
+                    return Err(TypeError::NotSupported(
+                        "Aggregating values of unknown types",
+                    ))
+                }
+            };
+
+            let integer_id = type_map.get_base_id(BaseType::Integer);
+            let double_id = type_map.get_base_id(BaseType::Double);
+            let arg_span = arg.as_ref().and_then(|arg| arg.span);
+
+            // Without a group-by clause there may be no rows, and then there is no minimum,
+            // maximum or average.
+            let optional_if_empty = |type_id| match grouping {
+                Grouping::GroupBy => Ok(type_id),
+                _ => optional_of(type_id, type_map),
+            };
+
+            match (function, arg_type) {
+                (Aggregate::Count, _) => Ok(integer_id.into()),
+                (Aggregate::Sum, Some(t)) | (Aggregate::Avg, Some(t))
+                    if t == integer_id || t == double_id =>
+                {
+                    if *function == Aggregate::Sum {
+                        Ok(t.into())
+                    } else {
+                        Ok(optional_if_empty(double_id)?.into())
+                    }
+                }
+                (Aggregate::Sum, Some(t)) | (Aggregate::Avg, Some(t)) => {
+                    Err(TypeError::InvalidType {
+                        span: arg_span,
+                        expected: "Integer or Double".to_string(),
+                        actual: type_map.get_name(t).unwrap().to_string(),
+                    })
+                }
+                // Values of every type are ordered, like by ORDER BY
+                (Aggregate::Min, Some(t)) | (Aggregate::Max, Some(t)) => {
+                    Ok(optional_if_empty(t)?.into())
+                }
+                (_, None) => unreachable!("Only COUNT(*) has no argument"),
+            }
+        }
+
+        Expr::In(box value, box select) => {
+            let value_type = check_expr(value, ctx)?;
+            let types = check_subquery(select, ctx)?;
+            let item_type = single_value(&types, expr.span)?;
+
+            let type_map = &ctx.globals.type_map;
+            assert_type_as(value_type, item_type, value.span, type_map)?;
+            Ok(type_map.get_base_id(BaseType::Bool).into())
+        }
+
+        Expr::Exists(box select) => {
+            check_subquery(select, ctx)?;
+            Ok(ctx.globals.type_map.get_base_id(BaseType::Bool).into())
+        }
+
+        Expr::Subquery(box select) => {
+            let types = check_subquery(select, ctx)?;
+            let type_id = single_value(&types, expr.span)?;
+
+            // The subquery may not select any rows
+            if is_single_row(select) {
+                Ok(type_id.into())
+            } else {
+                Ok(optional_of(type_id, &ctx.globals.type_map)?.into())
+            }
+        }
+    }
+}
+
+/// Check a subquery, which may refer to the columns and variables in scope where it is used
+fn check_subquery<'ast, T: TTable>(
+    select: &'ast Select<'ast>,
+    ctx: &mut Context<T>,
+) -> Result<Vec<TypeId>, TypeError> {
+    // Aggregate functions in the subquery are computed over the rows of the subquery
+    let grouping = std::mem::replace(&mut ctx.aggregates, Grouping::None);
+    ctx.push_locals_scope();
+    let types = check_select(select, None, ctx);
+    ctx.pop_locals_scope();
+    ctx.aggregates = grouping;
+    types
+}
+
+/// Get the type of the value selected by a subquery which must select a single value
+fn single_value(types: &[TypeId], span: Option<Span>) -> Result<TypeId, TypeError> {
+    match types {
+        &[type_id] => Ok(type_id),
+        _ => Err(TypeError::InvalidCount {
+            span,
+            expected: 1,
+            actual: types.len(),
+        }),
     }
 }
 
@@ -619,15 +1778,246 @@ where
                     type_2: type_map.get_name(type_2).unwrap().to_string(),
                 });
             }
+            Ok(Concrete(type_1))
+        }
+        (Concrete(concrete_type), duck @ Variant(_, _))
+        | (duck @ Variant(_, _), Concrete(concrete_type))
+        | (Concrete(concrete_type), duck @ Record(_))
+        | (duck @ Record(_), Concrete(concrete_type)) => {
+            assert_type_as(duck, concrete_type, span, type_map).map(Into::into)
         }
-        (Concrete(concrete_type), variant @ Variant(_, _))
-        | (variant @ Variant(_, _), Concrete(concrete_type)) => {
-            return assert_type_as(variant, concrete_type, span, type_map).map(Into::into);
+        (Concrete(concrete_type), duck @ OneOf(_)) | (duck @ OneOf(_), Concrete(concrete_type)) => {
+            assert_type_as(duck, concrete_type, span, type_map).map(Into::into)
         }
-        (_, _) => unimplemented!("Comparing, duck-types"),
+        // Values of unknown types, e.g. two constructors, have the type of either of them
+        (type_1, type_2) => {
+            let type_id = infer_type(type_1.clone(), None, span, type_map)
+                .or_else(|_| infer_type(type_2.clone(), None, span, type_map))?;
+            assert_type_as(type_1, type_id, span, type_map)?;
+            assert_type_as(type_2, type_id, span, type_map)?;
+            Ok(Concrete(type_id))
+        }
+    }
+}
+
+/// Find the type of a value, e.g. the sum-type of a constructor
+///
+/// If the value must have a type where it is used, it is checked against it. Otherwise the type
+/// is inferred from the names of its constructors or fields, which must only match one type.
+pub fn infer_type(
+    actual: DuckType,
+    expected: Option<TypeId>,
+    span: Option<Span>,
+    type_map: &TypeMap,
+) -> Result<TypeId, TypeError> {
+    if let Some(expected) = expected {
+        return assert_type_as(actual, expected, span, type_map);
     }
 
-    Ok(type_1)
+    let (name, candidates): (String, Vec<TypeId>) = match &actual {
+        DuckType::Concrete(type_id) => return Ok(*type_id),
+        DuckType::Variant(variant, members) => {
+            return infer_variant_type(variant, members, span, type_map)
+        }
+        DuckType::Record(fields) => {
+            let names: Vec<&str> = fields.iter().map(|(name, _)| name.value).collect();
+            let candidates = type_map
+                .types()
+                .iter()
+                .filter(|(_, t)| match t {
+                    Type::Product(field_types) => {
+                        field_types.len() == names.len()
+                            && field_types
+                                .iter()
+                                .all(|(name, _)| names.contains(&&name[..]))
+                    }
+                    _ => false,
+                })
+                .map(|(&type_id, _)| type_id)
+                .collect();
+            (format!("{{ {} }}", names.join(", ")), candidates)
+        }
+        DuckType::OneOf(arms) => {
+            // The type of any of the arms is the type of all of them
+            let mut error = None;
+            for (arm, arm_span) in arms {
+                match infer_type(arm.clone(), None, *arm_span, type_map) {
+                    Ok(type_id) => return assert_type_as(actual, type_id, span, type_map),
+                    Err(e) => {
+                        error.get_or_insert(e);
+                    }
+                }
+            }
+            return Err(error.expect("Match without arms"));
+        }
+    };
+
+    // Types are compared by id so that the errors don't depend on the order of the hashmaps
+    let mut candidates = candidates;
+    candidates.sort_unstable();
+
+    let mut matching = candidates
+        .iter()
+        .filter(|&&type_id| assert_type_as(actual.clone(), type_id, span, type_map).is_ok());
+    match (matching.next(), matching.next()) {
+        (Some(&type_id), None) => Ok(type_id),
+        (Some(_), Some(_)) => Err(TypeError::AmbiguousReference { span, ident: name }),
+
+        // Report why the value doesn't have the type it is most likely to have
+        (None, _) => match candidates.first() {
+            Some(&type_id) => assert_type_as(actual, type_id, span, type_map),
+            None => Err(TypeError::Undefined {
+                span,
+                kind: "record type",
+                item: name,
+            }),
+        },
+    }
+}
+
+/// Find the sum-type of a constructor, which may be an instance of a generic type
+///
+/// The type arguments of generic types are inferred from the arguments of the constructor, so
+/// the instance doesn't have to have been created yet. The constructor must only match one type.
+fn infer_variant_type<'ast>(
+    variant: &'ast str,
+    members: &[DuckType<'ast>],
+    span: Option<Span>,
+    type_map: &TypeMap,
+) -> Result<TypeId, TypeError> {
+    let actual = DuckType::Variant(variant, members.to_vec());
+
+    // Instances are found through their generic types instead, since they may not exist yet.
+    // Types are compared by id so that the errors don't depend on the order of the hashmaps.
+    let mut candidates: Vec<TypeId> = type_map
+        .constructors_of(variant)
+        .into_iter()
+        .flatten()
+        .copied()
+        .filter(|&type_id| type_map.template_of(type_id).is_none())
+        .collect();
+    candidates.sort_unstable();
+
+    let mut matching: Vec<Result<TypeId, TypeError>> = candidates
+        .iter()
+        .filter(|&&type_id| assert_type_as(actual.clone(), type_id, span, type_map).is_ok())
+        .map(|&type_id| Ok(type_id))
+        .collect();
+    let mut undetermined = 0;
+    let mut error = None;
+    for (template, _) in type_map.templates_with_constructor(variant) {
+        match infer_instance(template, variant, members, span, type_map) {
+            Ok(Some(type_id)) => matching.push(Ok(type_id)),
+            Ok(None) => undetermined += 1,
+            Err(e @ TypeError::MissingInstance(..)) => matching.push(Err(e)),
+            Err(e) => {
+                error.get_or_insert(e);
+            }
+        }
+    }
+
+    match (matching.len() + undetermined, candidates.first(), error) {
+        // The type arguments of a generic type can't be inferred from e.g. `Nothing()` alone
+        (1, _, _) if undetermined == 0 => matching.pop().unwrap(),
+        (n, _, _) if n > 0 => Err(TypeError::AmbiguousReference {
+            span,
+            ident: variant.to_string(),
+        }),
+
+        // Report why the value doesn't have the type it is most likely to have
+        (_, Some(&type_id), _) => assert_type_as(actual, type_id, span, type_map),
+        (_, None, Some(e)) => Err(e),
+        (_, None, None) => Err(TypeError::Undefined {
+            span,
+            kind: "constructor",
+            item: variant.to_string(),
+        }),
+    }
+}
+
+/// Find the instance of a generic type which a constructor has, e.g. Maybe<Integer> for Just(1)
+///
+/// The type arguments are inferred from the arguments of the constructor. Returns `None` if they
+/// can't be, e.g. for `Nothing()`, and `MissingInstance` if the instance hasn't been created yet.
+fn infer_instance<'ast>(
+    template_name: &str,
+    variant: &'ast str,
+    members: &[DuckType<'ast>],
+    span: Option<Span>,
+    type_map: &TypeMap,
+) -> Result<Option<TypeId>, TypeError> {
+    let template = type_map
+        .get_template(template_name)
+        .expect("Generic type is missing");
+    let (_, member_types) = template
+        .variants
+        .iter()
+        .find(|(name, _)| name == variant)
+        .ok_or_else(|| TypeError::Undefined {
+            span,
+            kind: "constructor",
+            item: variant.to_string(),
+        })?;
+
+    if member_types.len() != members.len() {
+        return Err(TypeError::InvalidCount {
+            span,
+            expected: member_types.len(),
+            actual: members.len(),
+        });
+    }
+
+    let mut args: Vec<Option<TypeId>> = vec![None; template.params];
+    for (member_type, member) in member_types.iter().zip(members) {
+        if let TemplateType::Param(i) = member_type {
+            args[*i] = Some(infer_type(member.clone(), args[*i], span, type_map)?);
+        }
+    }
+
+    let args: Vec<TypeId> = match args.into_iter().collect() {
+        Some(args) => args,
+        None => return Ok(None),
+    };
+    match type_map.get_instance(template_name, &args) {
+        Some(type_id) => {
+            let actual = DuckType::Variant(variant, members.to_vec());
+            assert_type_as(actual, type_id, span, type_map).map(Some)
+        }
+        None => Err(TypeError::MissingInstance(template_name.to_string(), args)),
+    }
+}
+
+/// Get the optional type of a type, which may not have been created yet
+fn optional_of(type_id: TypeId, type_map: &TypeMap) -> Result<TypeId, TypeError> {
+    type_map
+        .optional_of(type_id)
+        .ok_or_else(|| TypeError::MissingInstance(OPTIONAL.to_string(), vec![type_id]))
+}
+
+/// Check that a value is an integer or a double, which are the types arithmetic works on
+fn assert_numeric<'ast, T>(
+    actual: T,
+    span: Option<Span>,
+    type_map: &TypeMap,
+) -> Result<TypeId, TypeError>
+where
+    T: Into<DuckType<'ast>>,
+{
+    let expected = "Integer or Double".to_string();
+    match actual.into() {
+        DuckType::Concrete(type_id)
+            if type_id == type_map.get_base_id(BaseType::Integer)
+                || type_id == type_map.get_base_id(BaseType::Double) =>
+        {
+            Ok(type_id)
+        }
+        DuckType::Concrete(type_id) => Err(TypeError::InvalidType {
+            span,
+            expected,
+            actual: type_map.get_name(type_id).unwrap().to_string(),
+        }),
+        _ => Err(TypeError::InvalidUnknownType { span, expected }),
+    }
 }
 
 fn assert_type_as<'ast, T>(
@@ -670,9 +2060,51 @@ where
                     });
                 }
 
-                for (sub_type, sub_value) in sub_types.iter().zip(sub_values.iter()) {
-                    let vt = type_of_value(sub_value, span, type_map)?;
-                    assert_type_as(vt, *sub_type, span, type_map)?;
+                for (sub_type, sub_value) in sub_types.iter().zip(sub_values.into_iter()) {
+                    assert_type_as(sub_value, *sub_type, span, type_map)?;
+                }
+            } else {
+                return Err(TypeError::InvalidUnknownType {
+                    span,
+                    expected: type_map.get_name(expected).unwrap().to_string(),
+                });
+            }
+        }
+        DuckType::Record(values) => {
+            let t = type_map.get_by_id(expected);
+
+            if let Type::Product(fields) = t {
+                for (i, (name, value)) in values.iter().enumerate() {
+                    if values[..i]
+                        .iter()
+                        .any(|(other, _)| other.value == name.value)
+                    {
+                        return Err(TypeError::AlreadyDefined {
+                            span: name.span,
+                            ident: name.to_string(),
+                        });
+                    }
+
+                    let (_, field_type) = fields
+                        .iter()
+                        .find(|(field, _)| field == name.value)
+                        .ok_or_else(|| TypeError::Undefined {
+                            span: name.span,
+                            kind: "field",
+                            item: name.to_string(),
+                        })?;
+
+                    assert_type_as(value.clone(), *field_type, span, type_map)?;
+                }
+
+                // Every field must be given a value
+                for (field, _) in fields {
+                    if !values.iter().any(|(name, _)| name.value == field) {
+                        return Err(TypeError::MissingField {
+                            span,
+                            name: field.to_string(),
+                        });
+                    }
                 }
             } else {
                 return Err(TypeError::InvalidUnknownType {
@@ -681,11 +2113,63 @@ where
                 });
             }
         }
+        DuckType::OneOf(values) => {
+            for (value, value_span) in values {
+                assert_type_as(value, expected, value_span.or(span), type_map)?;
+            }
+        }
     }
 
     Ok(expected)
 }
 
+/// Get the types of expressions evaluated over rows with the given columns
+///
+/// Used when the result of a query is computed, rather than read from a table. `expected` are
+/// the types the values must have, like in [check_select](fn.check_select.html).
+pub fn type_of_exprs<'ast, T: TTable>(
+    exprs: impl IntoIterator<Item = &'ast Spanned<Expr<'ast>>>,
+    expected: Option<&[TypeId]>,
+    columns: &[(Option<&str>, &str, TypeId)],
+    outer: &[(Option<&str>, &str, TypeId)],
+    grouping: Grouping,
+    globals: &'ast ResourcesGuard<'ast, T>,
+) -> Result<Vec<TypeId>, TypeError> {
+    let mut ctx = Context::new(globals);
+    ctx.aggregates = grouping;
+
+    // The columns of enclosing queries are in order of lookup, and are shadowed by the columns
+    for &(table, name, type_id) in outer.iter().rev() {
+        ctx.push_locals_scope();
+        ctx.push_column(table, name, type_id);
+    }
+    ctx.push_locals_scope();
+    for &(table, name, type_id) in columns {
+        ctx.push_column(table, name, type_id);
+    }
+
+    exprs
+        .into_iter()
+        .enumerate()
+        .map(|(i, expr)| {
+            let t = check_expr(expr, &mut ctx)?;
+            let expected = expected.map(|expected| expected[i]);
+            infer_type(t, expected, expr.span, &globals.type_map)
+        })
+        .collect()
+}
+
+/// Get the types of the values selected by a select
+///
+/// Used when a view is created, since the types of its columns are stored with it.
+pub fn type_of_select<'ast, T: TTable>(
+    select: &'ast Select<'ast>,
+    globals: &'ast ResourcesGuard<'ast, T>,
+) -> Result<Vec<TypeId>, TypeError> {
+    let mut ctx = Context::new(globals);
+    check_select(select, None, &mut ctx)
+}
+
 pub fn type_of_value<'ast>(
     value: &'ast Value<'ast>,
     span: Option<Span>,
@@ -696,28 +2180,18 @@ pub fn type_of_value<'ast>(
         Value::Integer(_) => Ok(types.get_base_id(BaseType::Integer).into()),
         Value::Double(_) => Ok(types.get_base_id(BaseType::Double).into()),
         Value::Bool(_) => Ok(types.get_base_id(BaseType::Bool).into()),
-        Value::Sum(namespace, variant_name, sub_values) => {
-            if let Some(namespace) = namespace {
-                let type_id = types
-                    .get_id(namespace)
-                    .ok_or_else(|| TypeError::Undefined {
-                        span,
-                        kind: "type",
-                        item: namespace.to_string(),
-                    })?;
-
-                assert_type_as(
-                    DuckType::Variant(variant_name, sub_values),
-                    type_id,
-                    span,
-                    types,
-                )?;
-
-                Ok(DuckType::Concrete(type_id))
-            } else {
-                Ok(DuckType::Variant(variant_name, sub_values))
-            }
-        }
+        Value::Str(_) => Ok(types.get_base_id(BaseType::String).into()),
+        // Sum-type literals are parsed as Expr::Sum, so we only expect to see evaluated values here
+        Value::Sum(Some(namespace), _, _) => types
+            .get_id(namespace)
+            .map(DuckType::Concrete)
+            .ok_or_else(|| TypeError::Undefined {
+                span,
+                kind: "type",
+                item: namespace.to_string(),
+            }),
+        Value::Sum(None, _, _) => Err(TypeError::NotSupported("untyped sum-type values")),
+        Value::Product(_) => Err(TypeError::NotSupported("untyped record values")),
     }
 }
 
@@ -736,12 +2210,16 @@ mod tests {
         let (_ids, type_map) = create_type_map();
         let type_map = Arc::new(RwLock::new(type_map));
 
-        let dummy_ctx: Context<Table> = Context {
+        let mut dummy_ctx: Context<Table> = Context {
             globals: &ResourcesGuard {
                 type_map: Resource::Read(block_on(type_map.read())),
                 tables: vec![],
+                views: vec![],
             },
             locals: vec![],
+            warnings: vec![],
+            aggregates: Grouping::None,
+            tables: vec![],
         };
 
         let valid_examples = vec![
@@ -779,11 +2257,11 @@ mod tests {
         ];
 
         for example in valid_examples {
-            check_expr(&example.into(), &dummy_ctx).unwrap();
+            check_expr(&example.into(), &mut dummy_ctx).unwrap();
         }
 
         for example in invalid_examples {
-            check_expr(&example.into(), &dummy_ctx).unwrap_err();
+            check_expr(&example.into(), &mut dummy_ctx).unwrap_err();
         }
     }
 }
@@ -4,7 +4,7 @@ use std::borrow::Cow;
 use std::char;
 use std::cmp;
 use std::cmp::Ordering;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::{self, Display, Formatter};
 use std::io::Write;
 use std::mem::size_of;
@@ -13,16 +13,40 @@ use std::ops::Index;
 pub type EnumTag = usize;
 pub type TypeId = usize;
 
+/// A reference to a value in the heap of a table, stored as (offset, length) in bytes.
+pub type HeapRef = (usize, usize);
+
+/// The variants of the built-in optional types
+pub const OPTIONAL_NONE: &str = "None";
+pub const OPTIONAL_SOME: &str = "Some";
+pub const OPTIONAL_NONE_TAG: EnumTag = 0;
+pub const OPTIONAL_SOME_TAG: EnumTag = 1;
+
+/// The name of the built-in generic optional type
+pub const OPTIONAL: &str = "Optional";
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct TypeMap {
     types: HashMap<TypeId, Type>,
     identifiers: HashMap<String, TypeId>,
     constructors: HashMap<String, Vec<TypeId>>,
+
+    /// Maps types to their optional types, e.g. Integer -> Optional<Integer>.
+    /// Optional types are only created when they are needed, e.g. by an outer join.
+    optionals: HashMap<TypeId, TypeId>,
+
+    /// Types which refer to themselves, directly or through other types.
+    /// These are stored in the heap when they are members of sum-types.
+    recursive: HashSet<TypeId>,
+
+    /// Generic types, which are instantiated when they are given type arguments
+    templates: HashMap<String, Template>,
     next_id: TypeId,
     bool_id: TypeId,
     integer_id: TypeId,
     double_id: TypeId,
     char_id: TypeId,
+    string_id: TypeId,
 }
 
 pub enum BaseType {
@@ -30,6 +54,7 @@ pub enum BaseType {
     Integer,
     Double,
     Char,
+    String,
 }
 
 impl Index<&TypeId> for TypeMap {
@@ -46,17 +71,34 @@ impl TypeMap {
             types: HashMap::new(),
             identifiers: HashMap::new(),
             constructors: HashMap::new(),
+            optionals: HashMap::new(),
+            recursive: HashSet::new(),
+            templates: HashMap::new(),
             next_id: 1,
             integer_id: 0,
             double_id: 0,
             bool_id: 0,
             char_id: 0,
+            string_id: 0,
         };
 
         map.integer_id = map.insert("Integer", Type::Integer);
         map.double_id = map.insert("Double", Type::Double);
         map.bool_id = map.insert("Bool", Type::Bool);
         map.char_id = map.insert("Char", Type::Char);
+        map.string_id = map.insert("String", Type::String);
+
+        // Optional types are instances of a built-in generic type, e.g. Optional<Integer>
+        map.templates.insert(
+            OPTIONAL.into(),
+            Template {
+                params: 1,
+                variants: vec![
+                    (OPTIONAL_NONE.into(), vec![]),
+                    (OPTIONAL_SOME.into(), vec![TemplateType::Param(0)]),
+                ],
+            },
+        );
         map
     }
 
@@ -68,12 +110,243 @@ impl TypeMap {
         let id = self.next_id;
         self.next_id += 1;
 
+        self.insert_constructors(id, &t);
         self.types.insert(id, t);
         self.identifiers.insert(name.into(), id);
 
         id
     }
 
+    /// Declare a type without defining it, so that other types can refer to it
+    ///
+    /// The type must be defined using [define](#method.define) before it is used.
+    pub fn declare<I: Into<String>>(&mut self, name: I) -> TypeId {
+        self.insert(name, Type::Sum(vec![]))
+    }
+
+    /// Define a type which has been declared
+    pub fn define(&mut self, id: TypeId, t: Type) {
+        self.insert_constructors(id, &t);
+        self.types.insert(id, t);
+    }
+
+    /// Find the types in a group of newly defined types which refer to themselves
+    pub fn mark_recursive(&mut self, group: &[TypeId]) {
+        for &id in group {
+            // Types defined before the group can't refer to types in the group,
+            // so we only need to search through the group.
+            let mut visited = HashSet::new();
+            let mut stack = self[&id].members();
+            while let Some(t) = stack.pop() {
+                if t == id {
+                    self.recursive.insert(id);
+                    break;
+                }
+                if group.contains(&t) && visited.insert(t) {
+                    stack.extend(self[&t].members());
+                }
+            }
+        }
+    }
+
+    /// Add a variant to a sum-type, after its other variants
+    ///
+    /// The tags of the other variants are unchanged, but the size of the type may change, and so
+    /// may the sizes of the types which use it.
+    pub fn add_variant(&mut self, id: TypeId, name: String, members: Vec<TypeId>) {
+        self.constructors.entry(name.clone()).or_default().push(id);
+        match self.types.get_mut(&id) {
+            Some(Type::Sum(variants)) => variants.push((name, members)),
+            t => panic!("Not a sum-type: {:?}", t),
+        }
+
+        // The type may refer to itself through the members of the new variant, and then so do
+        // the types in between
+        let users = self.users_of(id);
+        self.mark_recursive(&users);
+    }
+
+    /// Rename a variant of a sum-type
+    pub fn rename_variant(&mut self, id: TypeId, from: &str, to: &str) {
+        let variants = match self.types.get_mut(&id) {
+            Some(Type::Sum(variants)) => variants,
+            t => panic!("Not a sum-type: {:?}", t),
+        };
+        let (name, _) = variants
+            .iter_mut()
+            .find(|(name, _)| name == from)
+            .unwrap_or_else(|| panic!("No variant named: {}", from));
+        *name = to.to_string();
+
+        let types = self.constructors.get_mut(from).unwrap();
+        types.retain(|&t| t != id);
+        if types.is_empty() {
+            self.constructors.remove(from);
+        }
+        self.constructors
+            .entry(to.to_string())
+            .or_default()
+            .push(id);
+    }
+
+    /// Get the types which have values of a type as members, directly or through other types,
+    /// including the type itself
+    pub fn users_of(&self, id: TypeId) -> Vec<TypeId> {
+        let uses = |user: TypeId| {
+            let mut visited = HashSet::new();
+            let mut stack = vec![user];
+            while let Some(t) = stack.pop() {
+                if t == id {
+                    return true;
+                }
+                if visited.insert(t) {
+                    stack.extend(self[&t].members());
+                }
+            }
+            false
+        };
+        self.types.keys().copied().filter(|&t| uses(t)).collect()
+    }
+
+    /// Check whether values of a type are stored in the heap when they are members of other types
+    pub fn is_boxed(&self, id: TypeId) -> bool {
+        self.recursive.contains(&id)
+    }
+
+    /// Get the size of a type when it is a member of another type
+    pub fn member_size_of(&self, id: TypeId) -> usize {
+        if self.is_boxed(id) {
+            size_of::<HeapRef>()
+        } else {
+            self[&id].size_of(self)
+        }
+    }
+
+    /// The id which will be given to the next type which is created
+    pub fn next_id(&self) -> TypeId {
+        self.next_id
+    }
+
+    /// Add a generic type which can be instantiated with type arguments
+    pub fn insert_template<I: Into<String>>(&mut self, name: I, template: Template) {
+        self.templates.insert(name.into(), template);
+    }
+
+    /// Get the number of type parameters of a generic type
+    pub fn template_arity(&self, name: &str) -> Option<usize> {
+        self.templates.get(name).map(|template| template.params)
+    }
+
+    pub fn get_template(&self, name: &str) -> Option<&Template> {
+        self.templates.get(name)
+    }
+
+    /// Get the generic types which have a constructor with the given name, sorted by name
+    pub fn templates_with_constructor(&self, constructor: &str) -> Vec<(&str, &Template)> {
+        let mut templates: Vec<(&str, &Template)> = self
+            .templates
+            .iter()
+            .filter(|(_, template)| {
+                template
+                    .variants
+                    .iter()
+                    .any(|(variant, _)| variant == constructor)
+            })
+            .map(|(name, template)| (&name[..], template))
+            .collect();
+        templates.sort_unstable_by_key(|(name, _)| *name);
+        templates
+    }
+
+    /// Get the generic type which a type is an instance of, e.g. Maybe for Maybe<Integer>
+    pub fn template_of(&self, id: TypeId) -> Option<&str> {
+        // Only instances have names with type arguments
+        let name = self.get_name(id)?;
+        let (template, _) = self.templates.get_key_value(&name[..name.find('<')?])?;
+        Some(template)
+    }
+
+    /// Get the id of an instance of a generic type, e.g. Maybe<Integer>, creating it if needed
+    ///
+    /// The type arguments must be concrete types.
+    pub fn instantiate(&mut self, name: &str, args: &[TypeId]) -> TypeId {
+        let first_id = self.next_id;
+        let id = self.instantiate_inner(name, args);
+
+        // Instances may be recursive, e.g. List<Integer>
+        let created: Vec<TypeId> = (first_id..self.next_id).collect();
+        self.mark_recursive(&created);
+        id
+    }
+
+    /// Get the id of an instance of a generic type, if it has been created
+    pub fn get_instance(&self, name: &str, args: &[TypeId]) -> Option<TypeId> {
+        if name == OPTIONAL {
+            return self.optional_of(args[0]);
+        }
+        self.get_id(&self.instance_name(name, args))
+    }
+
+    fn instance_name(&self, name: &str, args: &[TypeId]) -> String {
+        let arg_names: Vec<&str> = args
+            .iter()
+            .map(|&arg| self.get_name(arg).expect("Type arguments must have names"))
+            .collect();
+        format!("{}<{}>", name, arg_names.join(", "))
+    }
+
+    fn instantiate_inner(&mut self, name: &str, args: &[TypeId]) -> TypeId {
+        if let Some(id) = self.get_instance(name, args) {
+            return id;
+        }
+
+        // The instance is declared first, since its members may refer back to it
+        let id = self.declare(self.instance_name(name, args));
+        let template = self
+            .templates
+            .get(name)
+            .unwrap_or_else(|| panic!("No generic type named: {}", name))
+            .clone();
+
+        let variants = template
+            .variants
+            .iter()
+            .map(|(constructor, members)| {
+                let members = members
+                    .iter()
+                    .map(|member| self.substitute(member, args))
+                    .collect();
+                (constructor.clone(), members)
+            })
+            .collect();
+
+        self.define(id, Type::Sum(variants));
+        if name == OPTIONAL {
+            self.optionals.insert(args[0], id);
+        }
+        id
+    }
+
+    /// Get the concrete type of a member of a generic type, given the type arguments
+    fn substitute(&mut self, t: &TemplateType, args: &[TypeId]) -> TypeId {
+        match t {
+            TemplateType::Param(i) => args[*i],
+            TemplateType::Concrete(id) => *id,
+            TemplateType::Instance(name, inner) => {
+                let inner: Vec<TypeId> = inner.iter().map(|t| self.substitute(t, args)).collect();
+                self.instantiate_inner(name, &inner)
+            }
+        }
+    }
+
+    fn insert_constructors(&mut self, id: TypeId, t: &Type) {
+        if let Type::Sum(variants) = t {
+            for (name, _) in variants {
+                self.constructors.entry(name.clone()).or_default().push(id);
+            }
+        }
+    }
+
     pub fn get_id(&self, name: &str) -> Option<TypeId> {
         self.identifiers.get(name).map(|id| *id)
     }
@@ -84,6 +357,7 @@ impl TypeMap {
             BaseType::Integer => self.integer_id,
             BaseType::Double => self.double_id,
             BaseType::Char => self.char_id,
+            BaseType::String => self.string_id,
         }
     }
 
@@ -115,9 +389,51 @@ impl TypeMap {
         &self.identifiers
     }
 
+    /// Get the sum-types which have a constructor with the given name
     pub fn constructors_of(&self, name: &str) -> Option<&Vec<TypeId>> {
         self.constructors.get(name)
     }
+
+    /// Get the optional type of a type, if it has been created
+    ///
+    /// Optional types are created with [instantiate](#method.instantiate), and they may be
+    /// nested, e.g. Optional<Optional<Integer>>.
+    pub fn optional_of(&self, id: TypeId) -> Option<TypeId> {
+        self.optionals.get(&id).copied()
+    }
+
+    pub fn is_optional(&self, id: TypeId) -> bool {
+        // The optional type of the member of `Some` is the type itself
+        match self.types.get(&id) {
+            Some(Type::Sum(variants)) => variants
+                .get(OPTIONAL_SOME_TAG as usize)
+                .and_then(|(_, members)| members.first())
+                .map(|&member| self.optional_of(member) == Some(id))
+                .unwrap_or(false),
+            _ => false,
+        }
+    }
+}
+
+/// A generic sum-type, e.g. Maybe<T>
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Template {
+    /// The number of type parameters
+    pub params: usize,
+    pub variants: Vec<(String, Vec<TemplateType>)>,
+}
+
+/// The type of a member of a generic sum-type
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum TemplateType {
+    /// The type parameter with the given index
+    Param(usize),
+
+    /// A type which does not depend on the type parameters
+    Concrete(TypeId),
+
+    /// An instance of a generic type, e.g. Maybe<T>
+    Instance(String, Vec<TemplateType>),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -126,7 +442,13 @@ pub enum Type {
     Double,
     Bool,
     Char,
+
+    /// Variable-length strings, stored in the heap of the table.
+    String,
     Sum(Vec<(String, Vec<TypeId>)>),
+
+    /// Records, i.e. product-types with named fields.
+    Product(Vec<(String, TypeId)>),
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
@@ -135,7 +457,11 @@ pub enum Value<'a> {
     Integer(i32),
     Double(f64),
     Bool(bool),
+    Str(Cow<'a, str>),
     Sum(Option<Cow<'a, str>>, Cow<'a, str>, Vec<Value<'a>>),
+
+    /// The fields of a record, sorted by name so that equal records are equal values
+    Product(Vec<(Cow<'a, str>, Value<'a>)>),
 }
 
 impl Display for Value<'_> {
@@ -145,33 +471,65 @@ impl Display for Value<'_> {
             Value::Integer(v) => write!(f, "{}", v),
             Value::Double(v) => write!(f, "{}", v),
             Value::Bool(v) => write!(f, "{}", v),
+            Value::Str(v) => write!(f, "{}", v),
             Value::Sum(namespace, variant, values) => {
                 if let Some(namespace) = namespace {
                     write!(f, "{}::", namespace)?;
                 }
                 write!(f, "{}(", variant)?;
-                if values.len() > 0 {
-                    for value in values.iter().take(values.len() - 1) {
-                        write!(f, "{}, ", value)?;
-                    }
-                    for value in values.last() {
-                        write!(f, "{}", value)?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
                     }
+                    value.fmt_member(f)?;
                 }
                 write!(f, ")")
             }
+            Value::Product(fields) => {
+                write!(f, "{{")?;
+                for (i, (name, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: ", name)?;
+                    value.fmt_member(f)?;
+                }
+                write!(f, "}}")
+            }
         }
     }
 }
 
 impl Value<'_> {
-    pub fn to_bytes<W: Write>(&self, writer: &mut W, types: &TypeMap, t: &Type) {
+    /// Format the value as a member of another value, where strings are quoted
+    fn fmt_member(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Value::Str(v) => write!(f, "{:?}", v),
+            _ => self.fmt(f),
+        }
+    }
+
+    /// Serialize a value into the fixed-width format used in tables
+    ///
+    /// Variable-length data, such as strings, is appended to `heap`.
+    pub fn to_bytes<W: Write>(
+        &self,
+        writer: &mut W,
+        heap: &mut Vec<u8>,
+        types: &TypeMap,
+        t: &Type,
+    ) {
         let size = t.size_of(types);
         match self {
             Value::Char(val) => serialize_into(writer, &u32::from(*val)).unwrap(),
             Value::Integer(val) => serialize_into(writer, val).unwrap(),
             Value::Double(val) => serialize_into(writer, val).unwrap(),
             Value::Bool(val) => serialize_into(writer, val).unwrap(),
+            Value::Str(val) => {
+                let heap_ref: HeapRef = (heap.len(), val.len());
+                heap.extend_from_slice(val.as_bytes());
+                serialize_into(writer, &heap_ref).unwrap();
+            }
             Value::Sum(type_name, variant, values) => {
                 if let Type::Sum(variants) = t {
                     let (tag, variant_types) = variants
@@ -186,8 +544,12 @@ impl Value<'_> {
 
                     for (v, t_id) in values.iter().zip(variant_types.iter()) {
                         let t = &types[t_id];
-                        bytes_written += t.size_of(types);
-                        v.to_bytes(writer, types, t);
+                        bytes_written += types.member_size_of(*t_id);
+                        if types.is_boxed(*t_id) {
+                            v.to_boxed_bytes(writer, heap, types, t);
+                        } else {
+                            v.to_bytes(writer, heap, types, t);
+                        }
                     }
 
                     // pad with 0:s for variants smaller than the largest variant
@@ -201,43 +563,224 @@ impl Value<'_> {
                     );
                 }
             }
+            Value::Product(values) => {
+                if let Type::Product(fields) = t {
+                    // Fields are written in the order they were declared in
+                    for (field, t_id) in fields {
+                        let (_, v) = values
+                            .iter()
+                            .find(|(name, _)| name == field)
+                            .unwrap_or_else(|| panic!("Missing field: {}", field));
+                        let t = &types[t_id];
+                        if types.is_boxed(*t_id) {
+                            v.to_boxed_bytes(writer, heap, types, t);
+                        } else {
+                            v.to_bytes(writer, heap, types, t);
+                        }
+                    }
+                } else {
+                    panic!("Not a product-type: {:?}\nIs actually: {:?}", values, t);
+                }
+            }
         }
     }
 }
 
-impl PartialOrd for Value<'_> {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+impl Value<'_> {
+    /// Serialize a value into the heap, and write a reference to it
+    pub fn to_boxed_bytes<W: Write>(
+        &self,
+        writer: &mut W,
+        heap: &mut Vec<u8>,
+        types: &TypeMap,
+        t: &Type,
+    ) {
+        let mut bytes = Vec::with_capacity(t.size_of(types));
+        self.to_bytes(&mut bytes, heap, types, t);
+
+        let heap_ref: HeapRef = (heap.len(), bytes.len());
+        heap.extend_from_slice(&bytes);
+        serialize_into(writer, &heap_ref).unwrap();
+    }
+
+    /// Compare two values of the same type
+    ///
+    /// This is the ordering used by comparisons and by ORDER BY:
+    /// - Integers, chars and strings are ordered as usual, and `false` comes before `true`.
+    /// - Doubles are ordered by value, and NaN comes after every other double.
+    /// - Values of sum-types are ordered by their constructors, in the order they were declared
+    ///   in. Values with the same constructor are ordered by their members, from left to right.
+    /// - Records are ordered by their fields, in the order of the field names.
+    ///
+    /// The order of the constructors of a sum-type comes from its type. If it is not given,
+    /// the namespaces of the values are used.
+    pub fn compare(&self, other: &Value, type_id: Option<TypeId>, types: &TypeMap) -> Ordering {
         match (self, other) {
-            (Value::Char(v1), Value::Char(v2)) => Some(v1.cmp(v2)),
-            (Value::Integer(v1), Value::Integer(v2)) => Some(v1.cmp(v2)),
-            (Value::Double(v1), Value::Double(v2)) => {
-                Some(v1.partial_cmp(v2).unwrap_or(Ordering::Greater))
+            (Value::Char(v1), Value::Char(v2)) => v1.cmp(v2),
+            (Value::Integer(v1), Value::Integer(v2)) => v1.cmp(v2),
+            (Value::Double(v1), Value::Double(v2)) => compare_doubles(*v1, *v2),
+            (Value::Bool(v1), Value::Bool(v2)) => v1.cmp(v2),
+            (Value::Str(v1), Value::Str(v2)) => v1.cmp(v2),
+            (
+                Value::Sum(namespace_1, variant_1, values_1),
+                Value::Sum(namespace_2, variant_2, values_2),
+            ) => {
+                let namespace = namespace_1.as_ref().or_else(|| namespace_2.as_ref());
+                let type_id =
+                    type_id.or_else(|| namespace.and_then(|namespace| types.get_id(namespace)));
+                let variants = match type_id.map(|type_id| &types[&type_id]) {
+                    Some(Type::Sum(variants)) => variants,
+                    _ => {
+                        // Instances of generic types may be named by their generic type, e.g.
+                        // `Maybe::Just(1)`, which has the constructors in the same order.
+                        // Otherwise, e.g. for constructors from different arms of a match, the
+                        // only order we know of is the one of the names.
+                        let template =
+                            namespace.and_then(|namespace| types.get_template(namespace));
+                        let tag = |variant: &str| {
+                            template.and_then(|template| {
+                                template
+                                    .variants
+                                    .iter()
+                                    .position(|(name, _)| name == variant)
+                            })
+                        };
+                        return tag(variant_1)
+                            .cmp(&tag(variant_2))
+                            .then_with(|| variant_1.cmp(variant_2))
+                            .then_with(|| compare_all(values_1, values_2, |_| None, types));
+                    }
+                };
+
+                let tag = |variant: &str| variants.iter().position(|(name, _)| name == variant);
+                let (tag_1, tag_2) = (tag(variant_1), tag(variant_2));
+                match tag_1.cmp(&tag_2) {
+                    Ordering::Equal => {
+                        let (_, members) = &variants[tag_1.expect("Constructor does not exist")];
+                        compare_all(values_1, values_2, |i| members.get(i).copied(), types)
+                    }
+                    not_equal => not_equal,
+                }
+            }
+            (Value::Product(fields_1), Value::Product(fields_2)) => {
+                let field_types = match type_id.map(|type_id| &types[&type_id]) {
+                    Some(Type::Product(field_types)) => &field_types[..],
+                    _ => &[],
+                };
+
+                // The fields of record values are already sorted by name
+                for ((name, v1), (_, v2)) in fields_1.iter().zip(fields_2.iter()) {
+                    let field_type = field_types
+                        .iter()
+                        .find(|(field, _)| field == name)
+                        .map(|(_, t)| *t);
+                    match v1.compare(v2, field_type, types) {
+                        Ordering::Equal => continue,
+                        not_equal => return not_equal,
+                    }
+                }
+                Ordering::Equal
             }
-            (Value::Bool(v1), Value::Bool(v2)) => Some(v1.cmp(v2)),
-            (Value::Sum(_, _, _), Value::Sum(_, _, _)) => unimplemented!("Ord for sum-types"),
-            (_, _) => None,
+            (v1, v2) => panic!("Comparing values of different types: {:?} and {:?}", v1, v2),
+        }
+    }
+
+    /// Check if two values of the same type are equal, which they are if neither is ordered
+    /// before the other by [compare](#method.compare)
+    ///
+    /// Unlike `==`, this doesn't depend on whether the constructors are named with their
+    /// namespaces, and doubles are compared by value, e.g. -0.0 is equal to 0.0.
+    pub fn equals(&self, other: &Value, types: &TypeMap) -> bool {
+        self.compare(other, None, types) == Ordering::Equal
+    }
+
+    /// Write a key for the value, which is the same for values which are equal
+    ///
+    /// Like [equals](#method.equals), the namespaces of constructors are left out, and doubles
+    /// which are equal have the same key, even if their bytes are not.
+    pub fn write_key<W: Write>(&self, writer: &mut W) {
+        match self {
+            Value::Double(v) if *v == 0.0 => serialize_into(writer, &0.0f64).unwrap(),
+            Value::Double(v) if v.is_nan() => serialize_into(writer, &f64::NAN).unwrap(),
+            Value::Sum(_, variant, values) => {
+                serialize_into(&mut *writer, variant).unwrap();
+                for value in values {
+                    value.write_key(writer);
+                }
+            }
+            Value::Product(fields) => {
+                for (_, value) in fields {
+                    value.write_key(writer);
+                }
+            }
+            value => serialize_into(writer, value).unwrap(),
         }
     }
 }
 
+/// Compare the values of two lists pairwise, from left to right
+fn compare_all<F>(values_1: &[Value], values_2: &[Value], type_of: F, types: &TypeMap) -> Ordering
+where
+    F: Fn(usize) -> Option<TypeId>,
+{
+    for (i, (v1, v2)) in values_1.iter().zip(values_2.iter()).enumerate() {
+        match v1.compare(v2, type_of(i), types) {
+            Ordering::Equal => continue,
+            not_equal => return not_equal,
+        }
+    }
+    Ordering::Equal
+}
+
+/// Compare two doubles, where NaN comes after every other double
+pub fn compare_doubles(d1: f64, d2: f64) -> Ordering {
+    d1.partial_cmp(&d2)
+        .unwrap_or_else(|| d1.is_nan().cmp(&d2.is_nan()))
+}
+
 impl Type {
+    /// The types of the members of the variants or fields of the type
+    pub fn members(&self) -> Vec<TypeId> {
+        match self {
+            Type::Sum(variants) => variants
+                .iter()
+                .flat_map(|(_, members)| members.iter().copied())
+                .collect(),
+            Type::Product(fields) => fields.iter().map(|(_, member)| *member).collect(),
+            _ => vec![],
+        }
+    }
+
     pub fn size_of(&self, types: &TypeMap) -> usize {
         match self {
             Type::Char => size_of::<char>(),
             Type::Integer => size_of::<i32>(),
             Type::Double => size_of::<f64>(),
             Type::Bool => size_of::<bool>(),
+            Type::String => size_of::<HeapRef>(),
             Type::Sum(variants) => {
                 size_of::<EnumTag>()
                     + variants
                         .iter()
-                        .map(|(_, ts)| ts.iter().map(|t_id| types[t_id].size_of(types)).sum())
+                        .map(|(_, ts)| ts.iter().map(|t_id| types.member_size_of(*t_id)).sum())
                         .fold(0, cmp::max)
             }
+            Type::Product(fields) => fields
+                .iter()
+                .map(|(_, t_id)| types.member_size_of(*t_id))
+                .sum(),
         }
     }
 
-    pub fn from_bytes(&self, mut bytes: &[u8], types: &TypeMap) -> bincode::Result<Value<'static>> {
+    /// Deserialize a value from the fixed-width format used in tables
+    ///
+    /// `heap` is the heap of the table which the bytes were read from.
+    pub fn from_bytes(
+        &self,
+        mut bytes: &[u8],
+        heap: &[u8],
+        types: &TypeMap,
+    ) -> bincode::Result<Value<'static>> {
         assert_eq!(self.size_of(types), bytes.len());
 
         match self {
@@ -245,6 +788,11 @@ impl Type {
             Type::Integer => deserialize(bytes).map(|v| Value::Integer(v)),
             Type::Bool => deserialize(bytes).map(|v| Value::Bool(v)),
             Type::Double => deserialize(bytes).map(|v| Value::Double(v)),
+            Type::String => {
+                let (offset, len): HeapRef = deserialize(bytes)?;
+                let s = String::from_utf8_lossy(&heap[offset..offset + len]);
+                Ok(Value::Str(Cow::Owned(s.into_owned())))
+            }
             Type::Sum(variants) => {
                 // parse enum tag
                 let tag_size = size_of::<EnumTag>();
@@ -257,8 +805,12 @@ impl Type {
                     .iter()
                     .map(|t_id| {
                         let t = &types[t_id];
-                        let t_size = t.size_of(types);
-                        let v = t.from_bytes(&bytes[..t_size], types);
+                        let t_size = types.member_size_of(*t_id);
+                        let v = if types.is_boxed(*t_id) {
+                            t.from_boxed_bytes(&bytes[..t_size], heap, types)
+                        } else {
+                            t.from_bytes(&bytes[..t_size], heap, types)
+                        };
                         bytes = &bytes[t_size..];
                         v
                     })
@@ -267,15 +819,50 @@ impl Type {
                 // TODO: Type name
                 Ok(Value::Sum(None, Cow::Owned(name.clone()), values))
             }
+            Type::Product(fields) => {
+                let mut values = fields
+                    .iter()
+                    .map(|(name, t_id)| {
+                        let t = &types[t_id];
+                        let t_size = types.member_size_of(*t_id);
+                        let v = if types.is_boxed(*t_id) {
+                            t.from_boxed_bytes(&bytes[..t_size], heap, types)
+                        } else {
+                            t.from_bytes(&bytes[..t_size], heap, types)
+                        };
+                        bytes = &bytes[t_size..];
+                        v.map(|v| (Cow::<str>::Owned(name.clone()), v))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                values.sort_by(|(a, _), (b, _)| a.cmp(b));
+                Ok(Value::Product(values))
+            }
         }
     }
 
+    /// Deserialize a value from the heap, given a reference to it
+    pub fn from_boxed_bytes(
+        &self,
+        bytes: &[u8],
+        heap: &[u8],
+        types: &TypeMap,
+    ) -> bincode::Result<Value<'static>> {
+        let (offset, len): HeapRef = deserialize(bytes)?;
+        self.from_bytes(&heap[offset..offset + len], heap, types)
+    }
+
     pub fn random_value(&self, types: &TypeMap) -> Value<'static> {
         match self {
             Type::Char => Value::Char(rand::random::<char>()),
             Type::Integer => Value::Integer(rand::random::<i32>()),
             Type::Bool => Value::Bool(rand::random::<bool>()),
             Type::Double => Value::Double(rand::random::<f64>()),
+            Type::String => {
+                let len = rand::random::<usize>() % 16;
+                let s = (0..len).map(|_| rand::random::<char>()).collect();
+                Value::Str(Cow::Owned(s))
+            }
             Type::Sum(variants) => {
                 let i = rand::random::<usize>() % variants.len();
                 let (variant, members) = &variants[i];
@@ -286,6 +873,19 @@ impl Type {
                 // TODO: Type name
                 Value::Sum(None, Cow::Owned(variant.clone()), values)
             }
+            Type::Product(fields) => {
+                let mut values: Vec<_> = fields
+                    .iter()
+                    .map(|(name, t_id)| {
+                        (
+                            Cow::<str>::Owned(name.clone()),
+                            types[t_id].random_value(types),
+                        )
+                    })
+                    .collect();
+                values.sort_by(|(a, _), (b, _)| a.cmp(b));
+                Value::Product(values)
+            }
         }
     }
 }
@@ -309,6 +909,13 @@ impl Value<'_> {
             Value::Double(v) => Value::Double(*v),
             Value::Char(v) => Value::Char(*v),
             Value::Bool(v) => Value::Bool(*v),
+            Value::Str(v) => Value::Str(Cow::Owned(v.clone().into_owned())),
+            Value::Product(fields) => Value::Product(
+                fields
+                    .iter()
+                    .map(|(name, v)| (Cow::Owned(name.clone().into_owned()), v.deep_clone()))
+                    .collect(),
+            ),
         }
     }
 }
@@ -334,11 +941,12 @@ mod tests {
             println!("  Value Before: {}", value);
 
             let mut bytes: Vec<u8> = vec![];
-            value.to_bytes(&mut bytes, &types, &types[&type_id]);
+            let mut heap: Vec<u8> = vec![];
+            value.to_bytes(&mut bytes, &mut heap, &types, &types[&type_id]);
             println!("  Bytes: {:?}", bytes);
 
             let value_again = types[&type_id]
-                .from_bytes(&bytes[..], &types)
+                .from_bytes(&bytes[..], &heap[..], &types)
                 .expect("failed to parse");
 
             assert_eq!(value, value_again);
@@ -346,4 +954,70 @@ mod tests {
             println!();
         }
     }
+    #[test]
+    fn test_optional_types() {
+        let mut types = TypeMap::new();
+        let integer = types.get_base_id(BaseType::Integer);
+        assert_eq!(types.optional_of(integer), None);
+
+        // Optional types are created when they are first needed, and they may be nested
+        let optional = types.instantiate(OPTIONAL, &[integer]);
+        let nested = types.instantiate(OPTIONAL, &[optional]);
+        assert_eq!(types.optional_of(integer), Some(optional));
+        assert_eq!(types.optional_of(optional), Some(nested));
+        assert_eq!(types.instantiate(OPTIONAL, &[integer]), optional);
+        assert_eq!(types.get_id("Optional<Optional<Integer>>"), Some(nested));
+
+        assert!(types.is_optional(optional));
+        assert!(types.is_optional(nested));
+        assert!(!types.is_optional(integer));
+    }
+
+    #[test]
+    fn test_compare() {
+        let mut types = TypeMap::new();
+        let integer = types.get_base_id(BaseType::Integer);
+        let size = types.insert(
+            "Size",
+            Type::Sum(vec![
+                ("Small".into(), vec![]),
+                ("Large".into(), vec![integer]),
+            ]),
+        );
+
+        let variant = |name: &'static str, values| Value::Sum(None, Cow::Borrowed(name), values);
+        let small = variant("Small", vec![]);
+        let large_1 = variant("Large", vec![Value::Integer(1)]);
+        let large_2 = variant("Large", vec![Value::Integer(2)]);
+
+        // Constructors are ordered as they are declared, not by name
+        assert_eq!(small.compare(&large_1, Some(size), &types), Ordering::Less);
+        assert_eq!(
+            large_2.compare(&large_1, Some(size), &types),
+            Ordering::Greater
+        );
+        assert_eq!(
+            large_1.compare(&large_1, Some(size), &types),
+            Ordering::Equal
+        );
+
+        let optional = types.instantiate(OPTIONAL, &[size]);
+        let some = |v| variant(OPTIONAL_SOME, vec![v]);
+        let none = variant(OPTIONAL_NONE, vec![]);
+        assert_eq!(
+            none.compare(&some(small.clone()), Some(optional), &types),
+            Ordering::Less
+        );
+        assert_eq!(
+            some(large_1).compare(&some(small), Some(optional), &types),
+            Ordering::Greater
+        );
+
+        // NaN is greater than all other doubles, so that doubles have a total order
+        let nan = Value::Double(std::f64::NAN);
+        let inf = Value::Double(std::f64::INFINITY);
+        assert_eq!(nan.compare(&inf, None, &types), Ordering::Greater);
+        assert_eq!(inf.compare(&nan, None, &types), Ordering::Less);
+        assert_eq!(nan.compare(&nan, None, &types), Ordering::Equal);
+    }
 }